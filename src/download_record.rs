@@ -0,0 +1,154 @@
+use anyhow::{Context, Result};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use crate::config::Config;
+
+/// File in `downloads_dir` recording where each download was saved
+/// The saved name can differ from the URL's (a Content-Disposition name or a mirror's URL), so
+/// later phases read it back instead of deriving it from the URL again.
+pub const DOWNLOAD_RECORD: &str = ".downloads.json";
+
+/// The files `downloads_dir` can hold
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum DownloadKind {
+    Binary,
+    BinarySignature,
+    Snapshot,
+    Addrbook,
+}
+
+impl DownloadKind {
+    /// What the configured download of this kind is fetched from, `None` if none is configured
+    /// A recorded path only stands for the current download while this stays the same.
+    fn source(self, config: &Config) -> Option<String> {
+        let source = match self {
+            Self::Binary => config.binary_url.clone(),
+            Self::BinarySignature => config.binary_signature_url.clone()?,
+            Self::Snapshot => config
+                .snapshot_s3_prefix
+                .clone()
+                .or_else(|| config.snapshot_manifest_url.clone())
+                .unwrap_or_else(|| config.get_snapshot_urls().join(" ")),
+            Self::Addrbook => config.addrbook_url.clone()?,
+        };
+        Some(source).filter(|source| !source.is_empty())
+    }
+}
+
+/// A saved download and the source it was fetched from
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+struct RecordedDownload {
+    source: String,
+    path: PathBuf,
+}
+
+#[derive(Debug, Default, Serialize, Deserialize)]
+struct DownloadRecord(BTreeMap<DownloadKind, RecordedDownload>);
+
+impl DownloadRecord {
+    /// The record in `downloads_dir`, empty if there is none or it cannot be parsed
+    fn read(downloads_dir: &Path) -> Self {
+        fs::read_to_string(downloads_dir.join(DOWNLOAD_RECORD))
+            .ok()
+            .and_then(|content| serde_json::from_str(&content).ok())
+            .unwrap_or_default()
+    }
+
+    fn write(&self, downloads_dir: &Path) -> Result<()> {
+        let path = downloads_dir.join(DOWNLOAD_RECORD);
+        fs::write(&path, serde_json::to_string_pretty(self)?)
+            .with_context(|| format!("Failed to write {}", path.display()))
+    }
+}
+
+/// Record that the configured `kind` download was saved at `path`
+pub fn record_download(config: &Config, kind: DownloadKind, path: &Path) -> Result<()> {
+    let Some(source) = kind.source(config) else {
+        return Ok(());
+    };
+    let mut record = DownloadRecord::read(&config.downloads_dir);
+    record.0.insert(
+        kind,
+        RecordedDownload {
+            source,
+            path: path.to_path_buf(),
+        },
+    );
+    record.write(&config.downloads_dir)
+}
+
+/// Where the configured `kind` download was saved, if it was recorded for the current source
+pub fn downloaded_path(config: &Config, kind: DownloadKind) -> Option<PathBuf> {
+    let source = kind.source(config)?;
+    DownloadRecord::read(&config.downloads_dir)
+        .0
+        .remove(&kind)
+        .filter(|recorded| recorded.source == source)
+        .map(|recorded| recorded.path)
+}
+
+/// Forget the recorded downloads of `kinds`, returning the paths they were saved at
+/// Paths outside `downloads_dir` are not returned, so they are never deleted as downloads.
+pub fn take_downloads(config: &Config, kinds: &[DownloadKind]) -> Result<Vec<PathBuf>> {
+    let mut record = DownloadRecord::read(&config.downloads_dir);
+    let taken: Vec<RecordedDownload> = kinds
+        .iter()
+        .filter_map(|kind| record.0.remove(kind))
+        .collect();
+    if !taken.is_empty() {
+        record.write(&config.downloads_dir)?;
+    }
+    Ok(taken
+        .into_iter()
+        .map(|recorded| recorded.path)
+        .filter(|path| path.starts_with(&config.downloads_dir))
+        .collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::tempdir;
+
+    #[test]
+    fn test_recorded_path_follows_the_source() -> Result<()> {
+        let temp_dir = tempdir()?;
+        let config_path = temp_dir.path().join("config.yaml");
+        let write_config = |snapshot_url: &str| {
+            fs::write(
+                &config_path,
+                format!(
+                    "snapshot_url: \"{snapshot_url}\"\nbinary_url: \"https://example.com/gaiad\"\n\
+                     binary_relative_path: \"gaiad\"\nchain_id: \"test-1\"\nmoniker: \"test\"\n\
+                     base_dir: \"{}\"\n",
+                    temp_dir.path().display()
+                ),
+            )
+        };
+        write_config("https://example.com/latest.tar.gz")?;
+        let config = Config::from_file(&config_path)?;
+        fs::create_dir_all(&config.downloads_dir)?;
+        assert_eq!(downloaded_path(&config, DownloadKind::Snapshot), None);
+
+        let saved = config.downloads_dir.join("snapshot-19876543.tar.gz");
+        record_download(&config, DownloadKind::Snapshot, &saved)?;
+        assert_eq!(
+            downloaded_path(&config, DownloadKind::Snapshot),
+            Some(saved)
+        );
+        assert_eq!(downloaded_path(&config, DownloadKind::Binary), None);
+        // Nothing is recorded for a download that is not configured
+        record_download(&config, DownloadKind::Addrbook, Path::new("addrbook.json"))?;
+        assert_eq!(downloaded_path(&config, DownloadKind::Addrbook), None);
+
+        // A path recorded for another snapshot URL does not stand for the new one
+        write_config("https://example.com/other.tar.gz")?;
+        let config = Config::from_file(&config_path)?;
+        assert_eq!(downloaded_path(&config, DownloadKind::Snapshot), None);
+        Ok(())
+    }
+}
@@ -0,0 +1,231 @@
+use std::error::Error as StdError;
+use std::io;
+use std::path::PathBuf;
+use std::time::Duration;
+use thiserror::Error;
+
+/// Why a download failed, so callers can tell a missing file from a full disk or a bad checksum
+#[derive(Debug, Error)]
+pub enum DownloadError {
+    /// The server has no such file (HTTP 404 or a missing S3 object)
+    #[error("{file_type} not found at {url}")]
+    NotFound { file_type: String, url: String },
+
+    /// The server answered with any other error status
+    #[error("Failed to download {file_type}: HTTP status {status}")]
+    HttpStatus {
+        file_type: String,
+        status: reqwest::StatusCode,
+        /// How long the server asked to wait before retrying (`Retry-After`), e.g. with a 429
+        retry_after: Option<Duration>,
+    },
+
+    /// A request to the server timed out
+    #[error("{message}")]
+    Timeout { message: String },
+
+    /// The download, retries included, did not finish within `total_timeout_secs`
+    #[error("{file_type} download did not finish within the {}s total timeout", .timeout.as_secs())]
+    DeadlineExceeded {
+        file_type: String,
+        timeout: Duration,
+    },
+
+    /// The file is larger than `max_file_size_bytes`
+    #[error("{file_type} is larger than the {limit} byte limit ({size} bytes)")]
+    FileTooLarge {
+        file_type: String,
+        /// Size reported by the server, or the bytes received before giving up
+        size: u64,
+        limit: u64,
+    },
+
+    /// The server sent an HTML page, typically an error page, instead of the file
+    #[error("Expected {file_type} from {url} but the server sent an HTML page; the URL is likely wrong or the server is showing an error page")]
+    HtmlPage { file_type: String, url: String },
+
+    /// The downloaded file does not have the size its snapshot manifest lists
+    #[error("{} is {actual} bytes but {expected} bytes were expected", .path.display())]
+    SizeMismatch {
+        path: PathBuf,
+        expected: u64,
+        actual: u64,
+    },
+
+    /// The downloaded file does not match the MD5 in its S3 ETag or snapshot manifest
+    #[error("MD5 mismatch for {}: expected {expected}, got {actual}", .path.display())]
+    Checksum {
+        path: PathBuf,
+        expected: String,
+        actual: String,
+    },
+
+    /// Reading or writing a local file failed, e.g. because the disk is full
+    #[error("{context}")]
+    Io {
+        context: String,
+        #[source]
+        source: io::Error,
+    },
+
+    /// Any other failure, such as a refused connection, a malformed response or an S3 error
+    #[error("{message}")]
+    Other { message: String },
+}
+
+/// Classify an error raised inside the download code by the typed error at its root
+impl From<anyhow::Error> for DownloadError {
+    fn from(err: anyhow::Error) -> Self {
+        let err = match err.downcast::<DownloadError>() {
+            Ok(download_error) => return download_error,
+            Err(err) => err,
+        };
+
+        if err.chain().any(is_timeout) {
+            return Self::Timeout {
+                message: format!("{err:#}"),
+            };
+        }
+        // Network failures surface as I/O errors too, but they are not about local files
+        if err.chain().any(is_network) {
+            return Self::Other {
+                message: format!("{err:#}"),
+            };
+        }
+
+        let context = context_of(&err);
+        match err.downcast::<io::Error>() {
+            Ok(source) => Self::Io { context, source },
+            Err(err) => Self::Other {
+                message: format!("{err:#}"),
+            },
+        }
+    }
+}
+
+/// Why extracting an archive failed
+#[derive(Debug, Error)]
+pub enum ExtractError {
+    /// The file is not compressed with gzip, LZ4 or zstd
+    #[error("Unsupported archive format for {}. Only gzip, LZ4 and zstd compressed files (tar or single file) are supported.", .path.display())]
+    UnsupportedFormat { path: PathBuf },
+
+    /// Reading the archive or writing its contents failed, including corrupt or truncated
+    /// archives and a full disk
+    #[error("{context}")]
+    Io {
+        context: String,
+        #[source]
+        source: io::Error,
+    },
+
+    /// The extraction target has no directory name to stage next to
+    #[error("Invalid extraction target {}", .path.display())]
+    InvalidTarget { path: PathBuf },
+
+    /// The post-snapshot-extract command exited unsuccessfully
+    #[error("Post-snapshot-extract command failed with exit code: {exit_code}")]
+    CommandFailed { exit_code: i32 },
+
+    /// Any other failure
+    #[error("{message}")]
+    Other { message: String },
+}
+
+impl ExtractError {
+    pub(crate) fn io(context: impl Into<String>, source: io::Error) -> Self {
+        Self::Io {
+            context: context.into(),
+            source,
+        }
+    }
+}
+
+/// Attach a description of what was being done to an I/O error
+pub(crate) trait IoContext<T> {
+    fn io_context<F: FnOnce() -> String>(self, context: F) -> Result<T, ExtractError>;
+}
+
+impl<T> IoContext<T> for io::Result<T> {
+    fn io_context<F: FnOnce() -> String>(self, context: F) -> Result<T, ExtractError> {
+        self.map_err(|source| ExtractError::io(context(), source))
+    }
+}
+
+fn is_timeout(err: &(dyn StdError + 'static)) -> bool {
+    if let Some(reqwest_error) = err.downcast_ref::<reqwest::Error>() {
+        return reqwest_error.is_timeout();
+    }
+    err.downcast_ref::<io::Error>()
+        .is_some_and(|io_error| io_error.kind() == io::ErrorKind::TimedOut)
+}
+
+/// Whether an error came from the HTTP client, directly or wrapped in an I/O error
+fn is_network(err: &(dyn StdError + 'static)) -> bool {
+    err.is::<reqwest::Error>()
+        || err
+            .downcast_ref::<io::Error>()
+            .and_then(|io_error| io_error.get_ref())
+            .is_some_and(|inner| inner.is::<reqwest::Error>())
+}
+
+/// The context messages wrapped around an error's root cause, joined like `{:#}` prints them
+fn context_of(err: &anyhow::Error) -> String {
+    let messages: Vec<String> = err.chain().map(|cause| cause.to_string()).collect();
+    match messages.split_last() {
+        Some((_, context)) if !context.is_empty() => context.join(": "),
+        _ => "I/O error".to_string(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use anyhow::Context;
+
+    #[test]
+    fn test_download_error_from_io_error_keeps_kind_and_context() {
+        let err = Err::<(), _>(io::Error::from(io::ErrorKind::StorageFull))
+            .context("Failed to write snapshot.tar.gz")
+            .unwrap_err();
+
+        match DownloadError::from(err) {
+            DownloadError::Io { context, source } => {
+                assert_eq!(context, "Failed to write snapshot.tar.gz");
+                assert_eq!(source.kind(), io::ErrorKind::StorageFull);
+            }
+            other => panic!("expected Io, got {other:?}"),
+        }
+    }
+
+    #[test]
+    fn test_download_error_from_typed_error_is_unwrapped() {
+        let err = anyhow::Error::from(DownloadError::NotFound {
+            file_type: "binary".to_string(),
+            url: "http://example.com/gaiad".to_string(),
+        })
+        .context("Attempt failed");
+
+        let err = DownloadError::from(err);
+        assert!(matches!(err, DownloadError::NotFound { .. }), "{err:?}");
+    }
+
+    #[test]
+    fn test_download_error_from_timeout() {
+        let err = anyhow::Error::from(io::Error::from(io::ErrorKind::TimedOut))
+            .context("Failed to read response body");
+        assert!(matches!(
+            DownloadError::from(err),
+            DownloadError::Timeout { .. }
+        ));
+    }
+
+    #[test]
+    fn test_download_error_from_other_error() {
+        let err = anyhow::anyhow!("Invalid S3 URL format: s3://");
+        match DownloadError::from(err) {
+            DownloadError::Other { message } => assert_eq!(message, "Invalid S3 URL format: s3://"),
+            other => panic!("expected Other, got {other:?}"),
+        }
+    }
+}
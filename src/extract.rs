@@ -1,73 +1,398 @@
-use anyhow::{Context, Result};
 use flate2::read::GzDecoder;
 use lz4::Decoder;
-use std::fs::{self, File};
-use std::io::{BufRead, BufReader};
-use std::path::Path;
-use std::process::{Command, Stdio};
-use tar::Archive;
+use md5::{Digest, Md5};
+use regex::Regex;
+use serde::{Deserialize, Serialize};
+use std::collections::HashSet;
+use std::ffi::OsStr;
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, BufRead, BufReader, Read, Seek, SeekFrom, Write};
+use std::path::{Component, Path, PathBuf};
+use std::sync::mpsc::{self, Receiver, SyncSender};
+use std::sync::{Arc, Condvar, Mutex};
+use std::thread::JoinHandle;
+use std::time::{Duration, UNIX_EPOCH};
+use tar::{Archive, EntryType};
 use tracing::{debug, info, warn};
 use zstd::stream::read::Decoder as ZstdDecoder;
 
-pub fn extract_archive(archive_path: &Path, target_dir: &Path) -> Result<()> {
-    info!("Extracting archive: {:?}", archive_path);
+use crate::error::{ExtractError, IoContext};
+use crate::metrics;
+use crate::progress::{self, Progress};
+use crate::runner::ShellCommand;
 
-    fs::create_dir_all(target_dir)?;
+/// Which tar entries to extract, chosen by `extract_include` and `extract_exclude` glob patterns
+/// matched against each entry's path inside the archive (e.g. `data/**`, `**/*.log`)
+///
+/// `*` and `?` match within one path component, `**` matches any number of components,
+/// `[abc]` matches one of a set of characters and `{a,b}` one of several alternatives.
+/// The default filter extracts everything.
+#[derive(Debug, Clone, Default)]
+pub struct EntryFilter {
+    include: Vec<Regex>,
+    exclude: Vec<Regex>,
+}
 
-    if let Some(extension) = archive_path.extension() {
-        match extension.to_str() {
-            Some("gz") | Some("tgz") => {
-                extract_tar_gz(archive_path, target_dir)?;
-                Ok(())
+impl EntryFilter {
+    pub fn new(include: &[String], exclude: &[String]) -> anyhow::Result<Self> {
+        let compile = |patterns: &[String]| {
+            patterns
+                .iter()
+                .map(|pattern| {
+                    glob_to_regex(pattern)
+                        .map_err(|e| anyhow::anyhow!("Invalid glob pattern '{}': {}", pattern, e))
+                })
+                .collect::<anyhow::Result<Vec<_>>>()
+        };
+        Ok(Self {
+            include: compile(include)?,
+            exclude: compile(exclude)?,
+        })
+    }
+
+    /// Whether an entry is extracted: it matches an include pattern, if any are set, and no
+    /// exclude pattern
+    pub fn matches(&self, entry_path: &Path) -> bool {
+        // Archives often prefix entries with `./`, which the patterns should not have to spell out
+        let path = entry_path
+            .components()
+            .filter_map(|component| match component {
+                Component::Normal(part) => Some(part.to_string_lossy()),
+                _ => None,
+            })
+            .collect::<Vec<_>>()
+            .join("/");
+        (self.include.is_empty() || self.include.iter().any(|re| re.is_match(&path)))
+            && !self.exclude.iter().any(|re| re.is_match(&path))
+    }
+}
+
+/// How an archive is extracted; the default extracts everything from one thread with zstd's
+/// default window limit
+#[derive(Debug, Clone, Default)]
+pub struct ExtractOptions {
+    /// Which tar entries to extract
+    pub filter: EntryFilter,
+    /// Write the files of tar archives from one worker thread per CPU; the archive itself is
+    /// still read in order by one thread
+    pub parallel: bool,
+    /// Accept zstd archives compressed with a window up to `2^window_log_max` bytes (default:
+    /// zstd's 27); the decoder needs that much memory
+    pub zstd_window_log_max: Option<u32>,
+    /// Snapshot files to decompress in place after extraction, see `decompress_members`
+    pub decompress_members: Option<EntryFilter>,
+}
+
+/// Translate a glob pattern into an anchored regex
+fn glob_to_regex(glob: &str) -> Result<Regex, String> {
+    let chars: Vec<char> = glob.chars().collect();
+    let mut re = String::from("^");
+    let mut in_alternatives = false;
+    let mut i = 0;
+    while i < chars.len() {
+        match chars[i] {
+            '*' if chars.get(i + 1) == Some(&'*') => {
+                let starts_component = i == 0 || chars[i - 1] == '/';
+                match chars.get(i + 2) {
+                    // `**/` matches zero or more leading directories
+                    Some('/') if starts_component => {
+                        re.push_str("(?:.*/)?");
+                        i += 3;
+                        continue;
+                    }
+                    None if starts_component => re.push_str(".*"),
+                    _ => return Err("`**` must be a whole path component".to_string()),
+                }
+                i += 1;
             }
-            Some("lz4") => {
-                extract_tar_lz4(archive_path, target_dir)?;
-                Ok(())
+            '*' => re.push_str("[^/]*"),
+            '?' => re.push_str("[^/]"),
+            '[' => {
+                let end = chars[i + 1..]
+                    .iter()
+                    .skip(1)
+                    .position(|&c| c == ']')
+                    .map(|pos| i + 2 + pos)
+                    .ok_or("unclosed `[`")?;
+                re.push('[');
+                let mut class = &chars[i + 1..end];
+                if let Some(('!' | '^', rest)) = class.split_first() {
+                    re.push('^');
+                    class = rest;
+                }
+                for &c in class {
+                    if matches!(c, '\\' | '[' | ']' | '&' | '~' | '^') {
+                        re.push('\\');
+                    }
+                    re.push(c);
+                }
+                re.push(']');
+                i = end;
             }
-            Some("zst") => {
-                extract_tar_zst(archive_path, target_dir)?;
-                Ok(())
+            '{' if !in_alternatives => {
+                re.push_str("(?:");
+                in_alternatives = true;
             }
-            _ => {
-                warn!("Unsupported archive format: {:?}", extension);
-                Err(anyhow::anyhow!(
-                    "Unsupported archive format. Only tar.gz, tar.lz4, and tar.zst are supported."
-                ))
+            ',' if in_alternatives => re.push('|'),
+            '}' if in_alternatives => {
+                re.push(')');
+                in_alternatives = false;
             }
+            c => re.push_str(&regex::escape(c.encode_utf8(&mut [0; 4]))),
         }
-    } else {
-        warn!("Archive file has no extension: {:?}", archive_path);
-        Err(anyhow::anyhow!(
-            "Archive file has no extension, cannot determine format"
-        ))
+        i += 1;
+    }
+    if in_alternatives {
+        return Err("unclosed `{`".to_string());
+    }
+    re.push('$');
+    Regex::new(&re).map_err(|e| e.to_string())
+}
+
+/// Compression formats of the supported tar archives
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum ArchiveFormat {
+    TarGz,
+    TarLz4,
+    TarZst,
+}
+
+impl ArchiveFormat {
+    /// Recognize the compression from the file's leading magic bytes
+    fn from_magic(header: &[u8]) -> Option<Self> {
+        if header.starts_with(&[0x1f, 0x8b]) {
+            Some(Self::TarGz)
+        } else if header.starts_with(&[0x04, 0x22, 0x4d, 0x18]) {
+            Some(Self::TarLz4)
+        } else if header.starts_with(&[0x28, 0xb5, 0x2f, 0xfd]) {
+            Some(Self::TarZst)
+        } else {
+            None
+        }
+    }
+
+    fn from_extension(extension: &str) -> Option<Self> {
+        match extension {
+            "gz" | "tgz" => Some(Self::TarGz),
+            "lz4" => Some(Self::TarLz4),
+            "zst" => Some(Self::TarZst),
+            _ => None,
+        }
+    }
+
+    fn name(self) -> &'static str {
+        match self {
+            Self::TarGz => "gzip",
+            Self::TarLz4 => "lz4",
+            Self::TarZst => "zstd",
+        }
+    }
+
+    /// Decompress `reader`, accepting zstd windows up to `2^zstd_window_log_max` bytes if set
+    fn decoder<'a, R: Read + 'a>(
+        self,
+        reader: R,
+        zstd_window_log_max: Option<u32>,
+    ) -> io::Result<Box<dyn Read + 'a>> {
+        match self {
+            Self::TarGz => Ok(Box::new(GzDecoder::new(reader))),
+            Self::TarLz4 => Ok(Box::new(Decoder::new(reader)?)),
+            Self::TarZst => {
+                // The decoder reads every frame, so archives written by multi-threaded compressors
+                // work
+                let mut decoder = ZstdDecoder::new(reader)?;
+                if let Some(window_log_max) = zstd_window_log_max {
+                    decoder.window_log_max(window_log_max)?;
+                }
+                Ok(Box::new(decoder))
+            }
+        }
+    }
+}
+
+/// Detect an archive by its content, so files named without (or with a misleading) extension work
+fn detect_archive_format(path: &Path) -> Result<Option<ArchiveFormat>, ExtractError> {
+    let mut header = Vec::with_capacity(4);
+    File::open(path)
+        .and_then(|file| file.take(4).read_to_end(&mut header))
+        .io_context(|| format!("Failed to read {:?}", path))?;
+    Ok(ArchiveFormat::from_magic(&header))
+}
+
+/// What an extraction wrote
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize)]
+pub struct ExtractStats {
+    /// Files, links and other entries that are not directories
+    pub files: u64,
+    /// Directory entries; directories created only as parents of files are not counted
+    pub dirs: u64,
+    /// Uncompressed size of the files
+    pub bytes: u64,
+}
+
+impl ExtractStats {
+    fn add_file(&mut self, size: u64) {
+        self.files += 1;
+        self.bytes += size;
+    }
+}
+
+pub fn extract_archive(
+    archive_path: &Path,
+    target_dir: &Path,
+) -> Result<ExtractStats, ExtractError> {
+    extract_archive_with(archive_path, target_dir, &ExtractOptions::default())
+}
+
+/// Extract only the tar entries `options.filter` matches; a compressed file that is not a tar is
+/// written out whole regardless
+pub fn extract_archive_with(
+    archive_path: &Path,
+    target_dir: &Path,
+    options: &ExtractOptions,
+) -> Result<ExtractStats, ExtractError> {
+    info!("Extracting archive: {:?}", archive_path);
+
+    fs::create_dir_all(target_dir)
+        .io_context(|| format!("Failed to create directory {:?}", target_dir))?;
+
+    let format = archive_format(archive_path)?;
+    info!("Extracting {} archive...", format.name());
+    unpack_tar(archive_path, target_dir, options, |reader| {
+        format.decoder(reader, options.zstd_window_log_max)
+    })
+}
+
+/// The archive's format by content, falling back to the extension so a corrupt archive still
+/// reports a decoder error
+fn archive_format(archive_path: &Path) -> Result<ArchiveFormat, ExtractError> {
+    if let Some(format) = detect_archive_format(archive_path)? {
+        return Ok(format);
+    }
+    match archive_path.extension().and_then(|e| e.to_str()) {
+        Some(extension) => ArchiveFormat::from_extension(extension).ok_or_else(|| {
+            warn!("Unsupported archive format: {:?}", extension);
+            ExtractError::UnsupportedFormat {
+                path: archive_path.to_path_buf(),
+            }
+        }),
+        None => {
+            warn!("Archive file has no extension: {:?}", archive_path);
+            Err(ExtractError::UnsupportedFormat {
+                path: archive_path.to_path_buf(),
+            })
+        }
+    }
+}
+
+/// What `verify_archive` found in an archive
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ArchiveSummary {
+    /// Number of tar entries, or `None` for a compressed file that is not a tar
+    pub entries: Option<u64>,
+    /// Decompressed size of the entries (or of the file)
+    pub bytes: u64,
+}
+
+/// Read an archive through its decompressor and tar reader to the end without writing anything,
+/// so a truncated or corrupt download is caught before extracting it
+/// Only the decoder settings of `options` apply: every entry is read.
+pub fn verify_archive(
+    archive_path: &Path,
+    options: &ExtractOptions,
+) -> Result<ArchiveSummary, ExtractError> {
+    let format = archive_format(archive_path)?;
+    info!("Verifying {} archive: {:?}", format.name(), archive_path);
+    let (file, total, progress) = open_with_progress(archive_path, "verify", "Verifying")?;
+
+    let reader = ProgressReader {
+        inner: file,
+        read: 0,
+        total,
+        progress: &progress,
+    };
+    match format
+        .decoder(reader, options.zstd_window_log_max)
+        .and_then(read_all_entries)
+    {
+        Ok(summary) => {
+            progress.finish_with_message(total, "Verification complete");
+            Ok(summary)
+        }
+        Err(e) => {
+            progress.abandon();
+            Err(ExtractError::io(
+                format!("Failed to verify {:?}", archive_path),
+                e,
+            ))
+        }
+    }
+}
+
+/// Read every entry of a decompressed stream, failing on the first corrupt or truncated one
+fn read_all_entries(decoded: Box<dyn Read + '_>) -> io::Result<ArchiveSummary> {
+    let (is_tar, mut stream) = peek_tar(decoded)?;
+    if !is_tar {
+        let bytes = io::copy(&mut stream, &mut io::sink())?;
+        return Ok(ArchiveSummary {
+            entries: None,
+            bytes,
+        });
+    }
+
+    let mut archive = Archive::new(stream);
+    let mut entries = 0;
+    let mut bytes = 0;
+    for entry in archive.entries()? {
+        let mut entry = entry?;
+        let size = entry.size();
+        // Entry contents end early rather than fail when the stream is cut off
+        let read = io::copy(&mut entry, &mut io::sink())?;
+        if read != size {
+            return Err(io::Error::new(
+                io::ErrorKind::UnexpectedEof,
+                format!(
+                    "Entry {:?} is truncated: {} of {} bytes",
+                    entry.path()?,
+                    read,
+                    size
+                ),
+            ));
+        }
+        entries += 1;
+        bytes += size;
     }
+    io::copy(&mut archive.into_inner(), &mut io::sink())?;
+    Ok(ArchiveSummary {
+        entries: Some(entries),
+        bytes,
+    })
 }
 
+/// Install the binary into the workspace: extract it if it is an archive, otherwise copy it to
+/// `binary_relative_path`
+/// With `binary_search`, an archive that holds the binary at another path (e.g. under a
+/// versioned directory) gets `binary_relative_path` linked to it
 pub fn extract_binary(
     binary_path: &Path,
     workspace_dir: &Path,
     binary_relative_path: &str,
-) -> Result<()> {
+    binary_search: bool,
+) -> Result<ExtractStats, ExtractError> {
     info!("Processing binary...");
     debug!("Binary target directory: {:?}", workspace_dir);
     debug!("Binary relative path: {}", binary_relative_path);
 
-    // Check if the file has an archive extension
-    if let Some(extension) = binary_path.extension() {
-        match extension.to_str() {
-            Some("gz") | Some("tgz") | Some("lz4") | Some("zst") => {
-                // This is an archive, extract it
-                debug!("File appears to be an archive, extracting...");
-                return extract_archive(binary_path, workspace_dir);
-            }
-            _ => {
-                // Not a known archive type, treat as standalone binary
-                debug!(
-                    "File does not have a known archive extension, treating as standalone binary"
-                );
-            }
+    // Check the content rather than the name, which for S3 downloads comes from the object key
+    if detect_archive_format(binary_path)?.is_some() {
+        debug!("File appears to be an archive, extracting...");
+        let stats = extract_archive(binary_path, workspace_dir)?;
+        if binary_search {
+            link_nested_binary(binary_path, workspace_dir, binary_relative_path)?;
         }
+        return Ok(stats);
     }
+    debug!("File is not a known archive type, treating as standalone binary");
 
     // If we get here, treat the file as a standalone binary that just needs to be made executable
     info!("File appears to be a standalone binary, making it executable...");
@@ -78,51 +403,425 @@ pub fn extract_binary(
     // Create the parent directory structure if it doesn't exist
     if let Some(parent) = dest_path.parent() {
         debug!("Creating directory structure: {:?}", parent);
-        fs::create_dir_all(parent)?;
+        fs::create_dir_all(parent)
+            .io_context(|| format!("Failed to create directory {:?}", parent))?;
     }
 
     // Copy the binary to the destination
     debug!("Copying binary to {:?}", dest_path);
-    fs::copy(binary_path, &dest_path)?;
+    let bytes = fs::copy(binary_path, &dest_path)
+        .io_context(|| format!("Failed to copy binary to {:?}", dest_path))?;
 
     // Make the file executable
     #[cfg(unix)]
     {
         use std::os::unix::fs::PermissionsExt;
-        let mut perms = fs::metadata(&dest_path)?.permissions();
-        perms.set_mode(0o755);
-        fs::set_permissions(&dest_path, perms)?;
+        fs::set_permissions(&dest_path, fs::Permissions::from_mode(0o755))
+            .io_context(|| format!("Failed to make {:?} executable", dest_path))?;
         debug!("Made binary executable (chmod 755)");
     }
 
+    Ok(ExtractStats {
+        files: 1,
+        dirs: 0,
+        bytes,
+    })
+}
+
+/// Link `binary_relative_path` to the file of the same name in the archive if the archive has
+/// no entry at that path
+/// Only the archive's entries are searched, so binaries left by older releases are not picked up
+fn link_nested_binary(
+    archive_path: &Path,
+    workspace_dir: &Path,
+    binary_relative_path: &str,
+) -> Result<(), ExtractError> {
+    let expected = normal_path(Path::new(binary_relative_path));
+    let Some(name) = expected.file_name() else {
+        return Ok(());
+    };
+    let candidates = find_archive_files(archive_path, name)?;
+    if candidates.contains(&expected) {
+        return Ok(());
+    }
+    let found = match candidates.as_slice() {
+        [found] => found,
+        [] => {
+            return Err(ExtractError::Other {
+                message: format!(
+                    "No file named {:?} in binary archive {:?}",
+                    name, archive_path
+                ),
+            })
+        }
+        _ => {
+            return Err(ExtractError::Other {
+                message: format!(
+                    "Binary archive {:?} has several files named {:?} ({:?}); set binary_relative_path to one of them",
+                    archive_path, name, candidates
+                ),
+            })
+        }
+    };
+
+    let dest_path = workspace_dir.join(&expected);
+    info!(
+        "Binary found at {:?} in the archive, linking {:?} to it",
+        found, dest_path
+    );
+    if let Some(parent) = dest_path.parent() {
+        fs::create_dir_all(parent)
+            .io_context(|| format!("Failed to create directory {:?}", parent))?;
+    }
+    match fs::remove_file(&dest_path) {
+        Err(e) if e.kind() != io::ErrorKind::NotFound => {
+            return Err(ExtractError::io(
+                format!("Failed to remove {:?}", dest_path),
+                e,
+            ))
+        }
+        _ => {}
+    }
+
+    #[cfg(unix)]
+    {
+        // Relative, so the workspace can be moved; both paths are plain relative paths
+        let depth = expected.components().count() - 1;
+        let target: PathBuf = std::iter::repeat_n(Path::new(".."), depth)
+            .collect::<PathBuf>()
+            .join(found);
+        std::os::unix::fs::symlink(&target, &dest_path)
+            .io_context(|| format!("Failed to link {:?} to {:?}", dest_path, target))?;
+    }
+    #[cfg(not(unix))]
+    {
+        fs::copy(workspace_dir.join(found), &dest_path)
+            .io_context(|| format!("Failed to copy binary to {:?}", dest_path))?;
+    }
     Ok(())
 }
 
+/// Paths of the regular files named `name` in an archive, as plain relative paths
+fn find_archive_files(archive_path: &Path, name: &OsStr) -> Result<Vec<PathBuf>, ExtractError> {
+    let format = archive_format(archive_path)?;
+    let read_paths = || -> io::Result<Vec<PathBuf>> {
+        let file = File::open(archive_path)?;
+        let (is_tar, stream) = peek_tar(format.decoder(file, None)?)?;
+        if !is_tar {
+            let raw_name = decompressed_file_name(archive_path);
+            return Ok(if raw_name == name {
+                vec![PathBuf::from(raw_name)]
+            } else {
+                Vec::new()
+            });
+        }
+        let mut paths = Vec::new();
+        for entry in Archive::new(stream).entries()? {
+            let entry = entry?;
+            let path = normal_path(&entry.path()?);
+            if entry.header().entry_type().is_file() && path.file_name() == Some(name) {
+                paths.push(path);
+            }
+        }
+        Ok(paths)
+    };
+    read_paths().io_context(|| format!("Failed to read {:?}", archive_path))
+}
+
+/// The path without `.`, `/` and other non-name components, as archives often prefix entries
+/// with `./`
+fn normal_path(path: &Path) -> PathBuf {
+    path.components()
+        .filter(|component| matches!(component, Component::Normal(_)))
+        .collect()
+}
+
+/// Extract the snapshot into `home_dir` and run the post-snapshot-extract command, then leave a
+/// marker so a rerun with `skip_if_complete` skips both for the same snapshot
+/// Returns what was extracted, or `None` if the extraction was skipped
 pub fn extract_snapshot(
     snapshot_path: &Path,
     home_dir: &Path,
-    post_command: Option<&str>,
-) -> Result<()> {
+    post_command: Option<&ShellCommand>,
+    options: &ExtractOptions,
+    atomic: bool,
+    skip_if_complete: bool,
+) -> Result<Option<ExtractStats>, ExtractError> {
+    let marker = ExtractMarker::for_archive(snapshot_path)
+        .io_context(|| format!("Failed to read {:?}", snapshot_path))?;
+    let marker_path = home_dir.join(EXTRACT_MARKER);
+    if skip_if_complete && ExtractMarker::read(&marker_path).as_ref() == Some(&marker) {
+        info!(
+            "Snapshot {} was already extracted into {:?}, skipping extraction",
+            marker.archive, home_dir
+        );
+        return Ok(None);
+    }
+    // An extraction interrupted from here on must not look complete
+    match fs::remove_file(&marker_path) {
+        Err(e) if e.kind() != io::ErrorKind::NotFound => {
+            return Err(ExtractError::io(
+                format!("Failed to remove {:?}", marker_path),
+                e,
+            ))
+        }
+        _ => {}
+    }
+
     info!("Extracting snapshot...");
     debug!("Snapshot extraction target directory: {:?}", home_dir);
-    extract_archive(snapshot_path, home_dir)?;
+    let stats = if atomic {
+        extract_archive_atomically(snapshot_path, home_dir, options)?
+    } else {
+        extract_archive_with(snapshot_path, home_dir, options)?
+    };
+
+    if let Some(members) = &options.decompress_members {
+        decompress_members(home_dir, members, options.zstd_window_log_max)?;
+    }
 
     if let Some(cmd) = post_command {
         execute_post_snapshot_extract_command(cmd)?;
     }
 
+    marker
+        .write(&marker_path)
+        .io_context(|| format!("Failed to write {:?}", marker_path))?;
+    Ok(Some(stats))
+}
+
+/// Decompress the files below `dir` whose paths relative to it match `members`, for archives whose
+/// files are compressed one by one (e.g. `data/*.db.zst` inside a plain tar)
+/// Each file is replaced by its content under the name without the `.gz`, `.lz4` or `.zst` suffix.
+/// Returns how many files were decompressed
+pub fn decompress_members(
+    dir: &Path,
+    members: &EntryFilter,
+    zstd_window_log_max: Option<u32>,
+) -> Result<usize, ExtractError> {
+    // Collect the matches first, so decompressed files are never revisited
+    let mut matches = Vec::new();
+    let mut pending = vec![dir.to_path_buf()];
+    while let Some(current) = pending.pop() {
+        let entries =
+            fs::read_dir(&current).io_context(|| format!("Failed to read {:?}", current))?;
+        for entry in entries {
+            let entry = entry.io_context(|| format!("Failed to read {:?}", current))?;
+            let path = entry.path();
+            let file_type = entry
+                .file_type()
+                .io_context(|| format!("Failed to read {:?}", path))?;
+            if file_type.is_dir() {
+                pending.push(path);
+            } else if file_type.is_file()
+                && members.matches(path.strip_prefix(dir).unwrap_or(&path))
+            {
+                matches.push(path);
+            }
+        }
+    }
+
+    for path in &matches {
+        decompress_member(path, zstd_window_log_max)?;
+    }
+    if !matches.is_empty() {
+        info!("Decompressed {} extracted files", matches.len());
+    }
+    Ok(matches.len())
+}
+
+/// Replace a compressed file with its decompressed content, keeping its permissions and mtime
+fn decompress_member(path: &Path, zstd_window_log_max: Option<u32>) -> Result<(), ExtractError> {
+    let format = detect_archive_format(path)?.ok_or_else(|| ExtractError::UnsupportedFormat {
+        path: path.to_path_buf(),
+    })?;
+    let name = decompressed_file_name(path);
+    if Some(name) == path.file_name() {
+        return Err(ExtractError::Other {
+            message: format!(
+                "Cannot decompress {:?}: its name has no .gz, .lz4 or .zst suffix to remove",
+                path
+            ),
+        });
+    }
+    let target = path.with_file_name(name);
+    let mut partial_name = name.to_os_string();
+    partial_name.push(".partial");
+    let partial = path.with_file_name(partial_name);
+
+    let metadata = fs::metadata(path).io_context(|| format!("Failed to read {:?}", path))?;
+    let file = File::open(path).io_context(|| format!("Failed to open {:?}", path))?;
+    let mut decoded = format
+        .decoder(BufReader::new(file), zstd_window_log_max)
+        .io_context(|| format!("Failed to decompress {:?}", path))?;
+    let mut out =
+        File::create(&partial).io_context(|| format!("Failed to create {:?}", partial))?;
+    io::copy(&mut decoded, &mut out).io_context(|| format!("Failed to decompress {:?}", path))?;
+    out.set_permissions(metadata.permissions())
+        .io_context(|| format!("Failed to set permissions of {:?}", partial))?;
+    if let Ok(modified) = metadata.modified() {
+        out.set_modified(modified)
+            .io_context(|| format!("Failed to set mtime of {:?}", partial))?;
+    }
+    drop(out);
+
+    fs::rename(&partial, &target)
+        .io_context(|| format!("Failed to rename {:?} to {:?}", partial, target))?;
+    fs::remove_file(path).io_context(|| format!("Failed to remove {:?}", path))?;
+    debug!(
+        "Decompressed {:?} ({}) to {:?}",
+        path,
+        format.name(),
+        target
+    );
     Ok(())
 }
 
-fn execute_post_snapshot_extract_command(command: &str) -> Result<()> {
-    info!("Executing post-snapshot-extract command: {}", command);
+/// Whether the home directory's marker says this snapshot was already extracted into it completely
+pub fn is_extracted(snapshot_path: &Path, home_dir: &Path) -> bool {
+    ExtractMarker::for_archive(snapshot_path)
+        .is_ok_and(|marker| ExtractMarker::read(&home_dir.join(EXTRACT_MARKER)) == Some(marker))
+}
+
+/// File in the home directory recording which snapshot was extracted into it completely
+pub const EXTRACT_MARKER: &str = ".extract-complete";
+
+/// Bytes hashed at each end of the archive to tell snapshots of the same size apart
+const MARKER_SAMPLE_BYTES: u64 = 1024 * 1024;
+
+/// Identifies an extracted archive without hashing all of it, which would take as long as
+/// extracting a snapshot of hundreds of gigabytes
+#[derive(Debug, PartialEq, Eq, Serialize, Deserialize)]
+struct ExtractMarker {
+    archive: String,
+    size: u64,
+    /// MD5 of the first and last MiB of the archive
+    sample_md5: String,
+}
 
-    let mut child = Command::new("sh")
-        .args(["-c", command])
-        .stdout(Stdio::piped())
-        .stderr(Stdio::piped())
-        .spawn()
-        .context("Failed to execute post-snapshot-extract command")?;
+impl ExtractMarker {
+    fn for_archive(path: &Path) -> io::Result<Self> {
+        let mut file = File::open(path)?;
+        let size = file.metadata()?.len();
+
+        let mut hasher = Md5::new();
+        let mut sample = Vec::new();
+        (&mut file)
+            .take(MARKER_SAMPLE_BYTES)
+            .read_to_end(&mut sample)?;
+        hasher.update(&sample);
+        if size > MARKER_SAMPLE_BYTES {
+            sample.clear();
+            file.seek(SeekFrom::Start(
+                size.saturating_sub(MARKER_SAMPLE_BYTES)
+                    .max(MARKER_SAMPLE_BYTES),
+            ))?;
+            file.read_to_end(&mut sample)?;
+            hasher.update(&sample);
+        }
+
+        Ok(Self {
+            archive: path
+                .file_name()
+                .unwrap_or_default()
+                .to_string_lossy()
+                .into_owned(),
+            size,
+            sample_md5: format!("{:x}", hasher.finalize()),
+        })
+    }
+
+    /// The marker at `path`, or `None` if there is none or it cannot be parsed
+    fn read(path: &Path) -> Option<Self> {
+        let content = fs::read_to_string(path).ok()?;
+        serde_json::from_str(&content).ok()
+    }
+
+    fn write(&self, path: &Path) -> io::Result<()> {
+        fs::write(path, serde_json::to_string_pretty(self)?)
+    }
+}
+
+/// Extract into a temporary sibling of `target_dir`, then move the extracted entries into it
+/// If extraction fails the temporary directory is removed and `target_dir` is left untouched
+pub fn extract_archive_atomically(
+    archive_path: &Path,
+    target_dir: &Path,
+    options: &ExtractOptions,
+) -> Result<ExtractStats, ExtractError> {
+    let staging_dir = staging_dir(target_dir)?;
+
+    // Left over from an interrupted run
+    if staging_dir.exists() {
+        fs::remove_dir_all(&staging_dir)
+            .io_context(|| format!("Failed to remove stale staging directory {:?}", staging_dir))?;
+    }
+
+    debug!("Extracting into staging directory {:?}", staging_dir);
+    let stats = match extract_archive_with(archive_path, &staging_dir, options) {
+        Ok(stats) => stats,
+        Err(e) => {
+            if let Err(cleanup) = fs::remove_dir_all(&staging_dir) {
+                warn!(
+                    "Failed to remove staging directory {:?}: {}",
+                    staging_dir, cleanup
+                );
+            }
+            return Err(e);
+        }
+    };
+
+    fs::create_dir_all(target_dir)
+        .io_context(|| format!("Failed to create directory {:?}", target_dir))?;
+    move_into(&staging_dir, target_dir).io_context(|| {
+        format!(
+            "Failed to move extracted files from {:?} to {:?}",
+            staging_dir, target_dir
+        )
+    })?;
+    fs::remove_dir_all(&staging_dir)
+        .io_context(|| format!("Failed to remove staging directory {:?}", staging_dir))?;
+    Ok(stats)
+}
+
+/// Hidden directory next to `target_dir`, so moving out of it is a rename on the same filesystem
+fn staging_dir(target_dir: &Path) -> Result<PathBuf, ExtractError> {
+    let name = target_dir
+        .file_name()
+        .ok_or_else(|| ExtractError::InvalidTarget {
+            path: target_dir.to_path_buf(),
+        })?;
+    Ok(target_dir.with_file_name(format!(".{}.extracting", name.to_string_lossy())))
+}
+
+/// Move every entry of `from` into `to`, merging directories and replacing files like unpacking does
+fn move_into(from: &Path, to: &Path) -> io::Result<()> {
+    for entry in fs::read_dir(from)? {
+        let entry = entry?;
+        let source = entry.path();
+        let dest = to.join(entry.file_name());
+        let dest_metadata = fs::symlink_metadata(&dest).ok();
+
+        if entry.file_type()?.is_dir() && dest_metadata.as_ref().is_some_and(|m| m.is_dir()) {
+            move_into(&source, &dest)?;
+            continue;
+        }
+        match dest_metadata {
+            Some(metadata) if metadata.is_dir() => fs::remove_dir_all(&dest)?,
+            Some(_) => fs::remove_file(&dest)?,
+            None => {}
+        }
+        fs::rename(&source, &dest)?;
+    }
+    Ok(())
+}
+
+fn execute_post_snapshot_extract_command(cmd: &ShellCommand) -> Result<(), ExtractError> {
+    info!("Executing post-snapshot-extract command: {}", cmd.command);
+
+    let mut child = crate::runner::spawn_command(cmd).map_err(|e| ExtractError::Other {
+        message: format!("Failed to execute post-snapshot-extract command: {e:#}"),
+    })?;
 
     // Stream stdout in real-time
     if let Some(stdout) = child.stdout.take() {
@@ -147,7 +846,7 @@ fn execute_post_snapshot_extract_command(command: &str) -> Result<()> {
     // Wait for the process to complete
     let status = child
         .wait()
-        .context("Failed to wait for post-snapshot-extract command")?;
+        .io_context(|| "Failed to wait for post-snapshot-extract command".to_string())?;
 
     if status.success() {
         info!("Post-snapshot-extract command executed successfully");
@@ -158,36 +857,1382 @@ fn execute_post_snapshot_extract_command(command: &str) -> Result<()> {
             "Post-snapshot-extract command failed with exit code: {}",
             exit_code
         );
-        Err(anyhow::anyhow!(
-            "Post-snapshot-extract command failed with exit code: {}",
-            exit_code
-        ))
+        Err(ExtractError::CommandFailed { exit_code })
     }
 }
 
-fn extract_tar_gz(archive_path: &Path, target_dir: &Path) -> Result<()> {
-    info!("Extracting tar.gz archive...");
-    let file = File::open(archive_path)?;
-    let tar = GzDecoder::new(file);
-    let mut archive = Archive::new(tar);
-    archive.unpack(target_dir)?;
-    Ok(())
+/// Unpack a compressed tar archive, reporting progress by compressed bytes read
+/// A compressed file that is not a tar is written out whole, named after the archive minus its
+/// compression suffix (e.g. `application.db.lz4` becomes `application.db`)
+fn unpack_tar<F>(
+    archive_path: &Path,
+    target_dir: &Path,
+    options: &ExtractOptions,
+    decoder: F,
+) -> Result<ExtractStats, ExtractError>
+where
+    F: for<'a> FnOnce(ProgressReader<'a, File>) -> io::Result<Box<dyn Read + 'a>>,
+{
+    let (file, total, progress) = open_with_progress(archive_path, "extract", "Extracting")?;
+    let raw_path = target_dir.join(decompressed_file_name(archive_path));
+    match unpack_with_progress(
+        file, total, &progress, target_dir, &raw_path, options, decoder,
+    ) {
+        Ok(stats) => {
+            progress.finish_with_message(total, "Extraction complete");
+            info!(
+                "Extracted {} files and {} directories, {} bytes uncompressed",
+                stats.files, stats.dirs, stats.bytes
+            );
+            let file = archive_path
+                .file_name()
+                .unwrap_or_default()
+                .to_string_lossy();
+            progress::emit_summary("extract", &file, &stats);
+            Ok(stats)
+        }
+        Err(e) => {
+            progress.abandon();
+            Err(ExtractError::io(
+                format!("Failed to extract {:?} into {:?}", archive_path, target_dir),
+                e,
+            ))
+        }
+    }
 }
 
-fn extract_tar_zst(archive_path: &Path, target_dir: &Path) -> Result<()> {
-    info!("Extracting tar.zst archive...");
-    let file = File::open(archive_path)?;
-    let decoder = ZstdDecoder::new(file)?;
-    let mut archive = Archive::new(decoder);
-    archive.unpack(target_dir)?;
-    Ok(())
+/// Open an archive with a progress bar over its size, labelled with `action` (e.g. "Extracting")
+fn open_with_progress(
+    archive_path: &Path,
+    phase: &str,
+    action: &str,
+) -> Result<(File, u64, Progress), ExtractError> {
+    let file =
+        File::open(archive_path).io_context(|| format!("Failed to open {:?}", archive_path))?;
+    let total = file
+        .metadata()
+        .io_context(|| format!("Failed to read metadata of {:?}", archive_path))?
+        .len();
+    let progress = Progress::new(
+        phase,
+        &archive_path
+            .file_name()
+            .unwrap_or_default()
+            .to_string_lossy(),
+        total,
+        &format!(
+            "[{{elapsed_precise}}] {action} [{{bar:40.cyan/blue}}] {{bytes}}/{{total_bytes}} ({{eta}})"
+        ),
+    )
+    .map_err(|e| ExtractError::Other {
+        message: format!("{e:#}"),
+    })?;
+    Ok((file, total, progress))
 }
 
-fn extract_tar_lz4(archive_path: &Path, target_dir: &Path) -> Result<()> {
-    info!("Extracting tar.lz4 archive...");
-    let file = File::open(archive_path)?;
-    let decoder = Decoder::new(file)?;
-    let mut archive = Archive::new(decoder);
-    archive.unpack(target_dir)?;
-    Ok(())
+fn unpack_with_progress<F>(
+    file: File,
+    total: u64,
+    progress: &Progress,
+    target_dir: &Path,
+    raw_path: &Path,
+    options: &ExtractOptions,
+    decoder: F,
+) -> io::Result<ExtractStats>
+where
+    F: for<'a> FnOnce(ProgressReader<'a, File>) -> io::Result<Box<dyn Read + 'a>>,
+{
+    metrics::record_extract_progress(0, total);
+    let reader = ProgressReader {
+        inner: file,
+        read: 0,
+        total,
+        progress,
+    };
+    let (is_tar, mut stream) = peek_tar(decoder(reader)?)?;
+    if !is_tar {
+        info!(
+            "Archive does not contain a tar, writing its contents to {:?}",
+            raw_path
+        );
+        let bytes = io::copy(&mut stream, &mut File::create(raw_path)?)?;
+        return Ok(ExtractStats {
+            files: 1,
+            dirs: 0,
+            bytes,
+        });
+    }
+
+    let mut archive = Archive::new(stream);
+    let stats = unpack_entries(&mut archive, target_dir, options)?;
+
+    // tar stops at its end-of-archive marker; read the rest so the compressed trailer is consumed
+    io::copy(&mut archive.into_inner(), &mut io::sink())?;
+    Ok(stats)
+}
+
+/// Unpack the entries `options.filter` matches, like `Archive::unpack` does for all of them
+fn unpack_entries<R: Read>(
+    archive: &mut Archive<R>,
+    target_dir: &Path,
+    options: &ExtractOptions,
+) -> io::Result<ExtractStats> {
+    let workers = std::thread::available_parallelism().map_or(1, |n| n.get());
+    let (stats, skipped) = if options.parallel && workers > 1 {
+        debug!("Writing archive entries from {} worker threads", workers);
+        unpack_entries_parallel(archive, target_dir, &options.filter, workers)?
+    } else {
+        unpack_entries_sequential(archive, target_dir, &options.filter)?
+    };
+
+    if skipped > 0 {
+        info!(
+            "Skipped {} archive entries not selected by extract_include/extract_exclude",
+            skipped
+        );
+    }
+    Ok(stats)
+}
+
+/// Unpack the entries `filter` matches one after another, returning what was written and how
+/// many entries were skipped
+fn unpack_entries_sequential<R: Read>(
+    archive: &mut Archive<R>,
+    target_dir: &Path,
+    filter: &EntryFilter,
+) -> io::Result<(ExtractStats, u64)> {
+    fs::create_dir_all(target_dir)?;
+    let mut stats = ExtractStats::default();
+    let mut skipped = 0u64;
+    // Directories are unpacked last, deepest first, so read-only ones can still be filled
+    let mut directories = Vec::new();
+    for entry in archive.entries()? {
+        let mut entry = entry?;
+        let path = entry.path()?.into_owned();
+        if !filter.matches(&path) {
+            debug!("Skipping archive entry {:?}", path);
+            skipped += 1;
+            continue;
+        }
+        if entry.header().entry_type() == EntryType::Directory {
+            directories.push(entry);
+        } else {
+            let size = entry.size();
+            if entry.unpack_in(target_dir)? {
+                stats.add_file(size);
+            }
+        }
+    }
+    stats.dirs += unpack_directories(directories, target_dir)?;
+    Ok((stats, skipped))
+}
+
+/// Unpack directory entries deepest first, returning how many were unpacked
+fn unpack_directories<R: Read>(
+    mut directories: Vec<tar::Entry<'_, R>>,
+    target_dir: &Path,
+) -> io::Result<u64> {
+    directories.sort_by(|a, b| b.path_bytes().cmp(&a.path_bytes()));
+    let mut unpacked = 0;
+    for mut directory in directories {
+        if directory.unpack_in(target_dir)? {
+            unpacked += 1;
+        }
+    }
+    Ok(unpacked)
+}
+
+/// Largest file handed to the worker threads; bigger files are written by the reading thread, so
+/// the files held in memory while they wait for a worker stay small
+const PARALLEL_EXTRACT_MAX_FILE_BYTES: u64 = 4 * 1024 * 1024;
+
+/// Unpack the entries `filter` matches like `unpack_entries_sequential`, but read small regular
+/// files into memory and write them from `workers` threads
+///
+/// Parent directories are created, and checked to be inside `target_dir`, before a file is handed
+/// to a worker. Any other entry (a link, a large or sparse file) waits for the files in flight,
+/// so hard links find their targets and symlinks cannot redirect a pending write.
+fn unpack_entries_parallel<R: Read>(
+    archive: &mut Archive<R>,
+    target_dir: &Path,
+    filter: &EntryFilter,
+    workers: usize,
+) -> io::Result<(ExtractStats, u64)> {
+    fs::create_dir_all(target_dir)?;
+    let canonical_target = target_dir.canonicalize()?;
+    let pool = WritePool::new(workers);
+    let mut stats = ExtractStats::default();
+    let mut skipped = 0u64;
+    let mut directories = Vec::new();
+    // Directories already created and checked since the last link was unpacked
+    let mut checked_dirs: HashSet<PathBuf> = HashSet::new();
+    for entry in archive.entries()? {
+        let mut entry = entry?;
+        let path = entry.path()?.into_owned();
+        if !filter.matches(&path) {
+            debug!("Skipping archive entry {:?}", path);
+            skipped += 1;
+            continue;
+        }
+        let entry_type = entry.header().entry_type();
+        if entry_type == EntryType::Directory {
+            directories.push(entry);
+            continue;
+        }
+
+        let dest = entry_destination(target_dir, &path);
+        // A later entry for the same path replaces the earlier one, so it must be written after it
+        if dest.as_ref().is_some_and(|dest| pool.is_in_flight(dest)) {
+            pool.wait()?;
+        }
+        let parallel = matches!(entry_type, EntryType::Regular | EntryType::Continuous)
+            && entry.size() <= PARALLEL_EXTRACT_MAX_FILE_BYTES;
+        match (dest, parallel) {
+            (Some(dest), true) => {
+                let parent = dest.parent().unwrap_or(target_dir);
+                if !checked_dirs.contains(parent) {
+                    fs::create_dir_all(parent)?;
+                    if !parent.canonicalize()?.starts_with(&canonical_target) {
+                        return Err(io::Error::other(format!(
+                            "{:?} is outside of {:?}",
+                            parent, target_dir
+                        )));
+                    }
+                    checked_dirs.insert(parent.to_path_buf());
+                }
+                let mut data = Vec::with_capacity(entry.size() as usize);
+                entry.read_to_end(&mut data)?;
+                stats.add_file(entry.size());
+                pool.submit(FileWrite {
+                    path: dest,
+                    data,
+                    mode: entry.header().mode()?,
+                    mtime: entry.header().mtime()?,
+                })?;
+            }
+            _ => {
+                if entry_type != EntryType::Regular {
+                    pool.wait()?;
+                    checked_dirs.clear();
+                }
+                let size = entry.size();
+                if entry.unpack_in(target_dir)? {
+                    stats.add_file(size);
+                }
+            }
+        }
+    }
+    pool.finish()?;
+
+    stats.dirs += unpack_directories(directories, target_dir)?;
+    Ok((stats, skipped))
+}
+
+/// Where `unpack_in` would write an entry, or `None` for entries it skips (paths with `..`) or
+/// that name `target_dir` itself
+fn entry_destination(target_dir: &Path, entry_path: &Path) -> Option<PathBuf> {
+    let mut dest = target_dir.to_path_buf();
+    for component in entry_path.components() {
+        match component {
+            Component::Prefix(_) | Component::RootDir | Component::CurDir => {}
+            Component::ParentDir => return None,
+            Component::Normal(part) => dest.push(part),
+        }
+    }
+    (dest != target_dir).then_some(dest)
+}
+
+/// A regular file read from the archive, waiting to be written by a worker
+struct FileWrite {
+    path: PathBuf,
+    data: Vec<u8>,
+    mode: u32,
+    mtime: u64,
+}
+
+impl FileWrite {
+    /// Write the file like `unpack_in` does: replacing rather than overwriting an existing file,
+    /// with the archive's modification time and permission bits
+    fn write(&self) -> io::Result<()> {
+        let open = || {
+            OpenOptions::new()
+                .write(true)
+                .create_new(true)
+                .open(&self.path)
+        };
+        let mut file = match open() {
+            Err(e) if e.kind() == io::ErrorKind::AlreadyExists => {
+                fs::remove_file(&self.path)?;
+                open()?
+            }
+            result => result?,
+        };
+        file.write_all(&self.data)?;
+        // Like tar, avoid files with a zero mtime
+        file.set_modified(UNIX_EPOCH + Duration::from_secs(self.mtime.max(1)))?;
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+            file.set_permissions(fs::Permissions::from_mode(self.mode & 0o777))?;
+        }
+        Ok(())
+    }
+}
+
+/// Files handed to the workers and not yet written, with the first write error
+#[derive(Default)]
+struct PoolState {
+    in_flight: HashSet<PathBuf>,
+    error: Option<io::Error>,
+}
+
+/// Worker threads writing `FileWrite`s, at most two per worker queued at a time
+struct WritePool {
+    sender: Option<SyncSender<FileWrite>>,
+    workers: Vec<JoinHandle<()>>,
+    state: Arc<(Mutex<PoolState>, Condvar)>,
+}
+
+impl WritePool {
+    fn new(workers: usize) -> Self {
+        let (sender, receiver) = mpsc::sync_channel::<FileWrite>(workers * 2);
+        let receiver = Arc::new(Mutex::new(receiver));
+        let state = Arc::new((Mutex::new(PoolState::default()), Condvar::new()));
+        let workers = (0..workers)
+            .map(|_| {
+                let receiver = Arc::clone(&receiver);
+                let state = Arc::clone(&state);
+                std::thread::spawn(move || Self::work(&receiver, &state))
+            })
+            .collect();
+        Self {
+            sender: Some(sender),
+            workers,
+            state,
+        }
+    }
+
+    fn work(receiver: &Mutex<Receiver<FileWrite>>, state: &(Mutex<PoolState>, Condvar)) {
+        loop {
+            // Hold the lock only while taking the next file, not while writing it
+            let next = receiver.lock().map(|receiver| receiver.recv());
+            let Ok(Ok(file)) = next else {
+                return;
+            };
+            let result = file.write().map_err(|e| {
+                io::Error::new(e.kind(), format!("Failed to write {:?}: {e}", file.path))
+            });
+            let (lock, written) = state;
+            let mut state = lock.lock().unwrap_or_else(|e| e.into_inner());
+            state.in_flight.remove(&file.path);
+            if let Err(e) = result {
+                state.error.get_or_insert(e);
+            }
+            written.notify_all();
+        }
+    }
+
+    fn lock_state(&self) -> std::sync::MutexGuard<'_, PoolState> {
+        self.state.0.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    fn is_in_flight(&self, path: &Path) -> bool {
+        self.lock_state().in_flight.contains(path)
+    }
+
+    /// Queue a file, blocking while the queue is full; fails with the first error a worker hit
+    fn submit(&self, file: FileWrite) -> io::Result<()> {
+        {
+            let mut state = self.lock_state();
+            if let Some(e) = state.error.take() {
+                return Err(e);
+            }
+            state.in_flight.insert(file.path.clone());
+        }
+        self.sender
+            .as_ref()
+            .and_then(|sender| sender.send(file).ok())
+            .ok_or_else(|| io::Error::other("Extraction workers exited"))
+    }
+
+    /// Wait until every queued file is written
+    fn wait(&self) -> io::Result<()> {
+        let (_, written) = &*self.state;
+        let mut state = self.lock_state();
+        while !state.in_flight.is_empty() && state.error.is_none() {
+            state = written.wait(state).unwrap_or_else(|e| e.into_inner());
+        }
+        match state.error.take() {
+            Some(e) => Err(e),
+            None => Ok(()),
+        }
+    }
+
+    /// Wait for the queued files and stop the workers
+    fn finish(mut self) -> io::Result<()> {
+        let result = self.wait();
+        self.stop();
+        result
+    }
+
+    fn stop(&mut self) {
+        self.sender = None;
+        for worker in self.workers.drain(..) {
+            let _ = worker.join();
+        }
+    }
+}
+
+impl Drop for WritePool {
+    /// Let the workers finish, so no file is written after extraction has failed and returned
+    fn drop(&mut self) {
+        self.stop();
+    }
+}
+
+const TAR_BLOCK_SIZE: usize = 512;
+
+/// Whether a decompressed stream holds a tar, along with the stream from its start
+/// Some providers publish a single compressed file instead of a tar, so look before unpacking
+fn peek_tar<'a>(mut decoded: Box<dyn Read + 'a>) -> io::Result<(bool, impl Read + 'a)> {
+    let mut header = Vec::with_capacity(TAR_BLOCK_SIZE);
+    decoded
+        .by_ref()
+        .take(TAR_BLOCK_SIZE as u64)
+        .read_to_end(&mut header)?;
+    Ok((
+        is_tar_header(&header),
+        io::Cursor::new(header).chain(decoded),
+    ))
+}
+
+/// Whether a decompressed stream starts with a tar header: the `ustar` magic at offset 257 (POSIX
+/// and GNU tar), or an all-zero end-of-archive block for an empty archive
+fn is_tar_header(header: &[u8]) -> bool {
+    header.get(257..262) == Some(b"ustar")
+        || (header.len() == TAR_BLOCK_SIZE && header.iter().all(|&b| b == 0))
+}
+
+/// The archive's file name without its compression suffix
+fn decompressed_file_name(archive_path: &Path) -> &OsStr {
+    let name = match archive_path.extension().and_then(|e| e.to_str()) {
+        Some("gz" | "lz4" | "zst") => archive_path.file_stem(),
+        _ => archive_path.file_name(),
+    };
+    name.unwrap_or(OsStr::new("snapshot"))
+}
+
+/// Archive reader that reports how far extraction has got to the progress bar and metrics endpoint
+struct ProgressReader<'a, R> {
+    inner: R,
+    read: u64,
+    total: u64,
+    progress: &'a Progress,
+}
+
+impl<R: Read> Read for ProgressReader<'_, R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let n = self.inner.read(buf)?;
+        self.read += n as u64;
+        self.progress.set_position(self.read);
+        metrics::record_extract_progress(self.read, self.total);
+        Ok(n)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use anyhow::Result;
+    use flate2::write::GzEncoder;
+    use flate2::Compression;
+    use indicatif::ProgressBar;
+    use tempfile::tempdir;
+
+    /// `command` run with the default shell in the current directory
+    fn shell(command: &str) -> ShellCommand<'_> {
+        ShellCommand {
+            command,
+            shell: crate::runner::DEFAULT_COMMAND_SHELL,
+            working_dir: None,
+        }
+    }
+
+    /// Write a tar.gz archive holding the given files
+    fn write_tar_gz(path: &Path, files: &[(&str, &[u8])]) -> Result<()> {
+        let mut builder =
+            tar::Builder::new(GzEncoder::new(File::create(path)?, Compression::default()));
+        for (name, data) in files {
+            let mut header = tar::Header::new_gnu();
+            header.set_size(data.len() as u64);
+            header.set_mode(0o644);
+            header.set_cksum();
+            builder.append_data(&mut header, name, *data)?;
+        }
+        builder.into_inner()?.finish()?;
+        Ok(())
+    }
+
+    /// Compress `data` with LZ4 frame format
+    fn lz4_compress(path: &Path, data: &[u8]) -> Result<()> {
+        let mut encoder = lz4::EncoderBuilder::new().build(File::create(path)?)?;
+        io::Write::write_all(&mut encoder, data)?;
+        let (_, result) = encoder.finish();
+        Ok(result?)
+    }
+
+    /// A path under an extracted directory with its type, content or link target, permissions
+    /// and mtime
+    type TreeEntry = (PathBuf, String, Vec<u8>, u32, u64);
+
+    fn read_tree(dir: &Path) -> Result<Vec<TreeEntry>> {
+        let mut tree = Vec::new();
+        let mut pending = vec![dir.to_path_buf()];
+        while let Some(current) = pending.pop() {
+            for entry in fs::read_dir(&current)? {
+                let path = entry?.path();
+                let metadata = fs::symlink_metadata(&path)?;
+                let (kind, content) = if metadata.is_dir() {
+                    pending.push(path.clone());
+                    ("dir", Vec::new())
+                } else if metadata.is_symlink() {
+                    let target = fs::read_link(&path)?;
+                    (
+                        "symlink",
+                        target.to_string_lossy().into_owned().into_bytes(),
+                    )
+                } else {
+                    ("file", fs::read(&path)?)
+                };
+                #[cfg(unix)]
+                let mode = std::os::unix::fs::PermissionsExt::mode(&metadata.permissions());
+                #[cfg(not(unix))]
+                let mode = 0;
+                let mtime = match kind {
+                    "file" => metadata.modified()?.duration_since(UNIX_EPOCH)?.as_secs(),
+                    _ => 0,
+                };
+                let relative = path.strip_prefix(dir)?.to_path_buf();
+                tree.push((relative, kind.to_string(), content, mode, mtime));
+            }
+        }
+        tree.sort();
+        Ok(tree)
+    }
+
+    #[test]
+    fn test_parallel_extract_matches_sequential() -> Result<()> {
+        let mut builder = tar::Builder::new(Vec::new());
+        let append = |builder: &mut tar::Builder<Vec<u8>>,
+                      name: &str,
+                      entry_type: EntryType,
+                      mode: u32,
+                      data: &[u8]| {
+            let mut header = tar::Header::new_gnu();
+            header.set_entry_type(entry_type);
+            header.set_size(data.len() as u64);
+            header.set_mode(mode);
+            header.set_mtime(1_700_000_000 + name.len() as u64);
+            header.set_cksum();
+            builder.append_data(&mut header, name, data)
+        };
+        append(&mut builder, "data/", EntryType::Directory, 0o750, b"")?;
+        for i in 0..200 {
+            let name = format!("data/db{}/{:06}.ldb", i % 7, i);
+            let mode = if i % 3 == 0 { 0o600 } else { 0o644 };
+            append(
+                &mut builder,
+                &name,
+                EntryType::Regular,
+                mode,
+                name.repeat(i).as_bytes(),
+            )?;
+        }
+        // Larger than a worker takes, so written by the reading thread
+        let large: Vec<u8> = (0..PARALLEL_EXTRACT_MAX_FILE_BYTES + 1)
+            .map(|i| (i % 251) as u8)
+            .collect();
+        append(
+            &mut builder,
+            "data/blockstore.db",
+            EntryType::Regular,
+            0o644,
+            &large,
+        )?;
+        // A later entry for the same path replaces the earlier one
+        append(
+            &mut builder,
+            "config/genesis.json",
+            EntryType::Regular,
+            0o644,
+            b"old",
+        )?;
+        append(
+            &mut builder,
+            "config/genesis.json",
+            EntryType::Regular,
+            0o640,
+            b"new",
+        )?;
+        append(&mut builder, "wasm/", EntryType::Directory, 0o755, b"")?;
+        append(
+            &mut builder,
+            "wasm/code",
+            EntryType::Regular,
+            0o755,
+            b"wasm",
+        )?;
+        let mut header = tar::Header::new_gnu();
+        header.set_entry_type(EntryType::Link);
+        header.set_size(0);
+        header.set_mode(0o644);
+        header.set_mtime(1_700_000_000);
+        builder.append_link(&mut header, "data/hardlink.ldb", "data/db1/000001.ldb")?;
+        #[cfg(unix)]
+        {
+            let mut header = tar::Header::new_gnu();
+            header.set_entry_type(EntryType::Symlink);
+            header.set_size(0);
+            header.set_mode(0o777);
+            builder.append_link(&mut header, "data/latest", "db6")?;
+            append(
+                &mut builder,
+                "data/latest/after_link.ldb",
+                EntryType::Regular,
+                0o644,
+                b"via link",
+            )?;
+        }
+        let tar = builder.into_inner()?;
+
+        let temp_dir = tempdir()?;
+        let sequential = temp_dir.path().join("sequential");
+        let parallel = temp_dir.path().join("parallel");
+        let filter = EntryFilter::default();
+        let (stats, skipped) =
+            unpack_entries_sequential(&mut Archive::new(&tar[..]), &sequential, &filter)?;
+        assert_eq!(skipped, 0);
+        assert_eq!(stats.files, 205 + cfg!(unix) as u64 * 2);
+        assert_eq!(stats.dirs, 2);
+        assert_eq!(
+            unpack_entries_parallel(&mut Archive::new(&tar[..]), &parallel, &filter, 4)?,
+            (stats, 0)
+        );
+
+        let tree = read_tree(&sequential)?;
+        assert_eq!(tree.len(), 200 + 7 + 7 + cfg!(unix) as usize * 2);
+        assert_eq!(read_tree(&parallel)?, tree);
+        assert_eq!(fs::read(parallel.join("config/genesis.json"))?, b"new");
+        assert_eq!(fs::read(parallel.join("data/blockstore.db"))?, large);
+        Ok(())
+    }
+
+    #[test]
+    fn test_zstd_window_log_max() -> Result<()> {
+        let temp_dir = tempdir()?;
+        let mut builder = tar::Builder::new(Vec::new());
+        let mut header = tar::Header::new_gnu();
+        header.set_size(4);
+        header.set_mode(0o644);
+        header.set_cksum();
+        builder.append_data(&mut header, "data/state.db", &b"data"[..])?;
+        let tar = builder.into_inner()?;
+
+        // A 256 MiB window (log 28), one above what the decoder accepts by default; written as
+        // two frames, like a multi-threaded compressor would
+        let archive_path = temp_dir.path().join("snapshot.tar.zst");
+        let mut archive = Vec::new();
+        for chunk in tar.chunks(tar.len() / 2) {
+            let mut encoder = zstd::stream::write::Encoder::new(Vec::new(), 3)?;
+            encoder.window_log(28)?;
+            io::Write::write_all(&mut encoder, chunk)?;
+            archive.extend(encoder.finish()?);
+        }
+        fs::write(&archive_path, archive)?;
+
+        let home = temp_dir.path().join("home");
+        assert!(extract_archive(&archive_path, &home).is_err());
+
+        let options = ExtractOptions {
+            zstd_window_log_max: Some(28),
+            ..Default::default()
+        };
+        extract_archive_with(&archive_path, &home, &options)?;
+        assert_eq!(fs::read_to_string(home.join("data/state.db"))?, "data");
+        Ok(())
+    }
+
+    #[test]
+    fn test_extract_tar_lz4() -> Result<()> {
+        let temp_dir = tempdir()?;
+        let mut builder = tar::Builder::new(Vec::new());
+        let mut header = tar::Header::new_gnu();
+        header.set_size(4);
+        header.set_mode(0o644);
+        header.set_cksum();
+        builder.append_data(&mut header, "data/blockstore.db", &b"data"[..])?;
+        let archive_path = temp_dir.path().join("data.tar.lz4");
+        lz4_compress(&archive_path, &builder.into_inner()?)?;
+
+        let home = temp_dir.path().join("home");
+        extract_archive(&archive_path, &home)?;
+        assert_eq!(fs::read_to_string(home.join("data/blockstore.db"))?, "data");
+        assert!(!home.join("data.tar").exists());
+        Ok(())
+    }
+
+    #[test]
+    fn test_extract_stats_count_fixture() -> Result<()> {
+        let temp_dir = tempdir()?;
+        let mut builder = tar::Builder::new(GzEncoder::new(
+            File::create(temp_dir.path().join("snapshot.tar.gz"))?,
+            Compression::default(),
+        ));
+        for dir in ["data/", "data/application.db/", "wasm/"] {
+            let mut header = tar::Header::new_gnu();
+            header.set_entry_type(EntryType::Directory);
+            header.set_size(0);
+            header.set_mode(0o755);
+            header.set_cksum();
+            builder.append_data(&mut header, dir, io::empty())?;
+        }
+        for (name, data) in [
+            ("data/application.db/000001.ldb", &b"app"[..]),
+            ("data/priv_validator_state.json", b"{}"),
+            ("wasm/code", b"wasm code"),
+            ("node.log", b"excluded"),
+        ] {
+            let mut header = tar::Header::new_gnu();
+            header.set_size(data.len() as u64);
+            header.set_mode(0o644);
+            header.set_cksum();
+            builder.append_data(&mut header, name, data)?;
+        }
+        builder.into_inner()?.finish()?;
+        let archive_path = temp_dir.path().join("snapshot.tar.gz");
+
+        let options = ExtractOptions {
+            filter: EntryFilter::new(&[], &["*.log".to_string()])?,
+            ..Default::default()
+        };
+        let stats = extract_archive_with(&archive_path, &temp_dir.path().join("home"), &options)?;
+        assert_eq!(
+            stats,
+            ExtractStats {
+                files: 3,
+                dirs: 3,
+                bytes: 14
+            }
+        );
+
+        let stats = extract_snapshot(
+            &archive_path,
+            &temp_dir.path().join("atomic"),
+            None,
+            &ExtractOptions::default(),
+            true,
+            true,
+        )?;
+        assert_eq!(
+            stats,
+            Some(ExtractStats {
+                files: 4,
+                dirs: 3,
+                bytes: 22
+            })
+        );
+        // Skipped as already extracted
+        let stats = extract_snapshot(
+            &archive_path,
+            &temp_dir.path().join("atomic"),
+            None,
+            &ExtractOptions::default(),
+            true,
+            true,
+        )?;
+        assert_eq!(stats, None);
+
+        let raw_path = temp_dir.path().join("application.db.lz4");
+        lz4_compress(&raw_path, b"raw database")?;
+        let stats = extract_archive(&raw_path, &temp_dir.path().join("raw"))?;
+        assert_eq!(
+            stats,
+            ExtractStats {
+                files: 1,
+                dirs: 0,
+                bytes: 12
+            }
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_verify_archive_detects_truncated_gzip() -> Result<()> {
+        let temp_dir = tempdir()?;
+        let archive_path = temp_dir.path().join("snapshot.tar.gz");
+        let data: Vec<u8> = (0..256 * 1024).map(|i| (i * 7 % 251) as u8).collect();
+        write_tar_gz(
+            &archive_path,
+            &[("data/blockstore.db", &data), ("data/state.db", b"state")],
+        )?;
+
+        let summary = verify_archive(&archive_path, &ExtractOptions::default())?;
+        assert_eq!(summary.entries, Some(2));
+        assert_eq!(summary.bytes, data.len() as u64 + 5);
+
+        let content = fs::read(&archive_path)?;
+        let truncated = temp_dir.path().join("truncated.tar.gz");
+        fs::write(&truncated, &content[..content.len() / 2])?;
+        match verify_archive(&truncated, &ExtractOptions::default()) {
+            Err(ExtractError::Io { context, .. }) => assert!(context.contains("truncated.tar.gz")),
+            other => panic!("expected Io, got {other:?}"),
+        }
+        // Nothing is written next to the archive
+        assert_eq!(fs::read_dir(temp_dir.path())?.count(), 2);
+        Ok(())
+    }
+
+    #[test]
+    fn test_extract_include_and_exclude() -> Result<()> {
+        let temp_dir = tempdir()?;
+        let archive_path = temp_dir.path().join("snapshot.tar.gz");
+        write_tar_gz(
+            &archive_path,
+            &[
+                ("./data/application.db/000001.ldb", b"app"),
+                ("./data/cs.wal/wal.log", b"wal"),
+                ("./data/node.log", b"log"),
+                ("./wasm/code", b"wasm"),
+                ("./node.log", b"log"),
+            ],
+        )?;
+
+        let include_data = ExtractOptions {
+            filter: EntryFilter::new(&["data/**".to_string()], &[])?,
+            ..Default::default()
+        };
+        let home = temp_dir.path().join("data_only");
+        extract_archive_with(&archive_path, &home, &include_data)?;
+        assert_eq!(
+            fs::read_to_string(home.join("data/application.db/000001.ldb"))?,
+            "app"
+        );
+        assert!(home.join("data/cs.wal/wal.log").exists());
+        assert!(!home.join("wasm").exists());
+        assert!(!home.join("node.log").exists());
+
+        let exclude_logs = ExtractOptions {
+            filter: EntryFilter::new(&[], &["**/*.log".to_string()])?,
+            ..Default::default()
+        };
+        let home = temp_dir.path().join("no_logs");
+        extract_archive_with(&archive_path, &home, &exclude_logs)?;
+        assert!(home.join("data/application.db/000001.ldb").exists());
+        assert_eq!(fs::read_to_string(home.join("wasm/code"))?, "wasm");
+        assert!(!home.join("data/cs.wal/wal.log").exists());
+        assert!(!home.join("data/node.log").exists());
+        assert!(!home.join("node.log").exists());
+        Ok(())
+    }
+
+    #[test]
+    fn test_decompress_members() -> Result<()> {
+        let temp_dir = tempdir()?;
+        let archive_path = temp_dir.path().join("snapshot.tar.gz");
+        let blocks = zstd::encode_all(&b"block data"[..], 0)?;
+        let state = zstd::encode_all(&b"state data"[..], 0)?;
+        write_tar_gz(
+            &archive_path,
+            &[
+                ("./data/blocks.db.zst", &blocks),
+                ("./data/state/state.db.zst", &state),
+                ("./wasm/code.zst", &blocks),
+            ],
+        )?;
+
+        let home = temp_dir.path().join("home");
+        extract_archive(&archive_path, &home)?;
+        let members = EntryFilter::new(&["data/**/*.zst".to_string()], &[])?;
+        assert_eq!(decompress_members(&home, &members, None)?, 2);
+        assert_eq!(fs::read(home.join("data/blocks.db"))?, b"block data");
+        assert_eq!(fs::read(home.join("data/state/state.db"))?, b"state data");
+        assert!(!home.join("data/blocks.db.zst").exists());
+        assert!(!home.join("data/state/state.db.zst").exists());
+        // Files not matching the patterns are left compressed
+        assert_eq!(fs::read(home.join("wasm/code.zst"))?, blocks);
+
+        // A matching file that is not compressed is an error
+        fs::write(home.join("data/plain.zst"), "plain")?;
+        assert!(matches!(
+            decompress_members(&home, &members, None),
+            Err(ExtractError::UnsupportedFormat { .. })
+        ));
+
+        // Snapshot extraction decompresses them when the options ask for it
+        let home = temp_dir.path().join("snapshot_home");
+        let options = ExtractOptions {
+            decompress_members: Some(members),
+            ..Default::default()
+        };
+        extract_snapshot(&archive_path, &home, None, &options, false, true)?;
+        assert_eq!(fs::read(home.join("data/blocks.db"))?, b"block data");
+        assert_eq!(fs::read(home.join("wasm/code.zst"))?, blocks);
+        Ok(())
+    }
+
+    #[test]
+    fn test_entry_filter_globs() -> Result<()> {
+        let filter = |include: &[&str], exclude: &[&str]| {
+            let strings = |patterns: &[&str]| patterns.iter().map(|p| p.to_string()).collect();
+            let include: Vec<String> = strings(include);
+            let exclude: Vec<String> = strings(exclude);
+            EntryFilter::new(&include, &exclude)
+        };
+
+        let f = filter(&["data/*.db", "{wasm,config}/**"], &["**/LOCK"])?;
+        assert!(f.matches(Path::new("data/state.db")));
+        assert!(!f.matches(Path::new("data/state.db/000001.ldb")));
+        assert!(f.matches(Path::new("wasm/code/abc")));
+        assert!(f.matches(Path::new("./config/app.toml")));
+        assert!(!f.matches(Path::new("wasm/LOCK")));
+        assert!(!f.matches(Path::new("other/state.db")));
+
+        let f = filter(&["data/00000?.[!t]db"], &[])?;
+        assert!(f.matches(Path::new("data/000001.ldb")));
+        assert!(!f.matches(Path::new("data/000001.tdb")));
+        assert!(!f.matches(Path::new("data/0000012.ldb")));
+
+        assert!(EntryFilter::default().matches(Path::new("anything/at/all")));
+        assert!(filter(&["data**"], &[]).is_err());
+        assert!(filter(&["{data,wasm"], &[]).is_err());
+        assert!(filter(&[], &["[abc"]).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_glob_to_regex_syntax() {
+        let matches = |glob: &str, path: &str| glob_to_regex(glob).unwrap().is_match(path);
+
+        // `**` as a whole component: leading, inner, trailing and alone
+        assert!(matches("**/LOCK", "LOCK"));
+        assert!(matches("**/LOCK", "data/a/LOCK"));
+        assert!(!matches("**/LOCK", "data/NOLOCK"));
+        assert!(matches("data/**/LOCK", "data/LOCK"));
+        assert!(matches("data/**/LOCK", "data/a/b/LOCK"));
+        assert!(matches("data/**", "data/a/b"));
+        assert!(!matches("data/**", "database"));
+        assert!(matches("**", "any/path"));
+        for glob in ["data**", "**data", "data/**x", "data/x**/y"] {
+            assert!(glob_to_regex(glob).is_err(), "{glob}");
+        }
+
+        // `*` and `?` stay within one component
+        assert!(matches("data/*", "data/state.db"));
+        assert!(!matches("data/*", "data/a/state.db"));
+        assert!(matches("*.db", ".db"));
+        assert!(matches("data/?.db", "data/a.db"));
+        assert!(!matches("data/?.db", "data/ab.db"));
+        assert!(!matches("data?x", "data/x"));
+
+        // Character classes, negated with `!` or `^`, with a leading `]` taken literally and
+        // regex class syntax escaped
+        assert!(matches("[ab].db", "b.db"));
+        assert!(!matches("[ab].db", "c.db"));
+        assert!(matches("[!ab].db", "c.db"));
+        assert!(!matches("[!ab].db", "a.db"));
+        assert!(matches("[^ab].db", "c.db"));
+        assert!(!matches("[^ab].db", "b.db"));
+        assert!(matches("[]a]", "]"));
+        assert!(matches("[a^&~]", "^"));
+        assert!(matches("[a^&~]", "~"));
+        assert!(matches("[a-c]", "b"));
+        assert!(glob_to_regex("[abc").is_err());
+        assert!(glob_to_regex("[!]").is_err());
+
+        // Alternatives, with `,` and `}` literal outside them
+        assert!(matches("{wasm,data}/x", "wasm/x"));
+        assert!(matches("{wasm,data}/x", "data/x"));
+        assert!(!matches("{wasm,data}/x", "config/x"));
+        assert!(matches("{a,}b", "b"));
+        assert!(matches("a,b}", "a,b}"));
+        assert!(glob_to_regex("{a,b").is_err());
+
+        // Anything else is matched literally
+        assert!(matches("a.b+(c)|$", "a.b+(c)|$"));
+        assert!(!matches("a.b", "axb"));
+    }
+
+    #[test]
+    fn test_extract_raw_lz4_and_gzip_files() -> Result<()> {
+        let temp_dir = tempdir()?;
+        let data: Vec<u8> = (0..100_000u32).map(|i| (i % 251) as u8).collect();
+        let home = temp_dir.path().join("home");
+
+        let lz4_path = temp_dir.path().join("application.db.lz4");
+        lz4_compress(&lz4_path, &data)?;
+        extract_archive(&lz4_path, &home)?;
+        assert_eq!(fs::read(home.join("application.db"))?, data);
+
+        let gz_path = temp_dir.path().join("genesis.json.gz");
+        let mut encoder = GzEncoder::new(File::create(&gz_path)?, Compression::default());
+        io::Write::write_all(&mut encoder, br#"{"chain_id":"testchain-1"}"#)?;
+        encoder.finish()?;
+        extract_archive(&gz_path, &home)?;
+        assert_eq!(
+            fs::read_to_string(home.join("genesis.json"))?,
+            r#"{"chain_id":"testchain-1"}"#
+        );
+
+        // Shorter than a tar header
+        let short_path = temp_dir.path().join("tiny.lz4");
+        lz4_compress(&short_path, b"tiny")?;
+        extract_archive(&short_path, &home)?;
+        assert_eq!(fs::read_to_string(home.join("tiny"))?, "tiny");
+        Ok(())
+    }
+
+    #[test]
+    fn test_extract_progress_reaches_archive_size() -> Result<()> {
+        let temp_dir = tempdir()?;
+        let archive_path = temp_dir.path().join("snapshot.tar.gz");
+        let data = vec![7u8; 256 * 1024];
+        write_tar_gz(&archive_path, &[("data/blockstore.db", &data)])?;
+
+        let pb = ProgressBar::hidden();
+        let progress = Progress::Bar(pb.clone());
+        let file = File::open(&archive_path)?;
+        let total = file.metadata()?.len();
+        let target_dir = temp_dir.path().join("home");
+        let raw_path = target_dir.join("snapshot");
+        unpack_with_progress(
+            file,
+            total,
+            &progress,
+            &target_dir,
+            &raw_path,
+            &ExtractOptions::default(),
+            |reader| Ok(Box::new(GzDecoder::new(reader))),
+        )?;
+
+        assert_eq!(pb.position(), total);
+        assert_eq!(fs::read(target_dir.join("data/blockstore.db"))?, data);
+        Ok(())
+    }
+
+    #[test]
+    fn test_atomic_extract_merges_into_target() -> Result<()> {
+        let temp_dir = tempdir()?;
+        let home = temp_dir.path().join("home");
+        fs::create_dir_all(home.join("config"))?;
+        fs::write(home.join("config/genesis.json"), "{}")?;
+        fs::create_dir_all(home.join("data"))?;
+        fs::write(home.join("data/old.db"), "old")?;
+
+        let archive_path = temp_dir.path().join("snapshot.tar.gz");
+        write_tar_gz(
+            &archive_path,
+            &[
+                ("data/old.db", b"new"),
+                ("data/state.db/000001.log", b"log"),
+                ("wasm/code", b"wasm"),
+            ],
+        )?;
+
+        extract_archive_atomically(&archive_path, &home, &ExtractOptions::default())?;
+
+        assert_eq!(fs::read_to_string(home.join("config/genesis.json"))?, "{}");
+        assert_eq!(fs::read_to_string(home.join("data/old.db"))?, "new");
+        assert_eq!(
+            fs::read_to_string(home.join("data/state.db/000001.log"))?,
+            "log"
+        );
+        assert_eq!(fs::read_to_string(home.join("wasm/code"))?, "wasm");
+        assert!(!staging_dir(&home)?.exists());
+        Ok(())
+    }
+
+    #[test]
+    fn test_atomic_extract_failure_leaves_target_clean() -> Result<()> {
+        let temp_dir = tempdir()?;
+        let home = temp_dir.path().join("home");
+        fs::create_dir_all(home.join("config"))?;
+        fs::write(home.join("config/genesis.json"), "{}")?;
+
+        // Cut the archive off after its first entry, like a disk filling up mid-extraction
+        let archive_path = temp_dir.path().join("snapshot.tar.gz");
+        let noise: Vec<u8> = (0..512 * 1024u32).map(|i| (i * 7919 % 251) as u8).collect();
+        write_tar_gz(
+            &archive_path,
+            &[("data/first.db", b"first"), ("data/second.db", &noise)],
+        )?;
+        let archive = fs::read(&archive_path)?;
+        fs::write(&archive_path, &archive[..archive.len() / 2])?;
+
+        assert!(
+            extract_archive_atomically(&archive_path, &home, &ExtractOptions::default()).is_err()
+        );
+
+        let entries: Vec<_> = fs::read_dir(&home)?
+            .map(|entry| entry.map(|e| e.file_name()))
+            .collect::<io::Result<_>>()?;
+        assert_eq!(entries, vec![std::ffi::OsString::from("config")]);
+        assert!(!staging_dir(&home)?.exists());
+
+        // Without atomic extraction the partial data is left behind
+        assert!(extract_archive(&archive_path, &home).is_err());
+        assert!(home.join("data/first.db").exists());
+        Ok(())
+    }
+
+    #[test]
+    fn test_extract_error_variants() -> Result<()> {
+        let temp_dir = tempdir()?;
+        let target = temp_dir.path().join("home");
+
+        let unknown = temp_dir.path().join("snapshot.rar");
+        fs::write(&unknown, b"Rar!")?;
+        assert!(matches!(
+            extract_archive(&unknown, &target),
+            Err(ExtractError::UnsupportedFormat { path }) if path == unknown
+        ));
+
+        // A truncated archive fails while reading it
+        let truncated = temp_dir.path().join("snapshot.tar.gz");
+        write_tar_gz(&truncated, &[("data/state.db", &[1u8; 64 * 1024])])?;
+        let archive = fs::read(&truncated)?;
+        fs::write(&truncated, &archive[..archive.len() / 2])?;
+        assert!(matches!(
+            extract_archive(&truncated, &target),
+            Err(ExtractError::Io { .. })
+        ));
+
+        let missing = temp_dir.path().join("missing.tar.gz");
+        match extract_archive(&missing, &target) {
+            Err(ExtractError::Io { source, .. }) => {
+                assert_eq!(source.kind(), io::ErrorKind::NotFound)
+            }
+            other => panic!("expected Io, got {other:?}"),
+        }
+
+        assert!(matches!(
+            extract_snapshot(
+                &truncated,
+                &target,
+                Some(&shell("exit 4")),
+                &ExtractOptions::default(),
+                false,
+                true
+            ),
+            Err(ExtractError::Io { .. })
+        ));
+        write_tar_gz(&truncated, &[("data/state.db", b"state")])?;
+        assert!(matches!(
+            extract_snapshot(
+                &truncated,
+                &target,
+                Some(&shell("exit 4")),
+                &ExtractOptions::default(),
+                false,
+                true
+            ),
+            Err(ExtractError::CommandFailed { exit_code: 4 })
+        ));
+        Ok(())
+    }
+
+    #[test]
+    fn test_extract_marker_skips_same_snapshot() -> Result<()> {
+        let temp_dir = tempdir()?;
+        let home = temp_dir.path().join("home");
+        let archive_path = temp_dir.path().join("snapshot.tar.gz");
+        write_tar_gz(&archive_path, &[("data/state.db", b"state")])?;
+
+        extract_snapshot(
+            &archive_path,
+            &home,
+            None,
+            &ExtractOptions::default(),
+            false,
+            true,
+        )?;
+        assert!(home.join(EXTRACT_MARKER).exists());
+
+        // A rerun with the same snapshot leaves the home alone
+        fs::remove_file(home.join("data/state.db"))?;
+        extract_snapshot(
+            &archive_path,
+            &home,
+            Some(&shell("exit 1")),
+            &ExtractOptions::default(),
+            false,
+            true,
+        )?;
+        assert!(!home.join("data/state.db").exists());
+
+        // Unless skipping is turned off
+        extract_snapshot(
+            &archive_path,
+            &home,
+            None,
+            &ExtractOptions::default(),
+            false,
+            false,
+        )?;
+        assert_eq!(fs::read_to_string(home.join("data/state.db"))?, "state");
+        Ok(())
+    }
+
+    #[test]
+    fn test_extract_marker_mismatch_extracts_again() -> Result<()> {
+        let temp_dir = tempdir()?;
+        let home = temp_dir.path().join("home");
+        let archive_path = temp_dir.path().join("snapshot.tar.gz");
+        write_tar_gz(&archive_path, &[("data/state.db", b"old")])?;
+        extract_snapshot(
+            &archive_path,
+            &home,
+            None,
+            &ExtractOptions::default(),
+            false,
+            true,
+        )?;
+
+        // A different snapshot under the same name
+        write_tar_gz(&archive_path, &[("data/state.db", b"new")])?;
+        extract_snapshot(
+            &archive_path,
+            &home,
+            None,
+            &ExtractOptions::default(),
+            false,
+            true,
+        )?;
+        assert_eq!(fs::read_to_string(home.join("data/state.db"))?, "new");
+
+        // A failed extraction removes the marker, so the next run starts over
+        fs::write(&archive_path, b"not an archive")?;
+        assert!(extract_snapshot(
+            &archive_path,
+            &home,
+            None,
+            &ExtractOptions::default(),
+            false,
+            true
+        )
+        .is_err());
+        assert!(!home.join(EXTRACT_MARKER).exists());
+        Ok(())
+    }
+
+    #[test]
+    fn test_extract_marker_samples_both_ends() -> Result<()> {
+        let temp_dir = tempdir()?;
+        let path = temp_dir.path().join("snapshot.tar.lz4");
+        let mut data = vec![0u8; 3 * MARKER_SAMPLE_BYTES as usize];
+        fs::write(&path, &data)?;
+        let marker = ExtractMarker::for_archive(&path)?;
+        assert_eq!(marker.size, data.len() as u64);
+
+        *data.last_mut().unwrap() = 1;
+        fs::write(&path, &data)?;
+        assert_ne!(ExtractMarker::for_archive(&path)?, marker);
+        Ok(())
+    }
+
+    #[test]
+    fn test_archive_format_from_magic() -> Result<()> {
+        let zst = zstd::encode_all(&b"tar"[..], 0)?;
+        assert_eq!(ArchiveFormat::from_magic(&zst), Some(ArchiveFormat::TarZst));
+
+        let mut lz4 = lz4::EncoderBuilder::new().build(Vec::new())?;
+        io::Write::write_all(&mut lz4, b"tar")?;
+        let (lz4, result) = lz4.finish();
+        result?;
+        assert_eq!(ArchiveFormat::from_magic(&lz4), Some(ArchiveFormat::TarLz4));
+
+        assert_eq!(ArchiveFormat::from_magic(b"\x7fELF"), None);
+        assert_eq!(ArchiveFormat::from_magic(b""), None);
+        Ok(())
+    }
+
+    #[test]
+    fn test_extract_binary_extensionless_archive() -> Result<()> {
+        let temp_dir = tempdir()?;
+        // Named after an S3 key without any extension
+        let download = temp_dir.path().join("gaiad-v25");
+        write_tar_gz(&download, &[("bin/gaiad", b"#!/bin/sh\n")])?;
+
+        let workspace = temp_dir.path().join("workspace");
+        extract_binary(&download, &workspace, "bin/gaiad", false)?;
+        assert_eq!(fs::read(workspace.join("bin/gaiad"))?, b"#!/bin/sh\n");
+        Ok(())
+    }
+
+    #[test]
+    fn test_extract_binary_finds_nested_binary() -> Result<()> {
+        let temp_dir = tempdir()?;
+        let download = temp_dir.path().join("gaiad-v25.tar.gz");
+        write_tar_gz(
+            &download,
+            &[
+                ("./gaiad-v25/bin/gaiad", b"#!/bin/sh\necho v25\n"),
+                ("./gaiad-v25/README.md", b"readme"),
+            ],
+        )?;
+        let workspace = temp_dir.path().join("workspace");
+
+        // The older release's binary is not in this archive, so it is not a candidate
+        fs::create_dir_all(workspace.join("gaiad-v24/bin"))?;
+        fs::write(workspace.join("gaiad-v24/bin/gaiad"), b"old")?;
+
+        extract_binary(&download, &workspace, "bin/gaiad", true)?;
+        assert_eq!(
+            fs::read(workspace.join("bin/gaiad"))?,
+            b"#!/bin/sh\necho v25\n"
+        );
+        #[cfg(unix)]
+        assert_eq!(
+            fs::read_link(workspace.join("bin/gaiad"))?,
+            Path::new("../gaiad-v25/bin/gaiad")
+        );
+
+        // Extracting again replaces the link
+        extract_binary(&download, &workspace, "bin/gaiad", true)?;
+        assert!(workspace.join("bin/gaiad").exists());
+
+        // Without the option the archive is only extracted
+        let plain = temp_dir.path().join("plain");
+        extract_binary(&download, &plain, "bin/gaiad", false)?;
+        assert!(!plain.join("bin/gaiad").exists());
+
+        let err = extract_binary(&download, &workspace, "bin/simd", true).unwrap_err();
+        assert!(err.to_string().contains("No file named"), "{err}");
+        Ok(())
+    }
+
+    #[test]
+    fn test_extract_binary_raw_binary_regardless_of_name() -> Result<()> {
+        let temp_dir = tempdir()?;
+        let workspace = temp_dir.path().join("workspace");
+
+        for name in ["gaiad", "gaiad.bin"] {
+            let download = temp_dir.path().join(name);
+            fs::write(&download, b"\x7fELF raw binary")?;
+
+            extract_binary(&download, &workspace, "bin/gaiad", false)?;
+            assert_eq!(
+                fs::read(workspace.join("bin/gaiad"))?,
+                b"\x7fELF raw binary"
+            );
+            #[cfg(unix)]
+            {
+                use std::os::unix::fs::PermissionsExt;
+                let mode = fs::metadata(workspace.join("bin/gaiad"))?
+                    .permissions()
+                    .mode();
+                assert_eq!(mode & 0o777, 0o755);
+            }
+        }
+        Ok(())
+    }
 }
@@ -0,0 +1,124 @@
+use anyhow::{Context, Result};
+use serde::{Deserialize, Serialize};
+use std::fs;
+use std::io;
+use std::path::Path;
+use tracing::info;
+
+use crate::download::{self, DownloadOptions};
+
+/// File in `downloads_dir` recording the metadata of the last downloaded snapshot
+pub const METADATA_MARKER: &str = ".snapshot-metadata.json";
+
+/// What the JSON at `snapshot_metadata_url` says about the published snapshot
+///
+/// ```json
+/// {"height": 19876543, "timestamp": "2024-05-01T00:00:00Z", "size": 214748364800}
+/// ```
+///
+/// Every field is optional and other fields are ignored; the snapshot counts as unchanged only
+/// if all three match the last download.
+#[derive(Debug, Clone, PartialEq, Deserialize, Serialize)]
+pub struct SnapshotMetadata {
+    #[serde(default)]
+    pub height: Option<u64>,
+    /// RFC 3339 string or Unix time, compared as given
+    #[serde(default)]
+    pub timestamp: Option<serde_json::Value>,
+    /// Size in bytes
+    #[serde(default)]
+    pub size: Option<u64>,
+}
+
+impl SnapshotMetadata {
+    pub fn parse(content: &str) -> Result<Self> {
+        serde_json::from_str(content).context("Invalid snapshot metadata")
+    }
+
+    /// The metadata recorded by the last download, or `None` if there is none or it cannot be
+    /// parsed
+    pub fn read_marker(downloads_dir: &Path) -> Option<Self> {
+        let content = fs::read_to_string(downloads_dir.join(METADATA_MARKER)).ok()?;
+        Self::parse(&content).ok()
+    }
+
+    /// Record this metadata as that of the snapshot just downloaded
+    pub fn write_marker(&self, downloads_dir: &Path) -> Result<()> {
+        let path = downloads_dir.join(METADATA_MARKER);
+        fs::write(&path, serde_json::to_string_pretty(self)?)
+            .with_context(|| format!("Failed to write {}", path.display()))
+    }
+}
+
+/// Forget the last download's metadata, so the snapshot is downloaded again
+pub fn remove_marker(downloads_dir: &Path) -> Result<()> {
+    let path = downloads_dir.join(METADATA_MARKER);
+    match fs::remove_file(&path) {
+        Err(e) if e.kind() != io::ErrorKind::NotFound => {
+            Err(e).with_context(|| format!("Failed to remove {}", path.display()))
+        }
+        _ => Ok(()),
+    }
+}
+
+/// Download the metadata at `url` and parse it
+/// A copy left by an earlier run is replaced, since the metadata changes with each snapshot
+pub async fn fetch_metadata(
+    url: &str,
+    download_dir: &Path,
+    options: &DownloadOptions,
+) -> Result<SnapshotMetadata> {
+    let stale_path = download_dir.join(crate::utils::download_filename(url));
+    match fs::remove_file(&stale_path) {
+        Err(e) if e.kind() != io::ErrorKind::NotFound => {
+            return Err(e).with_context(|| format!("Failed to remove {}", stale_path.display()))
+        }
+        _ => {}
+    }
+
+    let path = download::download_with_mirrors(
+        &[url.to_string()],
+        download_dir,
+        "snapshot metadata",
+        options,
+    )
+    .await
+    .context("Failed to download snapshot metadata")?;
+    let content = fs::read_to_string(&path)
+        .with_context(|| format!("Failed to read snapshot metadata {}", path.display()))?;
+    let metadata = SnapshotMetadata::parse(&content)?;
+    info!(
+        "Published snapshot: height {:?}, timestamp {:?}, size {:?}",
+        metadata.height, metadata.timestamp, metadata.size
+    );
+    Ok(metadata)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::tempdir;
+
+    #[test]
+    fn test_metadata_marker_round_trip() -> Result<()> {
+        let temp_dir = tempdir()?;
+        assert_eq!(SnapshotMetadata::read_marker(temp_dir.path()), None);
+
+        let metadata = SnapshotMetadata::parse(
+            r#"{"height": 100, "timestamp": 1714521600, "size": 2048, "url": "ignored"}"#,
+        )?;
+        assert_eq!(metadata.height, Some(100));
+        metadata.write_marker(temp_dir.path())?;
+        assert_eq!(
+            SnapshotMetadata::read_marker(temp_dir.path()),
+            Some(metadata)
+        );
+
+        remove_marker(temp_dir.path())?;
+        remove_marker(temp_dir.path())?;
+        assert_eq!(SnapshotMetadata::read_marker(temp_dir.path()), None);
+
+        assert!(SnapshotMetadata::parse(r#"{"height": "tall"}"#).is_err());
+        Ok(())
+    }
+}
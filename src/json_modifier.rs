@@ -0,0 +1,171 @@
+use anyhow::{Context, Result};
+use serde_json::Value as JsonValue;
+use serde_yaml::Value as YamlValue;
+use std::fs;
+use std::path::PathBuf;
+use tracing::info;
+
+use crate::toml_modifier::DELETE_SENTINEL;
+
+/// Merges YAML-provided overrides into JSON files (e.g. genesis.json) under `home_dir/config`
+pub struct JsonModifier {
+    home_dir: PathBuf,
+    replace_arrays: bool,
+}
+
+impl JsonModifier {
+    pub fn new<P: Into<PathBuf>>(home_dir: P) -> Self {
+        Self {
+            home_dir: home_dir.into(),
+            replace_arrays: false,
+        }
+    }
+
+    /// Allow overrides to replace existing arrays wholesale (refused by default)
+    pub fn with_replace_arrays(mut self, replace_arrays: bool) -> Self {
+        self.replace_arrays = replace_arrays;
+        self
+    }
+
+    /// Apply YAML overrides to JSON files, given as (path relative to `home_dir/config`, overrides)
+    pub fn apply_overrides(&self, overrides: &[(&str, &YamlValue)]) -> Result<()> {
+        for (file_name, yaml) in overrides {
+            self.modify_file(file_name, yaml)
+                .with_context(|| format!("Failed to modify {file_name}"))?;
+        }
+        Ok(())
+    }
+
+    /// Modify a JSON file relative to `home_dir/config` with the provided YAML configuration
+    pub fn modify_file(&self, file_name: &str, yaml: &YamlValue) -> Result<()> {
+        let json_path = crate::utils::config_file_path(&self.home_dir, file_name)?;
+        info!("Modifying {} at {}", file_name, json_path.display());
+
+        let content = fs::read_to_string(&json_path)
+            .with_context(|| format!("Failed to read {} at {}", file_name, json_path.display()))?;
+        let mut document: JsonValue = serde_json::from_str(&content)
+            .with_context(|| format!("Failed to parse {file_name}"))?;
+
+        let overrides = serde_json::to_value(yaml)
+            .with_context(|| format!("Failed to convert YAML overrides for {file_name}"))?;
+        self.merge(&mut document, &overrides, "")?;
+
+        let mut output = serde_json::to_string_pretty(&document)
+            .with_context(|| format!("Failed to serialize {file_name}"))?;
+        output.push('\n');
+        fs::write(&json_path, output)
+            .with_context(|| format!("Failed to write {} at {}", file_name, json_path.display()))?;
+
+        info!("Successfully modified {}", file_name);
+        Ok(())
+    }
+
+    /// Recursively merge source into target, replacing scalars and descending into objects
+    fn merge(&self, target: &mut JsonValue, source: &JsonValue, path: &str) -> Result<()> {
+        match (target, source) {
+            (JsonValue::Object(target_map), JsonValue::Object(source_map)) => {
+                for (key, value) in source_map {
+                    let key_path = if path.is_empty() {
+                        key.clone()
+                    } else {
+                        format!("{path}.{key}")
+                    };
+
+                    if value.as_str() == Some(DELETE_SENTINEL) {
+                        target_map.shift_remove(key);
+                        continue;
+                    }
+
+                    match target_map.get_mut(key) {
+                        Some(existing) => self.merge(existing, value, &key_path)?,
+                        None => {
+                            target_map.insert(key.clone(), value.clone());
+                        }
+                    }
+                }
+            }
+            (JsonValue::Array(_), _) if !self.replace_arrays => {
+                anyhow::bail!(
+                    "Refusing to replace array at '{path}'; set json_replace_arrays: true to allow it"
+                );
+            }
+            (target, source) => *target = source.clone(),
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::tempdir;
+
+    const GENESIS: &str = r#"{
+  "chain_id": "testchain-1",
+  "app_state": {
+    "gov": {
+      "params": {
+        "voting_period": "172800s",
+        "quorum": "0.334"
+      }
+    },
+    "bank": {
+      "balances": [
+        { "address": "cosmos1abc", "coins": [] }
+      ]
+    }
+  }
+}"#;
+
+    fn write_genesis(home: &std::path::Path) -> Result<PathBuf> {
+        let config_dir = home.join("config");
+        fs::create_dir_all(&config_dir)?;
+        let genesis_path = config_dir.join("genesis.json");
+        fs::write(&genesis_path, GENESIS)?;
+        Ok(genesis_path)
+    }
+
+    #[test]
+    fn test_merge_nested_genesis_field() -> Result<()> {
+        let temp_dir = tempdir()?;
+        let genesis_path = write_genesis(temp_dir.path())?;
+
+        let yaml: YamlValue =
+            serde_yaml::from_str("app_state:\n  gov:\n    params:\n      voting_period: 60s\n")?;
+        JsonModifier::new(temp_dir.path()).modify_file("genesis.json", &yaml)?;
+
+        let genesis: JsonValue = serde_json::from_str(&fs::read_to_string(&genesis_path)?)?;
+        let params = &genesis["app_state"]["gov"]["params"];
+        assert_eq!(params["voting_period"], "60s");
+        assert_eq!(params["quorum"], "0.334");
+        assert_eq!(genesis["chain_id"], "testchain-1");
+
+        // Key order is preserved
+        let keys: Vec<&String> = genesis["app_state"].as_object().unwrap().keys().collect();
+        assert_eq!(keys, ["gov", "bank"]);
+        Ok(())
+    }
+
+    #[test]
+    fn test_array_replacement_requires_opt_in() -> Result<()> {
+        let temp_dir = tempdir()?;
+        let genesis_path = write_genesis(temp_dir.path())?;
+        let yaml: YamlValue = serde_yaml::from_str("app_state:\n  bank:\n    balances: []\n")?;
+
+        let err = JsonModifier::new(temp_dir.path())
+            .modify_file("genesis.json", &yaml)
+            .unwrap_err();
+        assert!(format!("{err:#}").contains("app_state.bank.balances"));
+        assert_eq!(fs::read_to_string(&genesis_path)?, GENESIS);
+
+        JsonModifier::new(temp_dir.path())
+            .with_replace_arrays(true)
+            .modify_file("genesis.json", &yaml)?;
+        let genesis: JsonValue = serde_json::from_str(&fs::read_to_string(&genesis_path)?)?;
+        assert_eq!(
+            genesis["app_state"]["bank"]["balances"],
+            serde_json::json!([])
+        );
+        Ok(())
+    }
+}
@@ -0,0 +1,74 @@
+//! Download and extract Cosmos node snapshots and binaries, then run the node.
+//!
+//! The `snapshot-downloader` binary is a thin wrapper around this library. A minimal
+//! flow that embeds the same steps in another program looks like this:
+//!
+//! ```no_run
+//! use snapshot_downloader::{download, extract, runner, utils, Config};
+//!
+//! # async fn run() -> anyhow::Result<()> {
+//! // Load configuration and create the working directories
+//! let config = Config::from_file("config.yaml")?;
+//! utils::create_directories(&config)?;
+//!
+//! // Download and unpack the node binary, then initialize the home directory
+//! let options = download::DownloadOptions::from_config(&config)?;
+//! let binary_path = download::download_with_mirrors(
+//!     &config.get_binary_sources(),
+//!     &config.downloads_dir,
+//!     "binary",
+//!     &options,
+//! )
+//! .await?;
+//! extract::extract_binary(
+//!     &binary_path,
+//!     &config.workspace_dir,
+//!     &config.binary_relative_path,
+//!     config.binary_search,
+//! )?;
+//! runner::run_binary_init(&config)?;
+//!
+//! // Download and extract the snapshot into the node home
+//! let snapshot_path = download::download_file(
+//!     &config.snapshot_url,
+//!     &config.downloads_dir,
+//!     "snapshot",
+//!     &options,
+//! )
+//! .await?;
+//! extract::extract_archive(&snapshot_path, &config.home_dir)?;
+//!
+//! // Start the node
+//! let (mut child, _post_start_shutdown) = runner::run_binary_start(&config)?;
+//! child.wait()?;
+//! # Ok(())
+//! # }
+//! ```
+//!
+//! Download and extraction failures are reported as [`DownloadError`] and [`ExtractError`], so
+//! callers can tell, for example, a missing file from a full disk or a checksum mismatch.
+
+pub mod config;
+pub mod download;
+pub mod download_record;
+pub mod error;
+pub mod extract;
+pub mod freshness;
+pub mod json_modifier;
+pub mod logging;
+pub mod manifest;
+pub mod metrics;
+pub mod node_log;
+pub mod progress;
+pub mod readiness;
+pub mod runner;
+pub mod signature;
+pub mod snapshot_index;
+pub mod systemd;
+pub mod toml_modifier;
+pub mod utils;
+
+pub use config::Config;
+pub use error::{DownloadError, ExtractError};
+pub use json_modifier::JsonModifier;
+pub use toml_modifier::TomlModifier;
@@ -0,0 +1,228 @@
+use clap::ValueEnum;
+use std::fmt;
+use tracing::field::{Field, Visit};
+use tracing::level_filters::LevelFilter;
+use tracing::{Event, Subscriber};
+use tracing_subscriber::filter::Targets;
+use tracing_subscriber::fmt::format::Writer;
+use tracing_subscriber::fmt::time::{FormatTime, SystemTime};
+use tracing_subscriber::fmt::writer::BoxMakeWriter;
+use tracing_subscriber::fmt::{FmtContext, FormatEvent, FormatFields};
+use tracing_subscriber::layer::SubscriberExt;
+use tracing_subscriber::registry::LookupSpan;
+use tracing_subscriber::util::SubscriberInitExt;
+use tracing_subscriber::Layer;
+
+/// Most verbose level of log lines to print
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, ValueEnum)]
+pub enum LogLevel {
+    Error,
+    Warn,
+    #[default]
+    Info,
+    Debug,
+    Trace,
+}
+
+impl From<LogLevel> for LevelFilter {
+    fn from(level: LogLevel) -> Self {
+        match level {
+            LogLevel::Error => LevelFilter::ERROR,
+            LogLevel::Warn => LevelFilter::WARN,
+            LogLevel::Info => LevelFilter::INFO,
+            LogLevel::Debug => LevelFilter::DEBUG,
+            LogLevel::Trace => LevelFilter::TRACE,
+        }
+    }
+}
+
+/// How log lines are written
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, ValueEnum)]
+pub enum LogFormat {
+    /// Human-readable lines
+    #[default]
+    Pretty,
+    /// One JSON object per line with `timestamp`, `level`, `target` and `fields`
+    Json,
+}
+
+/// Filter for log lines: `rust_log` (the `RUST_LOG` syntax `target=level,...`) when set, else
+/// `level` for every target
+/// An invalid `rust_log` is reported on stderr and ignored.
+pub fn log_filter(rust_log: Option<&str>, level: LogLevel) -> Targets {
+    if let Some(directives) = rust_log.map(str::trim).filter(|s| !s.is_empty()) {
+        match directives.parse() {
+            Ok(targets) => return targets,
+            Err(e) => eprintln!("Ignoring invalid RUST_LOG '{directives}': {e}"),
+        }
+    }
+    Targets::new().with_default(LevelFilter::from(level))
+}
+
+/// Subscriber writing log lines in `format` to `writer`, keeping those `filter` allows
+pub fn build_subscriber(
+    filter: Targets,
+    format: LogFormat,
+    writer: BoxMakeWriter,
+) -> impl Subscriber + Send + Sync + 'static {
+    let layer = match format {
+        LogFormat::Pretty => tracing_subscriber::fmt::layer().with_writer(writer).boxed(),
+        LogFormat::Json => tracing_subscriber::fmt::layer()
+            .event_format(JsonFormat)
+            .with_ansi(false)
+            .with_writer(writer)
+            .boxed(),
+    };
+    tracing_subscriber::registry().with(layer.with_filter(filter))
+}
+
+/// Install the process-wide subscriber, honouring `RUST_LOG` over `level`
+/// Logs go to stderr when `to_stderr` is set, e.g. to keep stdout free for JSON progress events.
+pub fn init(level: LogLevel, format: LogFormat, to_stderr: bool) {
+    let filter = log_filter(std::env::var("RUST_LOG").ok().as_deref(), level);
+    let writer = if to_stderr {
+        BoxMakeWriter::new(std::io::stderr)
+    } else {
+        BoxMakeWriter::new(std::io::stdout)
+    };
+    build_subscriber(filter, format, writer).init();
+}
+
+/// Formats each event as a single JSON object
+struct JsonFormat;
+
+impl<S, N> FormatEvent<S, N> for JsonFormat
+where
+    S: Subscriber + for<'a> LookupSpan<'a>,
+    N: for<'a> FormatFields<'a> + 'static,
+{
+    fn format_event(
+        &self,
+        _ctx: &FmtContext<'_, S, N>,
+        mut writer: Writer<'_>,
+        event: &Event<'_>,
+    ) -> fmt::Result {
+        let mut timestamp = String::new();
+        SystemTime.format_time(&mut Writer::new(&mut timestamp))?;
+        let mut fields = JsonFields::default();
+        event.record(&mut fields);
+
+        let metadata = event.metadata();
+        let line = serde_json::json!({
+            "timestamp": timestamp,
+            "level": metadata.level().as_str(),
+            "target": metadata.target(),
+            "fields": fields.0,
+        });
+        writeln!(writer, "{line}")
+    }
+}
+
+/// Collects the fields of an event into a JSON object
+#[derive(Default)]
+struct JsonFields(serde_json::Map<String, serde_json::Value>);
+
+impl Visit for JsonFields {
+    fn record_debug(&mut self, field: &Field, value: &dyn fmt::Debug) {
+        self.0
+            .insert(field.name().to_string(), format!("{value:?}").into());
+    }
+
+    fn record_str(&mut self, field: &Field, value: &str) {
+        self.0.insert(field.name().to_string(), value.into());
+    }
+
+    fn record_i64(&mut self, field: &Field, value: i64) {
+        self.0.insert(field.name().to_string(), value.into());
+    }
+
+    fn record_u64(&mut self, field: &Field, value: u64) {
+        self.0.insert(field.name().to_string(), value.into());
+    }
+
+    fn record_f64(&mut self, field: &Field, value: f64) {
+        self.0.insert(field.name().to_string(), value.into());
+    }
+
+    fn record_bool(&mut self, field: &Field, value: bool) {
+        self.0.insert(field.name().to_string(), value.into());
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Write;
+    use std::sync::{Arc, Mutex};
+
+    /// Log writer whose output the test can read back
+    #[derive(Clone, Default)]
+    struct Captured(Arc<Mutex<Vec<u8>>>);
+
+    impl Write for Captured {
+        fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+            self.0.lock().unwrap().write(buf)
+        }
+
+        fn flush(&mut self) -> std::io::Result<()> {
+            Ok(())
+        }
+    }
+
+    fn capture(filter: Targets, format: LogFormat) -> String {
+        let captured = Captured::default();
+        let writer = captured.clone();
+        let subscriber =
+            build_subscriber(filter, format, BoxMakeWriter::new(move || writer.clone()));
+        tracing::subscriber::with_default(subscriber, || {
+            tracing::debug!("debug line");
+            tracing::info!(height = 42, "info line");
+            tracing::warn!("warn line");
+        });
+        let output = captured.0.lock().unwrap().clone();
+        String::from_utf8(output).unwrap()
+    }
+
+    #[test]
+    fn test_log_level_is_applied() {
+        let output = capture(log_filter(None, LogLevel::Debug), LogFormat::Pretty);
+        assert!(output.contains("debug line"));
+        assert!(output.contains("info line"));
+
+        let output = capture(log_filter(None, LogLevel::Warn), LogFormat::Pretty);
+        assert!(!output.contains("debug line"));
+        assert!(!output.contains("info line"));
+        assert!(output.contains("warn line"));
+
+        // RUST_LOG takes precedence over the level flag, and an invalid value is ignored
+        let output = capture(log_filter(Some("debug"), LogLevel::Warn), LogFormat::Pretty);
+        assert!(output.contains("debug line"));
+        let output = capture(
+            log_filter(Some("other=trace"), LogLevel::Trace),
+            LogFormat::Pretty,
+        );
+        assert!(output.is_empty());
+        let output = capture(
+            log_filter(Some("snapshot=loud"), LogLevel::Info),
+            LogFormat::Pretty,
+        );
+        assert!(!output.contains("debug line"));
+        assert!(output.contains("info line"));
+    }
+
+    #[test]
+    fn test_json_log_format() -> anyhow::Result<()> {
+        let output = capture(log_filter(None, LogLevel::Info), LogFormat::Json);
+        let lines: Vec<serde_json::Value> = output
+            .lines()
+            .map(serde_json::from_str)
+            .collect::<Result<_, _>>()?;
+        assert_eq!(lines.len(), 2);
+        assert_eq!(lines[0]["level"], "INFO");
+        assert_eq!(lines[0]["fields"]["message"], "info line");
+        assert_eq!(lines[0]["fields"]["height"], 42);
+        assert_eq!(lines[1]["level"], "WARN");
+        assert!(lines[1]["timestamp"].is_string());
+        Ok(())
+    }
+}
@@ -1,12 +1,113 @@
 use anyhow::{Context, Result};
-use clap::Parser;
-use std::path::PathBuf;
+use clap::{Parser, Subcommand};
+use snapshot_downloader::download::{self, DownloadOptions};
+use snapshot_downloader::download_record::{self, DownloadKind};
+use snapshot_downloader::freshness::{self, SnapshotMetadata};
+use snapshot_downloader::logging::{self, LogFormat, LogLevel};
+use snapshot_downloader::progress::{self, ProgressMode};
+use snapshot_downloader::runner::ShellCommand;
+use snapshot_downloader::{
+    config, extract, manifest, metrics, runner, signature, snapshot_index, systemd, utils, Config,
+    JsonModifier, TomlModifier,
+};
+use std::path::{Path, PathBuf};
 use tokio::sync::oneshot;
 use tracing::{info, warn};
 
 #[derive(Parser)]
 #[command(author, version, about, long_about = None)]
 struct Args {
+    /// Path to the configuration file
+    #[arg(
+        long,
+        env = "SNAPSHOT_DOWNLOADER_CONFIG",
+        default_value = "config.yaml",
+        global = true
+    )]
+    config: PathBuf,
+
+    /// Profile to load from a config file with several chain profiles (default: its
+    /// `default_profile`)
+    #[arg(long, env = "SNAPSHOT_DOWNLOADER_PROFILE", global = true)]
+    profile: Option<String>,
+
+    /// Print the loaded configuration, with defaults and computed directories, as YAML and exit
+    #[arg(long, alias = "list-config", global = true)]
+    print_config: bool,
+
+    /// Write a commented example configuration documenting every option to PATH (default:
+    /// stdout) and exit
+    #[arg(
+        long,
+        value_name = "PATH",
+        num_args = 0..=1,
+        default_missing_value = "-"
+    )]
+    generate_config: Option<PathBuf>,
+
+    /// Progress output format: interactive bars or newline-delimited JSON on stdout, where each
+    /// extraction ends with a `{"phase":"extract","file":...,"files":...,"dirs":...,"bytes":...}` event
+    #[arg(long, value_enum, default_value_t = ProgressMode::Bar, global = true)]
+    progress: ProgressMode,
+
+    /// Most verbose log level to print; `RUST_LOG` (`target=level,...`) takes precedence when set
+    #[arg(long, value_enum, default_value_t = LogLevel::Info, global = true)]
+    log_level: LogLevel,
+
+    /// Only print warnings and errors (same as `--log-level warn`)
+    #[arg(long, short, global = true, conflicts_with_all = ["log_level", "verbose"])]
+    quiet: bool,
+
+    /// Also print debug output (same as `--log-level debug`)
+    #[arg(long, short, global = true, conflicts_with = "log_level")]
+    verbose: bool,
+
+    /// Log line format
+    #[arg(long, value_enum, default_value_t = LogFormat::Pretty, global = true)]
+    log_format: LogFormat,
+
+    /// Phase to run; every phase runs in order when omitted
+    #[command(subcommand)]
+    command: Option<Phase>,
+
+    /// Options for running every phase, also accepted without the `all` subcommand
+    #[command(flatten)]
+    all: AllArgs,
+}
+
+#[derive(Subcommand)]
+enum Phase {
+    /// Download the binary (verifying its signature if configured) and the snapshot
+    Download,
+    /// Extract the downloaded binary and snapshot
+    Extract,
+    /// Initialize the node home, then apply file overrides and the address book
+    Init,
+    /// Start the node and supervise it until it exits or a shutdown is requested
+    Run,
+    /// Run every phase in order (the default)
+    All(AllArgs),
+    /// Delete the downloaded snapshot and its part files to reclaim disk space
+    Prune(PruneArgs),
+    /// Check that a downloaded snapshot decompresses and reads to the end, without extracting it
+    Verify(VerifyArgs),
+}
+
+#[derive(clap::Args, Clone, Default)]
+struct VerifyArgs {
+    /// Snapshot file to check (default: where the configured snapshot was downloaded to)
+    path: Option<PathBuf>,
+}
+
+#[derive(clap::Args, Clone, Default)]
+struct PruneArgs {
+    /// Also delete the downloaded binary archive
+    #[arg(long)]
+    include_binary: bool,
+}
+
+#[derive(clap::Args, Clone, Default)]
+struct AllArgs {
     /// Skip downloading the snapshot (use existing snapshot file)
     #[arg(long)]
     skip_download_snapshot: bool,
@@ -26,277 +127,964 @@ struct Args {
     /// Skip execute the binary
     #[arg(long)]
     skip_execute_binary: bool,
+
+    /// Prepare the node home, print the start command and exit without starting the node
+    #[arg(long, alias = "no-start")]
+    skip_run: bool,
+
+    /// Print the planned downloads, extractions, file changes and commands without executing them
+    #[arg(long)]
+    dry_run: bool,
+
+    /// Download again from scratch instead of resuming or reusing existing files, and empty the
+    /// snapshot directories of the home (see `snapshot_dirs`) before extracting
+    #[arg(long)]
+    force: bool,
 }
 
-mod config;
-mod download;
-mod extract;
-mod runner;
-mod toml_modifier;
-mod utils;
+impl Args {
+    /// Log level chosen by `--log-level`, `--quiet` or `--verbose`
+    fn log_level(&self) -> LogLevel {
+        if self.quiet {
+            LogLevel::Warn
+        } else if self.verbose {
+            LogLevel::Debug
+        } else {
+            self.log_level
+        }
+    }
+}
 
-use config::Config;
-use toml_modifier::TomlModifier;
+impl AllArgs {
+    /// Combine the flags given before and after `all`
+    fn merge(&self, other: &AllArgs) -> AllArgs {
+        AllArgs {
+            skip_download_snapshot: self.skip_download_snapshot || other.skip_download_snapshot,
+            skip_extract_snapshot: self.skip_extract_snapshot || other.skip_extract_snapshot,
+            skip_binary_download: self.skip_binary_download || other.skip_binary_download,
+            skip_download_addrbook: self.skip_download_addrbook || other.skip_download_addrbook,
+            skip_execute_binary: self.skip_execute_binary || other.skip_execute_binary,
+            skip_run: self.skip_run || other.skip_run,
+            dry_run: self.dry_run || other.dry_run,
+            force: self.force || other.force,
+        }
+    }
+}
 
 /// Download snapshot (single file or multi-part)
-async fn download_snapshot(config: &Config) -> Result<PathBuf> {
+async fn download_snapshot(config: &Config, options: &DownloadOptions) -> Result<PathBuf> {
+    if let Some(ref prefix) = config.snapshot_s3_prefix {
+        let filename = config.get_snapshot_filename()?;
+        return download::download_s3_prefix_snapshot(
+            prefix,
+            &config.downloads_dir,
+            &filename,
+            options,
+            config.keep_parts,
+            config.part_concurrency,
+        )
+        .await
+        .context("Failed to download multi-part snapshot from S3 prefix");
+    }
+
+    if let Some(ref manifest_url) = config.snapshot_manifest_url {
+        let manifest =
+            manifest::fetch_manifest(manifest_url, &config.downloads_dir, options).await?;
+        let filename = config.get_snapshot_filename()?;
+        let path = download::download_multipart_snapshot(
+            &manifest.urls(),
+            &config.downloads_dir,
+            &filename,
+            options,
+            config.keep_parts,
+            config.part_concurrency,
+            config.aggregate_progress,
+        )
+        .await
+        .context("Failed to download multi-part snapshot from manifest")?;
+        manifest
+            .verify(&path)
+            .context("Snapshot does not match its manifest")?;
+        return Ok(path);
+    }
+
     let urls = config.get_snapshot_urls();
     if urls.is_empty() {
         return Err(anyhow::anyhow!("No snapshot URLs configured"));
     }
 
     if urls.len() == 1 {
-        let url = &urls[0];
-        if download::is_s3_url(url) {
-            download::download_s3_file(
-                url,
-                &config.downloads_dir,
-                "snapshot",
-                &config.download_retry,
-                config.s3.as_ref(),
-            )
-            .await
-            .context("Failed to download snapshot from S3")
-        } else {
-            download::download_file(
-                url,
-                &config.downloads_dir,
-                "snapshot",
-                &config.download_retry,
-            )
-            .await
-            .context("Failed to download snapshot")
-        }
+        download::download_with_mirrors(
+            &config.get_snapshot_sources(),
+            &config.downloads_dir,
+            "snapshot",
+            options,
+        )
+        .await
+        .context("Failed to download snapshot")
     } else {
         let filename = config.get_snapshot_filename()?;
         download::download_multipart_snapshot(
             &urls,
             &config.downloads_dir,
             &filename,
-            &config.download_retry,
+            options,
+            config.keep_parts,
+            config.part_concurrency,
+            config.aggregate_progress,
         )
         .await
         .context("Failed to download multi-part snapshot")
     }
 }
 
-#[tokio::main]
-async fn main() -> Result<()> {
-    // Parse command line arguments
-    let args = Args::parse();
+/// Log every action a real run would take, computing TOML changes without writing them
+fn log_dry_run_plan(config: &Config, args: &AllArgs) -> Result<()> {
+    info!("Dry run: nothing will be downloaded, extracted, modified or executed");
+    info!("Downloads directory: {}", config.downloads_dir.display());
+    info!("Workspace directory: {}", config.workspace_dir.display());
+    info!("Home directory: {}", config.home_dir.display());
 
-    // Initialize tracing
-    tracing_subscriber::fmt::init();
+    if args.skip_binary_download {
+        info!("Would skip binary download and extraction");
+    } else {
+        info!(
+            "Would download binary from {} into {}",
+            config.get_binary_sources().join(", "),
+            config.downloads_dir.display()
+        );
+        if let (Some(signature_url), Some(public_key)) =
+            (&config.binary_signature_url, &config.binary_public_key)
+        {
+            info!(
+                "Would verify binary signature from {} with key {}",
+                signature_url,
+                public_key.display()
+            );
+        }
+        info!(
+            "Would extract binary to {}",
+            config
+                .workspace_dir
+                .join(&config.binary_relative_path)
+                .display()
+        );
+        if let Some(ref cmd) = config.post_binary_extract_command {
+            info!("Would run post-binary-extract command: {}", cmd);
+        }
+        if let Some(ref version) = config.expected_binary_version {
+            info!("Would check the binary reports version {}", version);
+        }
+    }
 
-    // Load configuration
-    let config = Config::from_file("config.yaml").context("Failed to load configuration")?;
+    if config.init_before_snapshot {
+        log_init_plan(config);
+    }
 
-    // Create required directories
-    utils::create_directories(&config).context("Failed to create required directories")?;
+    if args.force {
+        info!("Would delete previous downloads and download them again from scratch");
+    }
 
-    // Handle binary download and extraction
-    if !args.skip_binary_download {
-        info!("Downloading and extracting binary...");
-        // Download binary
-        let binary_path = if download::is_s3_url(&config.binary_url) {
-            download::download_s3_file(
-                &config.binary_url,
-                &config.downloads_dir,
-                "binary",
-                &config.download_retry,
-                config.s3.as_ref(),
-            )
-            .await
-            .context("Failed to download binary from S3")?
+    // The snapshot's name is only known once its index has been fetched
+    let snapshot_path = match config.snapshot_index_url {
+        Some(_) => config.downloads_dir.join("<latest snapshot>"),
+        None => config.downloads_dir.join(config.get_snapshot_filename()?),
+    };
+    if args.skip_download_snapshot {
+        info!("Would use existing snapshot {}", snapshot_path.display());
+    } else {
+        let urls = config.get_snapshot_urls();
+        let sources = if let Some(ref prefix) = config.snapshot_s3_prefix {
+            vec![format!("every object under {prefix}")]
+        } else if let Some(ref manifest_url) = config.snapshot_manifest_url {
+            vec![format!("the parts listed in {manifest_url}")]
+        } else if let Some(ref index_url) = config.snapshot_index_url {
+            vec![format!("the latest snapshot listed at {index_url}")]
+        } else if urls.len() == 1 {
+            config.get_snapshot_sources()
         } else {
-            download::download_file(
-                &config.binary_url,
-                &config.downloads_dir,
-                "binary",
-                &config.download_retry,
-            )
-            .await
-            .context("Failed to download binary")?
+            urls
         };
+        info!(
+            "Would download snapshot from {} to {}",
+            sources.join(", "),
+            snapshot_path.display()
+        );
+        if let Some(ref metadata_url) = config.snapshot_metadata_url {
+            info!(
+                "  unless {} is unchanged since the last download",
+                metadata_url
+            );
+        }
+        if let Some(ref cmd) = config.post_snapshot_download_command {
+            info!("Would run post-snapshot-download command: {}", cmd);
+        }
+    }
 
-        // Extract binary
-        extract::extract_binary(
-            &binary_path,
-            &config.workspace_dir,
-            &config.binary_relative_path,
-        )
-        .context("Failed to extract binary")?;
-        info!("Binary download and extraction complete.");
+    if args.skip_extract_snapshot {
+        info!("Would skip snapshot extraction");
     } else {
-        info!("Skipping binary download and extraction");
+        if args.force || config.clean_target_before_extract {
+            info!(
+                "Would empty {} under {} (keeping priv_validator_state.json)",
+                config.snapshot_dirs.join(", "),
+                config.home_dir.display()
+            );
+        }
+        info!("Would extract snapshot into {}", config.home_dir.display());
+        if !config.extract_include.is_empty() {
+            info!(
+                "  only entries matching {}",
+                config.extract_include.join(", ")
+            );
+        }
+        if !config.extract_exclude.is_empty() {
+            info!(
+                "  except entries matching {}",
+                config.extract_exclude.join(", ")
+            );
+        }
+        if !config.decompress_members.is_empty() {
+            info!(
+                "Would decompress extracted files matching {}",
+                config.decompress_members.join(", ")
+            );
+        }
+        if let Some(ref cmd) = config.post_snapshot_extract_command {
+            info!("Would run post-snapshot-extract command: {}", cmd);
+        }
+        if config.cleanup_after_extract {
+            info!("Would delete the downloaded snapshot after extraction");
+        }
     }
 
-    // Run binary init
-    runner::run_binary_init(&config).context("Failed to initialize binary")?;
-
-    // Handle snapshot download
-    let snapshot_path = if args.skip_download_snapshot {
-        info!("Skipping snapshot download, using existing file");
-        let filename = config.get_snapshot_filename()?;
-        config.downloads_dir.join(filename)
-    } else {
-        let path = download_snapshot(&config).await?;
+    if !config.init_before_snapshot {
+        log_init_plan(config);
+    }
 
-        // Execute post-snapshot-download command if configured
-        if let Some(ref cmd) = config.post_snapshot_download_command {
-            if let Err(e) = runner::execute_post_snapshot_download_command(cmd) {
-                warn!(
-                    "Post-snapshot-download command failed after snapshot download: {}",
-                    e
-                );
+    let toml_modifier = TomlModifier::new(&config.home_dir)
+        .with_array_merge(config.array_merge, config.array_merge_overrides.clone());
+    for (file_name, yaml) in config.get_toml_overrides() {
+        match toml_modifier.diff_file(file_name, yaml) {
+            Ok(changes) if changes.is_empty() => info!("Would leave {} unchanged", file_name),
+            Ok(changes) => {
+                info!("Would modify {}:", file_name);
+                for change in changes {
+                    info!("  {}", change);
+                }
             }
+            Err(e) => warn!("Cannot compute changes to {}: {:#}", file_name, e),
+        }
+    }
+    for (file_name, _) in config.get_json_overrides() {
+        info!("Would merge overrides into {}", file_name);
+    }
+
+    if config.addrbook_url.is_some() && !args.skip_download_addrbook {
+        info!(
+            "Would download addrbook from {} to {}",
+            config.get_addrbook_sources().join(", "),
+            config.get_addrbook_target_path().display()
+        );
+    }
+
+    if config.chown_user.is_some() || config.chown_group.is_some() {
+        info!(
+            "Would change the owner of everything under {} to {}:{}",
+            config.home_dir.display(),
+            config.chown_user.as_deref().unwrap_or("-"),
+            config.chown_group.as_deref().unwrap_or("-")
+        );
+    }
+    if let Some(ref mode) = config.dir_mode {
+        info!(
+            "Would set directories under {} to mode {}",
+            config.home_dir.display(),
+            mode
+        );
+    }
+    if let Some(ref mode) = config.file_mode {
+        info!(
+            "Would set files under {} to mode {}",
+            config.home_dir.display(),
+            mode
+        );
+    }
+
+    if args.skip_run {
+        info!(
+            "Would print the start command without starting the node: {}",
+            runner::start_command_line(config)
+        );
+    } else if args.skip_execute_binary {
+        info!("Would skip binary execution");
+    } else {
+        if let Some(ref cmd) = config.pre_start_command {
+            info!("Would run pre-start command: {}", cmd);
         }
+        info!("Would run: {}", runner::start_command_line(config));
+    }
 
-        path
-    };
+    Ok(())
+}
 
-    // Extract snapshot and run post-snapshot command if configured
-    if args.skip_extract_snapshot {
-        info!("Skipping snapshot extraction");
+fn log_init_plan(config: &Config) {
+    if let Some(reason) = runner::init_skip_reason(config) {
+        info!("Would skip init, {}", reason);
     } else {
-        extract::extract_snapshot(
-            &snapshot_path,
-            &config.home_dir,
-            config.post_snapshot_extract_command.as_deref(),
-        )
-        .context("Failed to extract snapshot")?;
+        info!("Would run: {}", runner::init_command_line(config));
     }
+}
 
-    info!("Snapshot downloader completed successfully!");
+/// Wait for Ctrl+C or, on Unix, SIGTERM (as sent by systemd or Kubernetes), returning the signal name
+async fn wait_for_shutdown_signal() -> std::io::Result<&'static str> {
+    #[cfg(unix)]
+    {
+        use tokio::signal::unix::{signal, SignalKind};
 
-    // Helper function to check if a YAML value is a non-empty mapping (valid for TOML modification)
-    let is_valid_yaml_config = |yaml_opt: &Option<serde_yaml::Value>| -> bool {
-        match yaml_opt {
-            Some(serde_yaml::Value::Mapping(map)) => !map.is_empty(),
-            _ => false,
+        let mut sigterm = signal(SignalKind::terminate())?;
+        tokio::select! {
+            result = tokio::signal::ctrl_c() => result.map(|_| "Ctrl+C"),
+            _ = sigterm.recv() => Ok("SIGTERM"),
         }
+    }
+
+    #[cfg(not(unix))]
+    {
+        tokio::signal::ctrl_c().await.map(|_| "Ctrl+C")
+    }
+}
+
+/// Download the binary archive, verifying its detached signature when configured
+async fn download_binary(config: &Config, options: &DownloadOptions) -> Result<PathBuf> {
+    info!("Downloading binary...");
+    let binary_path = download::download_with_mirrors(
+        &config.get_binary_sources(),
+        &config.downloads_dir,
+        "binary",
+        options,
+    )
+    .await
+    .context("Failed to download binary")?;
+    download_record::record_download(config, DownloadKind::Binary, &binary_path)?;
+
+    // Verify the release signature before anything from the archive is used
+    if let (Some(signature_url), Some(public_key)) =
+        (&config.binary_signature_url, &config.binary_public_key)
+    {
+        let signature_path = download::download_with_mirrors(
+            std::slice::from_ref(signature_url),
+            &config.downloads_dir,
+            "binary signature",
+            options,
+        )
+        .await
+        .context("Failed to download binary signature")?;
+        download_record::record_download(config, DownloadKind::BinarySignature, &signature_path)?;
+        signature::verify_detached_signature(&binary_path, &signature_path, public_key)
+            .context("Binary signature verification failed")?;
+    }
+
+    Ok(binary_path)
+}
+
+/// Path a previous `download` left the binary archive at
+fn downloaded_binary_path(config: &Config) -> PathBuf {
+    download_record::downloaded_path(config, DownloadKind::Binary).unwrap_or_else(|| {
+        config
+            .downloads_dir
+            .join(utils::download_filename(&config.binary_url))
+    })
+}
+
+/// Extract the binary archive into the workspace
+fn extract_binary(config: &Config, binary_path: &Path) -> Result<()> {
+    extract::extract_binary(
+        binary_path,
+        &config.workspace_dir,
+        &config.binary_relative_path,
+        config.binary_search,
+    )
+    .context("Failed to extract binary")?;
+
+    if let Some(ref cmd) = config.post_binary_extract_command {
+        let working_dir = config.command_working_dirs.post_binary_extract.as_deref();
+        runner::execute_post_binary_extract_command(&ShellCommand::new(config, cmd, working_dir))
+            .context("Post-binary-extract command failed")?;
+    }
+    runner::verify_binary(config).context("Extracted binary failed verification")?;
+
+    info!("Binary download and extraction complete.");
+    Ok(())
+}
+
+/// Download the snapshot and run the post-snapshot-download command if configured
+/// Returns `None` without downloading if `snapshot_metadata_url` reports the snapshot unchanged
+/// since the last download
+async fn download_snapshot_phase(
+    config: &Config,
+    options: &DownloadOptions,
+) -> Result<Option<PathBuf>> {
+    let metadata = match &config.snapshot_metadata_url {
+        Some(url) => Some(freshness::fetch_metadata(url, &config.downloads_dir, options).await?),
+        None => None,
     };
+    if let Some(metadata) = &metadata {
+        match SnapshotMetadata::read_marker(&config.downloads_dir) {
+            Some(last) if last == *metadata => {
+                info!("Snapshot is unchanged since the last download, skipping its download");
+                return Ok(None);
+            }
+            // A previous snapshot left in place would be resumed or reused if its size matched
+            Some(_) => {
+                info!("A newer snapshot is published, discarding the previous download");
+                utils::remove_downloads(config, true, false, false)
+                    .context("Failed to remove the previous snapshot")?;
+            }
+            None => {}
+        }
+    }
 
-    // Only apply TOML modifications if there are valid (non-empty mapping) configurations
-    let should_modify_app = is_valid_yaml_config(&config.app_yaml);
-    let should_modify_config = is_valid_yaml_config(&config.config_yaml);
+    let path = download_snapshot(config, options).await?;
+    download_record::record_download(config, DownloadKind::Snapshot, &path)?;
+    if let Some(metadata) = &metadata {
+        metadata.write_marker(&config.downloads_dir)?;
+    }
+
+    // Execute post-snapshot-download command if configured
+    if let Some(ref cmd) = config.post_snapshot_download_command {
+        let working_dir = config
+            .command_working_dirs
+            .post_snapshot_download
+            .as_deref();
+        if let Err(e) = runner::execute_post_snapshot_download_command(&ShellCommand::new(
+            config,
+            cmd,
+            working_dir,
+        )) {
+            warn!(
+                "Post-snapshot-download command failed after snapshot download: {}",
+                e
+            );
+        }
+    }
+
+    Ok(Some(path))
+}
+
+/// Files fetched by `download_all`; `None` for each download that was skipped
+struct Downloads {
+    binary: Option<PathBuf>,
+    snapshot: Option<PathBuf>,
+    /// The snapshot was not downloaded because it is unchanged since the last download
+    snapshot_unchanged: bool,
+    addrbook: Option<PathBuf>,
+}
 
-    if should_modify_app || should_modify_config {
+/// Download the binary, snapshot and address book concurrently, skipping those not wanted
+/// The first download to fail cancels the others
+async fn download_all(
+    config: &Config,
+    binary: bool,
+    snapshot: bool,
+    addrbook: bool,
+) -> Result<Downloads> {
+    let mut names = Vec::new();
+    if binary {
+        names.push("binary");
+    }
+    if snapshot {
+        names.push("snapshot");
+    }
+    if addrbook {
+        names.push("address book");
+    }
+    if names.is_empty() {
+        return Ok(Downloads {
+            binary: None,
+            snapshot: None,
+            snapshot_unchanged: false,
+            addrbook: None,
+        });
+    }
+    systemd::notify_status(&format!("Downloading {}", names.join(", ")));
+    metrics::set_phase("download");
+
+    let options = DownloadOptions::from_config(config)?;
+    let _progress = progress::SharedProgress::start();
+    let (binary, snapshot, addrbook) = tokio::try_join!(
+        async {
+            if binary {
+                download_binary(config, &options).await.map(Some)
+            } else {
+                Ok(None)
+            }
+        },
+        async {
+            if snapshot {
+                download_snapshot_phase(config, &options).await.map(Some)
+            } else {
+                Ok(None)
+            }
+        },
+        async {
+            if addrbook {
+                download_addrbook(config, &options).await
+            } else {
+                Ok(None)
+            }
+        },
+    )?;
+
+    Ok(Downloads {
+        binary,
+        snapshot_unchanged: matches!(snapshot, Some(None)),
+        snapshot: snapshot.flatten(),
+        addrbook,
+    })
+}
+
+/// Path a previous download left the snapshot at, or the path it will be downloaded to
+fn snapshot_path(config: &Config) -> Result<PathBuf> {
+    match download_record::downloaded_path(config, DownloadKind::Snapshot) {
+        Some(path) => Ok(path),
+        None => Ok(config.downloads_dir.join(config.get_snapshot_filename()?)),
+    }
+}
+
+/// Extract the snapshot into the node home and run the post-snapshot-extract command if configured
+/// With `force` the snapshot is extracted even if the home's marker says it already was
+/// With `force` or `clean_target_before_extract` the snapshot directories are emptied first,
+/// unless the extraction is skipped
+fn extract_snapshot(config: &Config, snapshot_path: &Path, force: bool) -> Result<()> {
+    systemd::notify_status("Extracting snapshot");
+    metrics::set_phase("extract_snapshot");
+    let skip_if_complete = config.skip_extract_if_complete && !force;
+    if force
+        || (config.clean_target_before_extract
+            && !(skip_if_complete && extract::is_extracted(snapshot_path, &config.home_dir)))
+    {
+        utils::clear_snapshot_dirs(config).context("Failed to clear snapshot directories")?;
+    }
+    let post_command = config.post_snapshot_extract_command.as_deref().map(|cmd| {
+        let working_dir = config.command_working_dirs.post_snapshot_extract.as_deref();
+        ShellCommand::new(config, cmd, working_dir)
+    });
+    extract::extract_snapshot(
+        snapshot_path,
+        &config.home_dir,
+        post_command.as_ref(),
+        &config.get_extract_options()?,
+        config.atomic_extract,
+        skip_if_complete,
+    )
+    .context("Failed to extract snapshot")?;
+
+    if config.cleanup_after_extract {
+        utils::prune_downloads(config, false).context("Failed to clean up downloads")?;
+    }
+    Ok(())
+}
+
+/// Apply the TOML and JSON overrides, install the downloaded address book, if any, and fix the
+/// ownership and permissions of the home directory
+async fn configure_node(config: &Config, addrbook_path: Option<&Path>) -> Result<()> {
+    // Only apply TOML modifications if there are valid (non-empty mapping) configurations
+    let toml_overrides = config.get_toml_overrides();
+    if !toml_overrides.is_empty() {
         info!("Applying configuration changes to TOML files");
-        let toml_modifier = TomlModifier::new(&config.home_dir);
+        let toml_modifier = TomlModifier::new(&config.home_dir)
+            .with_backup(config.backup_toml)
+            .with_array_merge(config.array_merge, config.array_merge_overrides.clone());
         toml_modifier
-            .apply_config_changes(
-                if should_modify_app {
-                    config.app_yaml.as_ref()
-                } else {
-                    None
-                },
-                if should_modify_config {
-                    config.config_yaml.as_ref()
-                } else {
-                    None
-                },
-            )
+            .apply_overrides(&toml_overrides)
             .context("Failed to apply TOML configuration changes")?;
     }
 
-    // Download addrbook if configured
-    if let Some(addrbook_url) = &config.addrbook_url {
-        if args.skip_download_addrbook {
-            info!("Skipping address book download");
-        } else {
-            info!("Downloading addrbook from {}", addrbook_url);
-            let downloaded_addrbook_path = if download::is_s3_url(addrbook_url) {
-                download::download_s3_file(
-                    addrbook_url,
-                    &config.downloads_dir,
-                    "addrbook",
-                    &config.download_retry,
-                    config.s3.as_ref(),
-                )
-                .await
-                .context("Failed to download addrbook from S3")?
-            } else {
-                download::download_file(
-                    addrbook_url,
-                    &config.downloads_dir,
-                    "addrbook",
-                    &config.download_retry,
-                )
-                .await
-                .context("Failed to download addrbook")?
-            };
+    let json_overrides = config.get_json_overrides();
+    if !json_overrides.is_empty() {
+        info!("Applying configuration changes to JSON files");
+        JsonModifier::new(&config.home_dir)
+            .with_replace_arrays(config.json_replace_arrays)
+            .apply_overrides(&json_overrides)
+            .context("Failed to apply JSON configuration changes")?;
+    }
 
-            let target_addrbook_dir = config.home_dir.join("config");
-            let target_addrbook_path = target_addrbook_dir.join("addrbook.json"); // Assuming standard name
+    if let Some(addrbook_path) = addrbook_path {
+        install_addrbook(config, addrbook_path).await?;
+    }
 
-            // Ensure target directory exists
-            tokio::fs::create_dir_all(&target_addrbook_dir)
-                .await
-                .with_context(|| {
-                    format!(
-                        "Failed to create directory: {}",
-                        target_addrbook_dir.display()
-                    )
-                })?;
-
-            // Copy the downloaded file
-            tokio::fs::copy(&downloaded_addrbook_path, &target_addrbook_path)
-                .await
-                .with_context(|| {
-                    format!(
-                        "Failed to copy addrbook from {} to {}",
-                        downloaded_addrbook_path.display(),
-                        target_addrbook_path.display()
-                    )
-                })?;
-
-            // Remove the original downloaded file
-            tokio::fs::remove_file(&downloaded_addrbook_path)
-                .await
-                .with_context(|| {
-                    format!(
-                        "Failed to remove original addrbook file {}",
-                        downloaded_addrbook_path.display()
-                    )
-                })?;
+    // Last, so files written by init and the overrides are covered too
+    utils::fix_home_permissions(config).context("Failed to fix home directory permissions")?;
+    Ok(())
+}
+
+/// Download the address book if one is configured
+async fn download_addrbook(config: &Config, options: &DownloadOptions) -> Result<Option<PathBuf>> {
+    let Some(addrbook_url) = &config.addrbook_url else {
+        return Ok(None);
+    };
+    info!("Downloading addrbook from {}", addrbook_url);
+    let path = download::download_with_mirrors(
+        &config.get_addrbook_sources(),
+        &config.downloads_dir,
+        "addrbook",
+        options,
+    )
+    .await
+    .context("Failed to download addrbook")?;
+    download_record::record_download(config, DownloadKind::Addrbook, &path)?;
+    Ok(Some(path))
+}
+
+/// Check that a downloaded address book is a JSON object with an `addrs` list, so a truncated
+/// file or an HTML error page does not replace a working one
+async fn validate_addrbook(path: &Path) -> Result<()> {
+    let content = tokio::fs::read(path)
+        .await
+        .with_context(|| format!("Failed to read addrbook {}", path.display()))?;
+    let addrbook: serde_json::Value = serde_json::from_slice(&content)
+        .with_context(|| format!("Addrbook {} is not valid JSON", path.display()))?;
+    match addrbook.get("addrs") {
+        Some(serde_json::Value::Array(_) | serde_json::Value::Null) => Ok(()),
+        _ => Err(anyhow::anyhow!(
+            "Addrbook {} has no top-level \"addrs\" list",
+            path.display()
+        )),
+    }
+}
+
+/// Place a downloaded address book at its target path (see `addrbook_target_path`)
+/// An invalid download is deleted and any existing address book is left in place
+async fn install_addrbook(config: &Config, downloaded_addrbook_path: &Path) -> Result<()> {
+    if let Err(e) = validate_addrbook(downloaded_addrbook_path).await {
+        if let Err(remove_error) = tokio::fs::remove_file(downloaded_addrbook_path).await {
+            warn!(
+                "Failed to remove invalid addrbook {}: {}",
+                downloaded_addrbook_path.display(),
+                remove_error
+            );
+        }
+        return Err(e.context("Refusing to install the downloaded addrbook"));
+    }
+
+    let target_addrbook_path = config.get_addrbook_target_path();
+    let target_addrbook_dir = target_addrbook_path.parent().unwrap_or(&config.home_dir);
+
+    // Ensure target directory exists
+    tokio::fs::create_dir_all(target_addrbook_dir)
+        .await
+        .with_context(|| {
+            format!(
+                "Failed to create directory: {}",
+                target_addrbook_dir.display()
+            )
+        })?;
 
+    // Copying a file onto itself and then removing the "original" would lose it
+    if let (Ok(downloaded), Ok(target)) = (
+        tokio::fs::canonicalize(downloaded_addrbook_path).await,
+        tokio::fs::canonicalize(&target_addrbook_path).await,
+    ) {
+        if downloaded == target {
             info!(
-                "Addrbook downloaded and placed at {}", // Changed "moved to" -> "placed at" for clarity
+                "Addrbook was downloaded to its target {}",
                 target_addrbook_path.display()
             );
+            return Ok(());
         }
     }
 
+    // Copy the downloaded file
+    tokio::fs::copy(downloaded_addrbook_path, &target_addrbook_path)
+        .await
+        .with_context(|| {
+            format!(
+                "Failed to copy addrbook from {} to {}",
+                downloaded_addrbook_path.display(),
+                target_addrbook_path.display()
+            )
+        })?;
+
+    // Remove the original downloaded file
+    tokio::fs::remove_file(downloaded_addrbook_path)
+        .await
+        .with_context(|| {
+            format!(
+                "Failed to remove original addrbook file {}",
+                downloaded_addrbook_path.display()
+            )
+        })?;
+
+    info!(
+        "Addrbook downloaded and placed at {}", // Changed "moved to" -> "placed at" for clarity
+        target_addrbook_path.display()
+    );
+    Ok(())
+}
+
+/// Run every phase in order, honouring the skip flags, returning the exit code
+async fn run_all(config: &Config, args: &AllArgs) -> Result<i32> {
+    if config.addrbook_url.is_some() && args.skip_download_addrbook {
+        info!("Skipping address book download");
+    }
+    if args.force {
+        info!("Discarding previous downloads (--force)");
+        utils::remove_downloads(
+            config,
+            !args.skip_download_snapshot,
+            !args.skip_binary_download,
+            !args.skip_download_addrbook,
+        )
+        .context("Failed to remove previous downloads")?;
+        if !args.skip_download_snapshot {
+            freshness::remove_marker(&config.downloads_dir)?;
+        }
+    }
+    let downloads = download_all(
+        config,
+        !args.skip_binary_download,
+        !args.skip_download_snapshot,
+        !args.skip_download_addrbook,
+    )
+    .await?;
+
+    // Handle binary extraction
+    match &downloads.binary {
+        Some(binary_path) => extract_binary(config, binary_path)?,
+        None => info!("Skipping binary download and extraction"),
+    }
+
+    if config.init_before_snapshot {
+        runner::run_binary_init(config).context("Failed to initialize binary")?;
+    }
+
+    let snapshot_path = match downloads.snapshot {
+        Some(path) => path,
+        None => {
+            if !downloads.snapshot_unchanged {
+                info!("Skipping snapshot download, using existing file");
+            }
+            snapshot_path(config)?
+        }
+    };
+
+    // Extract snapshot and run post-snapshot command if configured
+    if args.skip_extract_snapshot {
+        info!("Skipping snapshot extraction");
+    } else if downloads.snapshot_unchanged && !snapshot_path.exists() {
+        // Already extracted by the run that downloaded it, then cleaned up
+        info!("Skipping snapshot extraction, the unchanged snapshot was extracted before");
+    } else {
+        extract_snapshot(config, &snapshot_path, args.force)?;
+    }
+
+    // Initializing after extraction lets a genesis from the snapshot skip init (see init_skip_if)
+    if !config.init_before_snapshot {
+        runner::run_binary_init(config).context("Failed to initialize binary")?;
+    }
+
+    info!("Snapshot downloader completed successfully!");
+
+    configure_node(config, downloads.addrbook.as_deref()).await?;
+    start_node(config, args).await
+}
+
+/// Point `snapshot_url` at the snapshot picked from `snapshot_index_url`, if one is set
+async fn resolve_snapshot_index(config: &mut Config) -> Result<()> {
+    let Some(ref index_url) = config.snapshot_index_url else {
+        return Ok(());
+    };
+    let options = DownloadOptions::from_config(config)?;
+    config.snapshot_url = snapshot_index::resolve_latest(
+        index_url,
+        config.snapshot_index_selector,
+        &config.get_snapshot_index_pattern()?,
+        &config.downloads_dir,
+        &options,
+    )
+    .await?;
+    Ok(())
+}
+
+/// Run a single phase; each one can be repeated safely
+/// Returns the exit code, which is the node's when the node ran
+async fn run_phase(config: &Config, phase: &Phase) -> Result<i32> {
+    match phase {
+        Phase::Download => {
+            download_all(config, true, true, false).await?;
+        }
+        Phase::Extract => {
+            extract_binary(config, &downloaded_binary_path(config))?;
+            extract_snapshot(config, &snapshot_path(config)?, false)?;
+            utils::fix_home_permissions(config)
+                .context("Failed to fix home directory permissions")?;
+        }
+        Phase::Init => {
+            runner::run_binary_init(config).context("Failed to initialize binary")?;
+            let options = DownloadOptions::from_config(config)?;
+            let addrbook_path = download_addrbook(config, &options).await?;
+            configure_node(config, addrbook_path.as_deref()).await?;
+        }
+        Phase::Run => return start_node(config, &AllArgs::default()).await,
+        Phase::All(args) => return run_all(config, args).await,
+        Phase::Prune(args) => {
+            utils::prune_downloads(config, args.include_binary)?;
+        }
+        Phase::Verify(args) => {
+            let path = match &args.path {
+                Some(path) => path.clone(),
+                None => snapshot_path(config)?,
+            };
+            let summary = extract::verify_archive(&path, &config.get_extract_options()?)
+                .with_context(|| format!("Snapshot {} is corrupt or truncated", path.display()))?;
+            match summary.entries {
+                Some(entries) => info!(
+                    "Snapshot {} is intact: {} entries, {} bytes uncompressed",
+                    path.display(),
+                    entries,
+                    summary.bytes
+                ),
+                None => info!(
+                    "Snapshot {} is intact: a single {} byte file",
+                    path.display(),
+                    summary.bytes
+                ),
+            }
+        }
+    }
+    Ok(0)
+}
+
+/// Write the example configuration to `path` ("-" for stdout), never overwriting a file
+fn generate_config(path: &Path) -> Result<()> {
+    if path == Path::new("-") {
+        print!("{}", config::CONFIG_TEMPLATE);
+        return Ok(());
+    }
+    let mut file = std::fs::OpenOptions::new()
+        .write(true)
+        .create_new(true)
+        .open(path)
+        .with_context(|| format!("Failed to create {}", path.display()))?;
+    std::io::Write::write_all(&mut file, config::CONFIG_TEMPLATE.as_bytes())
+        .with_context(|| format!("Failed to write {}", path.display()))?;
+    info!("Wrote example configuration to {}", path.display());
+    Ok(())
+}
+
+#[tokio::main]
+async fn main() -> Result<()> {
+    // Parse command line arguments
+    let args = Args::parse();
+
+    // Initialize tracing, keeping stdout free for JSON progress events when requested
+    progress::set_mode(args.progress);
+    logging::init(
+        args.log_level(),
+        args.log_format,
+        args.progress == ProgressMode::Json,
+    );
+
+    if let Some(path) = &args.generate_config {
+        return generate_config(path);
+    }
+
+    // Load configuration
+    let mut config = Config::from_file_with_profile(&args.config, args.profile.as_deref())
+        .context("Failed to load configuration")?;
+    if args.print_config {
+        print!("{}", config.to_redacted_yaml()?);
+        return Ok(());
+    }
+
+    // Without a subcommand every phase runs, as before subcommands existed
+    let phase = match args.command {
+        Some(Phase::All(ref all)) => Phase::All(args.all.merge(all)),
+        Some(phase) => phase,
+        None => Phase::All(args.all.clone()),
+    };
+
+    if let Phase::All(ref all) = phase {
+        if all.dry_run {
+            return log_dry_run_plan(&config, all);
+        }
+    }
+
+    systemd::set_enabled(config.systemd_notify);
+
+    // Serve metrics for the whole run, including downloads and extraction
+    let metrics_task = match config.metrics_addr {
+        Some(addr) => {
+            let listener = tokio::net::TcpListener::bind(addr)
+                .await
+                .with_context(|| format!("Failed to bind metrics address {addr}"))?;
+            Some(tokio::spawn(metrics::serve(listener)))
+        }
+        None => None,
+    };
+
+    // Create required directories
+    utils::create_directories(&config).context("Failed to create required directories")?;
+
+    let uses_snapshot = match &phase {
+        Phase::Download | Phase::Extract | Phase::All(_) | Phase::Prune(_) => true,
+        Phase::Verify(args) => args.path.is_none(),
+        Phase::Init | Phase::Run => false,
+    };
+    if uses_snapshot {
+        resolve_snapshot_index(&mut config).await?;
+    }
+
+    let result = run_phase(&config, &phase).await;
+
+    // Clean up the metrics server
+    if let Some(metrics_task) = metrics_task {
+        metrics_task.abort();
+    }
+
+    // Exit with the node's code so process managers see a crash as a failure
+    match result? {
+        0 => Ok(()),
+        code => std::process::exit(code),
+    }
+}
+
+/// Run the pre-start command and supervise the node until it exits or a shutdown is requested
+/// With `--skip-run` the start command is printed instead and no process or signal handler is set up
+async fn start_node(config: &Config, args: &AllArgs) -> Result<i32> {
+    if args.skip_run {
+        // Hand the prepared home over to another process manager
+        info!("Node prepared, not starting it (--skip-run)");
+        println!("{}", runner::start_command_line(config));
+        return Ok(0);
+    }
+
     if args.skip_execute_binary {
         info!("Skipping binary execution");
-        return Ok(());
+        return Ok(0);
     }
 
     // Execute pre-start command if configured
     if let Some(ref cmd) = config.pre_start_command {
-        if let Err(e) = runner::execute_pre_start_command(cmd) {
+        let working_dir = config.command_working_dirs.pre_start.as_deref();
+        if let Err(e) =
+            runner::execute_pre_start_command(&ShellCommand::new(config, cmd, working_dir))
+        {
             warn!("Pre-start command failed before binary start: {}", e);
         }
     }
 
-    // Start the binary and get the process handle
-    let (binary_process, post_start_shutdown_rx) =
-        runner::run_binary_start(&config).context("Failed to start binary")?;
-
-    // Store the process ID for later use
-    let process_id = binary_process.id();
-
-    // Set up channels to communicate between tasks
+    // Set up a channel to communicate between tasks
     let (shutdown_tx, shutdown_rx) = oneshot::channel();
-    let (exit_tx, exit_rx) = oneshot::channel();
 
     // Spawn a task to handle termination signals in a cross-platform way
     let signal_task = tokio::spawn(async move {
-        // Wait for ctrl-c signal
-        match tokio::signal::ctrl_c().await {
-            Ok(_) => {
-                info!("Received Ctrl+C, initiating graceful shutdown...");
+        // Wait for Ctrl+C or SIGTERM
+        match wait_for_shutdown_signal().await {
+            Ok(signal_name) => {
+                info!("Received {}, initiating graceful shutdown...", signal_name);
+                systemd::notify_stopping();
             }
             Err(err) => {
                 warn!("Unable to listen for shutdown signal: {}", err);
@@ -308,79 +1096,758 @@ async fn main() -> Result<()> {
         let _ = shutdown_tx.send(());
     });
 
-    // Create a separate task that waits for the process to exit and holds the process handle
-    let process_wait_task = tokio::task::spawn_blocking(move || {
-        let mut binary_process = binary_process;
-        let result = binary_process.wait();
-        let _ = exit_tx.send(result);
-        binary_process
-    });
+    // Run the binary until it exits, a shutdown is requested, or restarts are exhausted
+    systemd::notify_status("Waiting for the node to become ready");
+    metrics::set_phase("running");
+    let result = runner::supervise_node(config, shutdown_rx).await;
+
+    // Clean up the signal task
+    signal_task.abort();
+    let code = result?;
 
-    // Block the main thread until we receive a shutdown signal, post start shutdown, OR the process exits on its own
-    tokio::select! {
-        _ = shutdown_rx => {
-            info!("Shutdown signal received, terminating process {}", process_id);
-
-            // Abort the waiting task to get the process handle back
-            process_wait_task.abort();
-
-            // Try to get the process handle back from the aborted task
-            match process_wait_task.await {
-                Ok(mut binary_process) => {
-                    // Terminate the process directly using the process handle
-                    info!("Attempting graceful termination of process {}", process_id);
-                    match binary_process.kill() {
-                        Ok(_) => {
-                            info!("Successfully sent kill signal to process {}", process_id);
-                            // Wait for the process to exit
-                            match binary_process.wait() {
-                                Ok(status) => {
-                                    info!("Process exited with status: {:?}", status);
-                                }
-                                Err(e) => {
-                                    warn!("Error waiting for process: {}", e);
-                                }
-                            }
-                        }
-                        Err(e) => {
-                            warn!("Failed to kill process directly: {}", e);
-                        }
+    if code == 0 {
+        info!("Graceful shutdown complete");
+    } else {
+        warn!("Node exited with code {}", code);
+    }
+    Ok(code)
+}
+
+#[cfg(all(test, unix))]
+mod tests {
+    use super::*;
+    use std::fs;
+    use std::os::unix::fs::PermissionsExt;
+    use tempfile::tempdir;
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+    /// Node binary that writes a genesis file on `init` and leaves a marker on `start`
+    const NODE_SCRIPT: &str = r#"#!/bin/sh
+case "$1" in
+init)
+    while [ $# -gt 0 ]; do
+        [ "$1" = "--home" ] && home="$2"
+        shift
+    done
+    mkdir -p "$home/config"
+    [ -f "$home/data/priv_validator_state.json" ] && touch "$home/init-after-snapshot"
+    echo '{"chain_id":"testchain-1"}' > "$home/config/genesis.json"
+    echo 'minimum-gas-prices = ""' > "$home/config/app.toml"
+    ;;
+start)
+    touch "$(dirname "$0")/node-started"
+    ;;
+esac
+"#;
+
+    /// Serve fixed files over HTTP, keyed by request path
+    async fn spawn_file_server(files: Vec<(&'static str, Vec<u8>)>) -> String {
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(async move {
+            loop {
+                let Ok((mut socket, _)) = listener.accept().await else {
+                    return;
+                };
+                let mut buf = vec![0u8; 4096];
+                let n = socket.read(&mut buf).await.unwrap_or(0);
+                let request = String::from_utf8_lossy(&buf[..n]).to_lowercase();
+                let path = request.split_whitespace().nth(1).unwrap_or_default();
+                let response = match files.iter().find(|(name, _)| path == format!("/{name}")) {
+                    Some((_, body)) if request.contains("range: bytes=0-0") => {
+                        let mut head = format!(
+                            "HTTP/1.1 206 Partial Content\r\ncontent-range: bytes 0-0/{}\r\ncontent-length: 1\r\nconnection: close\r\n\r\n",
+                            body.len()
+                        )
+                        .into_bytes();
+                        head.extend_from_slice(&body[..1]);
+                        head
                     }
-                }
-                Err(e) => {
-                    warn!("Could not get process handle back for termination: {}", e);
-                }
+                    Some((_, body)) => {
+                        let mut head = format!(
+                            "HTTP/1.1 200 OK\r\ncontent-length: {}\r\nconnection: close\r\n\r\n",
+                            body.len()
+                        )
+                        .into_bytes();
+                        head.extend_from_slice(body);
+                        head
+                    }
+                    None => {
+                        b"HTTP/1.1 404 Not Found\r\ncontent-length: 0\r\nconnection: close\r\n\r\n"
+                            .to_vec()
+                    }
+                };
+                let _ = socket.write_all(&response).await;
+                let _ = socket.shutdown().await;
             }
+        });
+        format!("http://{addr}")
+    }
+
+    /// Snapshot archive holding a single data file
+    fn snapshot_archive() -> Result<Vec<u8>> {
+        let encoder = flate2::write::GzEncoder::new(Vec::new(), flate2::Compression::default());
+        let mut builder = tar::Builder::new(encoder);
+        let state = br#"{"height":"42"}"#;
+        let mut header = tar::Header::new_gnu();
+        header.set_size(state.len() as u64);
+        header.set_mode(0o644);
+        header.set_cksum();
+        builder.append_data(&mut header, "data/priv_validator_state.json", &state[..])?;
+        Ok(builder.into_inner()?.finish()?)
+    }
+
+    const ADDRBOOK: &str = r#"{"key":"0123456789abcdef","addrs":[]}"#;
+
+    /// Write a config whose binary, snapshot and address book are served by a local file server
+    /// `{server}` in `extra` is replaced by the server's URL
+    async fn fixture_config(dir: &Path, extra: &str) -> Result<Config> {
+        let server = spawn_file_server(vec![
+            ("gaiad", NODE_SCRIPT.as_bytes().to_vec()),
+            ("snapshot.tar.gz", snapshot_archive()?),
+            ("addrbook.json", ADDRBOOK.as_bytes().to_vec()),
+        ])
+        .await;
+        let extra = extra.replace("{server}", &server);
+        let config_path = dir.join("config.yaml");
+        fs::write(
+            &config_path,
+            format!(
+                r#"
+snapshot_url: "{server}/snapshot.tar.gz"
+binary_url: "{server}/gaiad"
+binary_relative_path: "bin/gaiad"
+chain_id: "testchain-1"
+moniker: "test-node"
+base_dir: "{}"
+app_yaml:
+  minimum-gas-prices: "0.01uatom"
+{extra}"#,
+                dir.display()
+            ),
+        )?;
+        let config = Config::from_file(&config_path)?;
+        utils::create_directories(&config)?;
+        Ok(config)
+    }
+
+    #[tokio::test]
+    async fn test_download_all_fetches_every_file() -> Result<()> {
+        let temp_dir = tempdir()?;
+        let config =
+            fixture_config(temp_dir.path(), r#"addrbook_url: "{server}/addrbook.json""#).await?;
+
+        let downloads = download_all(&config, true, true, true).await?;
+        assert_eq!(downloads.binary, Some(downloaded_binary_path(&config)));
+        assert_eq!(downloads.snapshot, Some(snapshot_path(&config)?));
+        let addrbook = downloads.addrbook.expect("addrbook downloaded");
+        assert_eq!(fs::read_to_string(addrbook)?, ADDRBOOK);
+        assert_eq!(
+            fs::read(snapshot_path(&config)?)?,
+            snapshot_archive()?,
+            "snapshot downloaded intact alongside the other files"
+        );
+
+        // Skipped downloads are not fetched
+        let downloads = download_all(&config, false, false, true).await?;
+        assert!(downloads.binary.is_none() && downloads.snapshot.is_none());
+        assert!(downloads.addrbook.is_some());
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_download_all_fails_when_one_download_fails() -> Result<()> {
+        let temp_dir = tempdir()?;
+        let config =
+            fixture_config(temp_dir.path(), r#"addrbook_url: "{server}/missing.json""#).await?;
+
+        let err = download_all(&config, true, true, true)
+            .await
+            .err()
+            .expect("missing addrbook fails the download phase");
+        assert!(format!("{err:#}").contains("addrbook"), "{err:#}");
+        Ok(())
+    }
+
+    fn phase(args: &[&str]) -> Phase {
+        let args =
+            Args::parse_from(std::iter::once("snapshot-downloader").chain(args.iter().copied()));
+        args.command.expect("subcommand")
+    }
+
+    #[tokio::test]
+    async fn test_subcommands_run_each_phase() -> Result<()> {
+        let temp_dir = tempdir()?;
+        let config = fixture_config(temp_dir.path(), "").await?;
+        let binary_path = config.workspace_dir.join(&config.binary_relative_path);
+        let genesis_path = config.home_dir.join("config").join("genesis.json");
+        let state_path = config
+            .home_dir
+            .join("data")
+            .join("priv_validator_state.json");
+
+        // download only fetches the files
+        run_phase(&config, &phase(&["download"])).await?;
+        assert!(downloaded_binary_path(&config).exists());
+        assert!(snapshot_path(&config)?.exists());
+        assert!(!binary_path.exists());
+        assert!(!state_path.exists());
+
+        // extract installs the binary and unpacks the snapshot, and can be repeated
+        for _ in 0..2 {
+            run_phase(&config, &phase(&["extract"])).await?;
         }
-        _ = async {
-            if let Some(rx) = post_start_shutdown_rx {
-                rx.await.ok();
-            } else {
-                // If no post start shutdown is configured, wait forever
-                std::future::pending::<()>().await;
-            }
-        } => {
-            info!("Post start command completed, terminating process {} and exiting program", process_id);
-            std::process::exit(0);
-        }
-        exit_status = exit_rx => {
-            match exit_status {
-                Ok(Ok(status)) => {
-                    info!("Binary process exited with status: {:?}", status);
-                }
-                Ok(Err(e)) => {
-                    warn!("Error waiting for binary process: {}", e);
-                }
-                Err(_) => {
-                    warn!("Failed to receive process exit status");
-                }
-            }
+        assert!(binary_path.exists());
+        assert_eq!(fs::read_to_string(&state_path)?, r#"{"height":"42"}"#);
+        assert!(!genesis_path.exists());
+
+        // init creates the home and applies overrides, and can be repeated
+        for _ in 0..2 {
+            run_phase(&config, &phase(&["init"])).await?;
         }
+        assert!(genesis_path.exists());
+        let app_toml = fs::read_to_string(config.home_dir.join("config").join("app.toml"))?;
+        assert!(app_toml.contains("minimum-gas-prices = \"0.01uatom\""));
+
+        // run starts the node
+        let marker = binary_path.parent().unwrap().join("node-started");
+        assert!(!marker.exists());
+        assert_eq!(run_phase(&config, &phase(&["run"])).await?, 0);
+        assert!(marker.exists());
+        Ok(())
     }
 
-    // Clean up the signal task
-    signal_task.abort();
+    #[tokio::test]
+    async fn test_extract_uses_recorded_download_paths() -> Result<()> {
+        let temp_dir = tempdir()?;
+        let mut config = fixture_config(temp_dir.path(), "").await?;
+        // The primary URLs are missing, so both files are saved under their mirrors' names
+        config.snapshot_mirrors = vec![config.snapshot_url.clone()];
+        config.snapshot_url = config.snapshot_url.replace("snapshot", "latest");
+        config.binary_mirrors = vec![config.binary_url.clone()];
+        config.binary_url = format!("{}-latest", config.binary_url);
 
-    info!("Graceful shutdown complete");
-    Ok(())
+        run_phase(&config, &phase(&["download"])).await?;
+        assert!(!config.downloads_dir.join("latest.tar.gz").exists());
+        assert_eq!(
+            snapshot_path(&config)?,
+            config.downloads_dir.join("snapshot.tar.gz")
+        );
+        assert_eq!(
+            downloaded_binary_path(&config),
+            config.downloads_dir.join("gaiad")
+        );
+
+        run_phase(&config, &phase(&["extract"])).await?;
+        assert!(config
+            .workspace_dir
+            .join(&config.binary_relative_path)
+            .exists());
+        assert!(config
+            .home_dir
+            .join("data")
+            .join("priv_validator_state.json")
+            .exists());
+
+        // prune finds the files under the names they were saved as
+        run_phase(&config, &phase(&["prune", "--include-binary"])).await?;
+        assert!(!config.downloads_dir.join("snapshot.tar.gz").exists());
+        assert!(!config.downloads_dir.join("gaiad").exists());
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_verify_checks_the_downloaded_snapshot() -> Result<()> {
+        let temp_dir = tempdir()?;
+        let mut config = fixture_config(temp_dir.path(), "").await?;
+        // Saved under the mirror's name, next to a corrupt file under the primary URL's name
+        config.snapshot_mirrors = vec![config.snapshot_url.clone()];
+        config.snapshot_url = config.snapshot_url.replace("snapshot", "latest");
+
+        run_phase(&config, &phase(&["download"])).await?;
+        fs::write(config.downloads_dir.join("latest.tar.gz"), b"corrupt")?;
+        run_phase(&config, &phase(&["verify"])).await?;
+
+        let corrupt = config.downloads_dir.join("latest.tar.gz");
+        let args = ["verify", corrupt.to_str().unwrap()];
+        assert!(run_phase(&config, &phase(&args)).await.is_err());
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_all_runs_every_phase() -> Result<()> {
+        let temp_dir = tempdir()?;
+        let config =
+            fixture_config(temp_dir.path(), r#"addrbook_url: "{server}/addrbook.json""#).await?;
+
+        assert_eq!(run_phase(&config, &phase(&["all"])).await?, 0);
+        assert!(config.home_dir.join("config").join("genesis.json").exists());
+        assert_eq!(
+            fs::read_to_string(config.home_dir.join("config").join("addrbook.json"))?,
+            ADDRBOOK
+        );
+        assert!(config
+            .home_dir
+            .join("data")
+            .join("priv_validator_state.json")
+            .exists());
+        assert!(config
+            .workspace_dir
+            .join("bin")
+            .join("node-started")
+            .exists());
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_init_before_snapshot_order() -> Result<()> {
+        let args = AllArgs {
+            skip_run: true,
+            ..AllArgs::default()
+        };
+
+        // Default: init runs on an empty home, then the snapshot is extracted over it
+        let temp_dir = tempdir()?;
+        let config = fixture_config(temp_dir.path(), "").await?;
+        run_all(&config, &args).await?;
+        assert!(config.home_dir.join("config/genesis.json").exists());
+        assert!(!config.home_dir.join("init-after-snapshot").exists());
+
+        let temp_dir = tempdir()?;
+        let config = fixture_config(temp_dir.path(), "init_before_snapshot: false").await?;
+        run_all(&config, &args).await?;
+        assert!(config.home_dir.join("config/genesis.json").exists());
+        assert!(config.home_dir.join("init-after-snapshot").exists());
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_force_downloads_again() -> Result<()> {
+        let temp_dir = tempdir()?;
+        let config = fixture_config(temp_dir.path(), "").await?;
+        let args = AllArgs {
+            skip_run: true,
+            ..AllArgs::default()
+        };
+
+        // A complete-looking but corrupt snapshot is reused, so extraction fails
+        let corrupt = vec![0u8; snapshot_archive()?.len()];
+        fs::write(snapshot_path(&config)?, &corrupt)?;
+        assert!(run_all(&config, &args).await.is_err());
+        assert_eq!(fs::read(snapshot_path(&config)?)?, corrupt);
+
+        // --force fetches it again and clears stale data before extracting
+        let stale = config.home_dir.join("data").join("stale.db");
+        fs::create_dir_all(stale.parent().unwrap())?;
+        fs::write(&stale, b"stale")?;
+        let args = AllArgs {
+            force: true,
+            ..args
+        };
+        run_all(&config, &args).await?;
+        assert_eq!(fs::read(snapshot_path(&config)?)?, snapshot_archive()?);
+        assert!(!stale.exists());
+        assert!(config.home_dir.join("config/genesis.json").exists());
+        assert_eq!(
+            fs::read_to_string(config.home_dir.join("data/priv_validator_state.json"))?,
+            r#"{"height":"42"}"#
+        );
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_node_exit_code_is_returned() -> Result<()> {
+        let temp_dir = tempdir()?;
+        let config = fixture_config(temp_dir.path(), "").await?;
+
+        // A node that crashes straight away, with no restarts configured
+        let binary_path = config.workspace_dir.join(&config.binary_relative_path);
+        fs::create_dir_all(binary_path.parent().unwrap())?;
+        fs::write(&binary_path, "#!/bin/sh\nexit 3\n")?;
+        fs::set_permissions(&binary_path, fs::Permissions::from_mode(0o755))?;
+
+        assert_eq!(run_phase(&config, &phase(&["run"])).await?, 3);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_cleanup_after_extract_keeps_home() -> Result<()> {
+        let temp_dir = tempdir()?;
+        let config = fixture_config(temp_dir.path(), "cleanup_after_extract: true\n").await?;
+
+        run_phase(&config, &phase(&["download"])).await?;
+        run_phase(&config, &phase(&["extract"])).await?;
+
+        assert!(!snapshot_path(&config)?.exists());
+        assert!(downloaded_binary_path(&config).exists());
+        assert_eq!(
+            fs::read_to_string(config.home_dir.join("data/priv_validator_state.json"))?,
+            r#"{"height":"42"}"#
+        );
+
+        // prune also removes the binary when asked to
+        run_phase(&config, &phase(&["prune", "--include-binary"])).await?;
+        assert!(!downloaded_binary_path(&config).exists());
+        assert!(config.workspace_dir.join("bin/gaiad").exists());
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_post_binary_extract_command() -> Result<()> {
+        let temp_dir = tempdir()?;
+        let marker = temp_dir.path().join("post-binary-extract-ran");
+        let config = fixture_config(
+            temp_dir.path(),
+            &format!(
+                "post_binary_extract_command: \"test -x {} && touch {}\"\n",
+                temp_dir.path().join("workspace/bin/gaiad").display(),
+                marker.display()
+            ),
+        )
+        .await?;
+
+        let binary_path = download_binary(&config, &DownloadOptions::from_config(&config)?).await?;
+        extract_binary(&config, &binary_path)?;
+        assert!(marker.exists());
+
+        // A failing command aborts before init
+        let failing =
+            fixture_config(temp_dir.path(), "post_binary_extract_command: \"exit 3\"\n").await?;
+        let err = extract_binary(&failing, &binary_path).unwrap_err();
+        assert!(format!("{err:#}").contains("exit code: 3"), "{err:#}");
+        Ok(())
+    }
+
+    #[test]
+    fn test_skip_flags_without_subcommand() {
+        // Flags given without a subcommand keep working for the implicit `all`
+        let args = Args::parse_from(["snapshot-downloader", "--skip-download-snapshot"]);
+        assert!(args.command.is_none());
+        assert!(args.all.skip_download_snapshot);
+
+        // Flags before and after `all` are combined
+        let args = Args::parse_from([
+            "snapshot-downloader",
+            "--skip-binary-download",
+            "all",
+            "--skip-run",
+        ]);
+        let Some(Phase::All(all)) = &args.command else {
+            panic!("expected the all subcommand");
+        };
+        let merged = args.all.merge(all);
+        assert!(merged.skip_binary_download);
+        assert!(merged.skip_run);
+        assert!(!merged.dry_run);
+
+        // Global options are accepted after a subcommand
+        let args = Args::parse_from(["snapshot-downloader", "download", "--config", "node.yaml"]);
+        assert_eq!(args.config, PathBuf::from("node.yaml"));
+    }
+
+    #[test]
+    fn test_log_level_flags() {
+        let args = Args::parse_from(["snapshot-downloader"]);
+        assert_eq!(args.log_level(), LogLevel::Info);
+        let args = Args::parse_from(["snapshot-downloader", "download", "--quiet"]);
+        assert_eq!(args.log_level(), LogLevel::Warn);
+        let args = Args::parse_from(["snapshot-downloader", "-v", "--log-format", "json"]);
+        assert_eq!(args.log_level(), LogLevel::Debug);
+        assert_eq!(args.log_format, LogFormat::Json);
+        let args = Args::parse_from(["snapshot-downloader", "--log-level", "trace"]);
+        assert_eq!(args.log_level(), LogLevel::Trace);
+        assert!(Args::try_parse_from(["snapshot-downloader", "--quiet", "--verbose"]).is_err());
+    }
+
+    #[tokio::test]
+    async fn test_skip_run_does_not_spawn_the_node() -> Result<()> {
+        let temp_dir = tempdir()?;
+        let config_path = temp_dir.path().join("config.yaml");
+        fs::write(
+            &config_path,
+            format!(
+                r#"
+snapshot_url: "https://example.com/snapshot.tar.gz"
+binary_url: "https://example.com/gaiad.tar.gz"
+binary_relative_path: "bin/gaiad"
+chain_id: "cosmoshub-4"
+moniker: "test-node"
+base_dir: "{}"
+pre_start_command: "touch {}/pre-start-ran"
+"#,
+                temp_dir.path().display(),
+                temp_dir.path().display()
+            ),
+        )?;
+        let config = Config::from_file(&config_path)?;
+
+        // A node binary that leaves a marker behind if it is ever executed
+        let marker = temp_dir.path().join("node-started");
+        let binary_path = config.workspace_dir.join(&config.binary_relative_path);
+        fs::create_dir_all(binary_path.parent().unwrap())?;
+        fs::write(
+            &binary_path,
+            format!("#!/bin/sh\ntouch {}\n", marker.display()),
+        )?;
+        fs::set_permissions(&binary_path, fs::Permissions::from_mode(0o755))?;
+
+        let args = Args::parse_from(["snapshot-downloader", "--no-start"]);
+        assert!(args.all.skip_run);
+        assert_eq!(start_node(&config, &args.all).await?, 0);
+
+        assert!(!marker.exists(), "the node was started");
+        assert!(!temp_dir.path().join("pre-start-ran").exists());
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_install_addrbook_custom_target() -> Result<()> {
+        let temp_dir = tempdir()?;
+        let config_path = temp_dir.path().join("config.yaml");
+        fs::write(
+            &config_path,
+            format!(
+                r#"
+snapshot_url: "https://example.com/snapshot.tar.gz"
+binary_url: "https://example.com/gaiad.tar.gz"
+binary_relative_path: "bin/gaiad"
+chain_id: "cosmoshub-4"
+moniker: "test-node"
+base_dir: "{}"
+addrbook_url: "https://example.com/addrbook.json"
+addrbook_target_path: "cfg/p2p/addrbook.json"
+"#,
+                temp_dir.path().display()
+            ),
+        )?;
+        let config = Config::from_file(&config_path)?;
+        let target = config.home_dir.join("cfg/p2p/addrbook.json");
+        assert_eq!(config.get_addrbook_target_path(), target);
+
+        fs::create_dir_all(&config.downloads_dir)?;
+        let downloaded = config.downloads_dir.join("addrbook.json");
+        fs::write(&downloaded, r#"{"addrs":[]}"#)?;
+        install_addrbook(&config, &downloaded).await?;
+        assert_eq!(fs::read_to_string(&target)?, r#"{"addrs":[]}"#);
+        assert!(!downloaded.exists());
+        assert!(!config.home_dir.join("config/addrbook.json").exists());
+
+        // Already in place: nothing to copy, and the file must survive
+        install_addrbook(&config, &target).await?;
+        assert_eq!(fs::read_to_string(&target)?, r#"{"addrs":[]}"#);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_install_addrbook_rejects_invalid_file() -> Result<()> {
+        let temp_dir = tempdir()?;
+        let config_path = temp_dir.path().join("config.yaml");
+        fs::write(
+            &config_path,
+            format!(
+                r#"
+snapshot_url: "https://example.com/snapshot.tar.gz"
+binary_url: "https://example.com/gaiad.tar.gz"
+binary_relative_path: "bin/gaiad"
+chain_id: "cosmoshub-4"
+moniker: "test-node"
+base_dir: "{}"
+addrbook_url: "https://example.com/addrbook.json"
+"#,
+                temp_dir.path().display()
+            ),
+        )?;
+        let config = Config::from_file(&config_path)?;
+        let target = config.get_addrbook_target_path();
+        fs::create_dir_all(&config.downloads_dir)?;
+        let downloaded = config.downloads_dir.join("addrbook.json");
+
+        let valid = r#"{"key": "c0ffee", "addrs": [{"addr": {"id": "abc", "ip": "1.2.3.4", "port": 26656}}]}"#;
+        fs::write(&downloaded, valid)?;
+        install_addrbook(&config, &downloaded).await?;
+        assert_eq!(fs::read_to_string(&target)?, valid);
+
+        for invalid in [
+            "<html><body><h1>502 Bad Gateway</h1></body></html>",
+            r#"{"key": "c0ffee", "addrs": [{"addr""#,
+            r#"{"peers": []}"#,
+        ] {
+            fs::write(&downloaded, invalid)?;
+            assert!(install_addrbook(&config, &downloaded).await.is_err());
+            assert!(!downloaded.exists());
+            assert_eq!(fs::read_to_string(&target)?, valid);
+        }
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_snapshot_metadata_skips_unchanged_snapshot() -> Result<()> {
+        let temp_dir = tempdir()?;
+        let published = temp_dir.path().join("published");
+        fs::create_dir_all(&published)?;
+        write_snapshot(
+            &published.join("snapshot.tar.gz"),
+            &[("data/state.db", b"v1")],
+        )?;
+        fs::write(
+            published.join("metadata.json"),
+            r#"{"height": 100, "timestamp": "2024-05-01T00:00:00Z"}"#,
+        )?;
+
+        let config_path = temp_dir.path().join("config.yaml");
+        fs::write(
+            &config_path,
+            format!(
+                r#"
+snapshot_url: "file://{published}/snapshot.tar.gz"
+snapshot_metadata_url: "file://{published}/metadata.json"
+binary_url: "https://example.com/gaiad.tar.gz"
+binary_relative_path: "bin/gaiad"
+chain_id: "cosmoshub-4"
+moniker: "test-node"
+base_dir: "{}"
+"#,
+                temp_dir.path().display(),
+                published = published.display()
+            ),
+        )?;
+        let config = Config::from_file(&config_path)?;
+        utils::create_directories(&config)?;
+
+        let downloads = download_all(&config, false, true, false).await?;
+        assert_eq!(downloads.snapshot, Some(snapshot_path(&config)?));
+        assert!(!downloads.snapshot_unchanged);
+
+        // Unchanged: the cleaned up download is not fetched again
+        fs::remove_file(snapshot_path(&config)?)?;
+        let downloads = download_all(&config, false, true, false).await?;
+        assert_eq!(downloads.snapshot, None);
+        assert!(downloads.snapshot_unchanged);
+        assert!(!snapshot_path(&config)?.exists());
+
+        // A newer snapshot is downloaded, replacing one still on disk
+        fs::write(snapshot_path(&config)?, b"stale")?;
+        write_snapshot(
+            &published.join("snapshot.tar.gz"),
+            &[("data/state.db", b"v2")],
+        )?;
+        fs::write(
+            published.join("metadata.json"),
+            r#"{"height": 200, "timestamp": "2024-05-02T00:00:00Z"}"#,
+        )?;
+        let downloads = download_all(&config, false, true, false).await?;
+        assert_eq!(downloads.snapshot, Some(snapshot_path(&config)?));
+        extract_snapshot(&config, &snapshot_path(&config)?, false)?;
+        assert_eq!(
+            fs::read_to_string(config.home_dir.join("data/state.db"))?,
+            "v2"
+        );
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_extract_snapshot_from_file_url() -> Result<()> {
+        let temp_dir = tempdir()?;
+        let archive_path = temp_dir.path().join("nfs/snapshot.tar.gz");
+        fs::create_dir_all(archive_path.parent().unwrap())?;
+        write_snapshot(&archive_path, &[("data/state.db", b"state")])?;
+
+        let config_path = temp_dir.path().join("config.yaml");
+        fs::write(
+            &config_path,
+            format!(
+                r#"
+snapshot_url: "file://{}"
+binary_url: "https://example.com/gaiad.tar.gz"
+binary_relative_path: "bin/gaiad"
+chain_id: "cosmoshub-4"
+moniker: "test-node"
+base_dir: "{}"
+"#,
+                archive_path.display(),
+                temp_dir.path().display()
+            ),
+        )?;
+        let config = Config::from_file(&config_path)?;
+        utils::create_directories(&config)?;
+
+        let snapshot = download_snapshot(&config, &DownloadOptions::from_config(&config)?).await?;
+        assert_eq!(snapshot, snapshot_path(&config)?);
+        extract_snapshot(&config, &snapshot, false)?;
+        assert_eq!(
+            fs::read_to_string(config.home_dir.join("data/state.db"))?,
+            "state"
+        );
+        assert!(archive_path.exists());
+        Ok(())
+    }
+
+    /// Write a tar.gz snapshot holding the given files
+    fn write_snapshot(path: &Path, files: &[(&str, &[u8])]) -> Result<()> {
+        let mut builder = tar::Builder::new(flate2::write::GzEncoder::new(
+            fs::File::create(path)?,
+            flate2::Compression::default(),
+        ));
+        for (name, data) in files {
+            let mut header = tar::Header::new_gnu();
+            header.set_size(data.len() as u64);
+            header.set_mode(0o644);
+            header.set_cksum();
+            builder.append_data(&mut header, name, *data)?;
+        }
+        builder.into_inner()?.finish()?;
+        Ok(())
+    }
+
+    #[test]
+    fn test_clean_target_before_extract_clears_only_listed_dirs() -> Result<()> {
+        let temp_dir = tempdir()?;
+        let config_path = temp_dir.path().join("config.yaml");
+        fs::write(
+            &config_path,
+            format!(
+                r#"
+snapshot_url: "https://example.com/snapshot.tar.gz"
+binary_url: "https://example.com/gaiad.tar.gz"
+binary_relative_path: "bin/gaiad"
+chain_id: "cosmoshub-4"
+moniker: "test-node"
+base_dir: "{}"
+clean_target_before_extract: true
+snapshot_dirs: ["data"]
+"#,
+                temp_dir.path().display()
+            ),
+        )?;
+        let config = Config::from_file(&config_path)?;
+        let home = &config.home_dir;
+        for (file, content) in [
+            ("data/application.db/old.ldb", "orphan"),
+            ("data/priv_validator_state.json", "{}"),
+            ("wasm/code", "wasm"),
+            ("config/config.toml", "config"),
+        ] {
+            fs::create_dir_all(home.join(file).parent().unwrap())?;
+            fs::write(home.join(file), content)?;
+        }
+
+        let snapshot = temp_dir.path().join("snapshot.tar.gz");
+        write_snapshot(&snapshot, &[("data/application.db/new.ldb", b"new")])?;
+        extract_snapshot(&config, &snapshot, false)?;
+
+        assert!(home.join("data/application.db/new.ldb").exists());
+        assert!(!home.join("data/application.db/old.ldb").exists());
+        assert!(home.join("data/priv_validator_state.json").exists());
+        assert_eq!(fs::read_to_string(home.join("wasm/code"))?, "wasm");
+        assert_eq!(
+            fs::read_to_string(home.join("config/config.toml"))?,
+            "config"
+        );
+
+        // Skipping an extraction that already completed leaves the directories alone
+        fs::write(home.join("data/added-after.db"), "kept")?;
+        extract_snapshot(&config, &snapshot, false)?;
+        assert!(home.join("data/added-after.db").exists());
+        Ok(())
+    }
 }
@@ -0,0 +1,287 @@
+use anyhow::{Context, Result};
+use md5::{Digest, Md5};
+use serde::Deserialize;
+use std::fs;
+use std::io::{self, Read, Seek, SeekFrom};
+use std::path::Path;
+use tracing::{debug, info, warn};
+
+use crate::download::{self, DownloadOptions};
+use crate::error::DownloadError;
+
+/// Parts of a multipart snapshot, as listed by the JSON or YAML file at `snapshot_manifest_url`
+///
+/// ```yaml
+/// parts:
+///   - url: "snapshot.tar.lz4.part1" # absolute, or relative to the manifest's URL
+///     size: 1073741824              # optional
+///     md5: "9e107d9d372bb6826bd81d3542a419d6" # optional, checked when size is known
+/// ```
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Manifest {
+    pub parts: Vec<ManifestPart>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct ManifestPart {
+    pub url: String,
+    /// Size in bytes
+    #[serde(default)]
+    pub size: Option<u64>,
+    /// Hex MD5 of the part's content
+    #[serde(default)]
+    pub md5: Option<String>,
+}
+
+impl Manifest {
+    /// Parse a manifest fetched from `manifest_url`, resolving relative part URLs against it
+    pub fn parse(content: &str, manifest_url: &str) -> Result<Self> {
+        // YAML is a superset of JSON, so one parser reads both
+        let mut manifest: Manifest =
+            serde_yaml::from_str(content).context("Invalid snapshot manifest")?;
+        if manifest.parts.is_empty() {
+            return Err(anyhow::anyhow!("Snapshot manifest lists no parts"));
+        }
+
+        let base = reqwest::Url::parse(manifest_url).ok();
+        for (i, part) in manifest.parts.iter_mut().enumerate() {
+            let url = match (reqwest::Url::parse(&part.url), &base) {
+                (Ok(url), _) => url,
+                (Err(_), Some(base)) => base
+                    .join(&part.url)
+                    .with_context(|| format!("Invalid URL for part {}: {}", i + 1, part.url))?,
+                (Err(e), None) => {
+                    return Err(e)
+                        .with_context(|| format!("Invalid URL for part {}: {}", i + 1, part.url))
+                }
+            };
+            // Parts are downloaded with HTTP range requests
+            if !matches!(url.scheme(), "http" | "https") {
+                return Err(anyhow::anyhow!(
+                    "Part {} must be an http:// or https:// URL, got '{}'",
+                    i + 1,
+                    url
+                ));
+            }
+            part.url = url.to_string();
+
+            if let Some(md5) = &mut part.md5 {
+                if md5.len() != 32 || !md5.chars().all(|c| c.is_ascii_hexdigit()) {
+                    return Err(anyhow::anyhow!("Invalid MD5 for part {}: '{}'", i + 1, md5));
+                }
+                md5.make_ascii_lowercase();
+            }
+        }
+        Ok(manifest)
+    }
+
+    /// URLs of the parts, in order
+    pub fn urls(&self) -> Vec<String> {
+        self.parts.iter().map(|part| part.url.clone()).collect()
+    }
+
+    /// Check the snapshot assembled from the parts against their sizes and checksums, removing
+    /// it on a mismatch so the next run downloads it again
+    /// A checksum can only be checked if the sizes of its part and all parts before it are known
+    pub fn verify(&self, snapshot_path: &Path) -> Result<(), DownloadError> {
+        let result = self.check(snapshot_path);
+        if matches!(
+            result,
+            Err(DownloadError::SizeMismatch { .. } | DownloadError::Checksum { .. })
+        ) {
+            if let Err(e) = fs::remove_file(snapshot_path) {
+                warn!("Failed to remove {}: {}", snapshot_path.display(), e);
+            }
+        }
+        result
+    }
+
+    fn check(&self, snapshot_path: &Path) -> Result<(), DownloadError> {
+        let io_error = |source| DownloadError::Io {
+            context: format!(
+                "Failed to read {} for verification",
+                snapshot_path.display()
+            ),
+            source,
+        };
+        let mut file = fs::File::open(snapshot_path).map_err(io_error)?;
+        let actual_size = file.metadata().map_err(io_error)?.len();
+
+        let expected_size: Option<u64> = self.parts.iter().map(|part| part.size).sum();
+        if let Some(expected) = expected_size {
+            if expected != actual_size {
+                return Err(DownloadError::SizeMismatch {
+                    path: snapshot_path.to_path_buf(),
+                    expected,
+                    actual: actual_size,
+                });
+            }
+        }
+
+        // Start of the current part, unknown once a part without a size has been passed
+        let mut offset = Some(0);
+        for (i, part) in self.parts.iter().enumerate() {
+            match (offset, part.size, &part.md5) {
+                (Some(start), Some(size), Some(expected)) => {
+                    let actual = md5_of_range(&mut file, start, size).map_err(io_error)?;
+                    if actual != *expected {
+                        warn!("Part {} of {} is corrupt", i + 1, snapshot_path.display());
+                        return Err(DownloadError::Checksum {
+                            path: snapshot_path.to_path_buf(),
+                            expected: expected.clone(),
+                            actual,
+                        });
+                    }
+                    debug!("MD5 of part {} matches the manifest", i + 1);
+                }
+                (None, _, Some(_)) => {
+                    warn!(
+                        "Cannot locate part {} without the sizes of the parts before it, skipping its checksum",
+                        i + 1
+                    );
+                }
+                _ => {}
+            }
+            offset = offset.zip(part.size).map(|(start, size)| start + size);
+        }
+
+        info!("Snapshot matches its manifest: {}", snapshot_path.display());
+        Ok(())
+    }
+}
+
+/// Download the manifest at `url` and parse it
+/// A copy left by an earlier run is replaced, since the manifest may change between runs
+pub async fn fetch_manifest(
+    url: &str,
+    download_dir: &Path,
+    options: &DownloadOptions,
+) -> Result<Manifest> {
+    let stale_path = download_dir.join(crate::utils::download_filename(url));
+    match fs::remove_file(&stale_path) {
+        Err(e) if e.kind() != io::ErrorKind::NotFound => {
+            return Err(e).with_context(|| format!("Failed to remove {}", stale_path.display()))
+        }
+        _ => {}
+    }
+
+    let path = download::download_with_mirrors(
+        &[url.to_string()],
+        download_dir,
+        "snapshot manifest",
+        options,
+    )
+    .await
+    .context("Failed to download snapshot manifest")?;
+    let content = fs::read_to_string(&path)
+        .with_context(|| format!("Failed to read snapshot manifest {}", path.display()))?;
+    Manifest::parse(&content, url)
+}
+
+fn md5_of_range(file: &mut fs::File, start: u64, len: u64) -> io::Result<String> {
+    file.seek(SeekFrom::Start(start))?;
+    let mut hasher = Md5::new();
+    io::copy(&mut file.take(len), &mut hasher)?;
+    Ok(format!("{:x}", hasher.finalize()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::tempdir;
+
+    fn md5_hex(data: &[u8]) -> String {
+        format!("{:x}", Md5::digest(data))
+    }
+
+    #[test]
+    fn test_parse_manifest() -> Result<()> {
+        let json = r#"{
+            "parts": [
+                {"url": "snapshot.tar.lz4.part1", "size": 4, "md5": "ABCDEF0123456789ABCDEF0123456789"},
+                {"url": "https://mirror.example.com/snapshot.tar.lz4.part2"}
+            ]
+        }"#;
+        let manifest = Manifest::parse(json, "https://example.com/snapshots/manifest.json")?;
+        assert_eq!(
+            manifest.urls(),
+            vec![
+                "https://example.com/snapshots/snapshot.tar.lz4.part1",
+                "https://mirror.example.com/snapshot.tar.lz4.part2",
+            ]
+        );
+        assert_eq!(manifest.parts[0].size, Some(4));
+        assert_eq!(
+            manifest.parts[0].md5.as_deref(),
+            Some("abcdef0123456789abcdef0123456789")
+        );
+        assert_eq!(manifest.parts[1].size, None);
+
+        let yaml = "parts:\n  - url: part1\n    size: 4\n";
+        let from_yaml = Manifest::parse(yaml, "https://example.com/manifest.yaml")?;
+        assert_eq!(from_yaml.urls(), vec!["https://example.com/part1"]);
+
+        assert!(Manifest::parse("parts: []", "https://example.com/m.json").is_err());
+        assert!(Manifest::parse("parts:\n  - url: s3://bucket/part1\n", "").is_err());
+        assert!(Manifest::parse(
+            "parts:\n  - url: part1\n    md5: nope\n",
+            "https://example.com/m.json"
+        )
+        .is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_verify_catches_size_mismatch() -> Result<()> {
+        let temp_dir = tempdir()?;
+        let path = temp_dir.path().join("snapshot.tar.lz4");
+        let manifest = Manifest::parse(
+            "parts:\n  - url: part1\n    size: 4\n  - url: part2\n    size: 4\n",
+            "https://example.com/manifest.yaml",
+        )?;
+
+        fs::write(&path, b"partpar")?;
+        match manifest.verify(&path) {
+            Err(DownloadError::SizeMismatch {
+                expected: 8,
+                actual: 7,
+                ..
+            }) => {}
+            other => panic!("expected SizeMismatch, got {other:?}"),
+        }
+        assert!(!path.exists());
+
+        fs::write(&path, b"partpart")?;
+        manifest.verify(&path)?;
+        Ok(())
+    }
+
+    #[test]
+    fn test_verify_checks_part_checksums() -> Result<()> {
+        let temp_dir = tempdir()?;
+        let path = temp_dir.path().join("snapshot.tar.lz4");
+        let manifest = Manifest::parse(
+            &format!(
+                "parts:\n  - url: part1\n    size: 5\n    md5: {}\n  - url: part2\n    size: 5\n    md5: {}\n",
+                md5_hex(b"first"),
+                md5_hex(b"other")
+            ),
+            "https://example.com/manifest.yaml",
+        )?;
+
+        fs::write(&path, b"firstsecnd")?;
+        match manifest.verify(&path) {
+            Err(DownloadError::Checksum { expected, .. }) => {
+                assert_eq!(expected, md5_hex(b"other"))
+            }
+            other => panic!("expected Checksum, got {other:?}"),
+        }
+        assert!(!path.exists());
+
+        fs::write(&path, b"firstother")?;
+        manifest.verify(&path)?;
+        Ok(())
+    }
+}
@@ -0,0 +1,233 @@
+use anyhow::{Context, Result};
+use bytes::Bytes;
+use http_body_util::Full;
+use hyper::server::conn::http1;
+use hyper::service::service_fn;
+use hyper::{Method, Request, Response, StatusCode};
+use hyper_util::rt::TokioIo;
+use std::collections::BTreeMap;
+use std::convert::Infallible;
+use std::fmt::Write as _;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Mutex;
+use std::time::Instant;
+use tokio::net::TcpListener;
+use tracing::{debug, info};
+
+/// Process-wide metrics, updated from the download and extraction code
+static METRICS: Mutex<Metrics> = Mutex::new(Metrics {
+    phase: None,
+    downloads: BTreeMap::new(),
+    extract_read_bytes: 0,
+    extract_total_bytes: 0,
+});
+
+static DOWNLOAD_RETRIES: AtomicU64 = AtomicU64::new(0);
+
+struct Metrics {
+    phase: Option<&'static str>,
+    downloads: BTreeMap<String, DownloadMetrics>,
+    extract_read_bytes: u64,
+    extract_total_bytes: u64,
+}
+
+struct DownloadMetrics {
+    bytes: u64,
+    /// When this process first saw the download and how many bytes it already had then
+    started: Instant,
+    start_bytes: u64,
+}
+
+fn lock() -> std::sync::MutexGuard<'static, Metrics> {
+    METRICS.lock().unwrap_or_else(|e| e.into_inner())
+}
+
+/// Set the current phase (e.g. "extract_snapshot"), exported as `snapshot_downloader_phase`
+pub fn set_phase(phase: &'static str) {
+    lock().phase = Some(phase);
+}
+
+/// Record how many bytes of a file (labelled by its file type, e.g. "snapshot") are on disk
+pub fn record_download_bytes(file: &str, bytes: u64) {
+    let mut metrics = lock();
+    match metrics.downloads.get_mut(file) {
+        Some(download) => download.bytes = bytes,
+        None => {
+            metrics.downloads.insert(
+                file.to_string(),
+                DownloadMetrics {
+                    bytes,
+                    started: Instant::now(),
+                    start_bytes: bytes,
+                },
+            );
+        }
+    }
+}
+
+/// Count a failed download attempt that is about to be retried
+pub fn record_download_retry() {
+    DOWNLOAD_RETRIES.fetch_add(1, Ordering::Relaxed);
+}
+
+/// Record extraction progress as compressed bytes read out of the archive's total size
+pub fn record_extract_progress(read_bytes: u64, total_bytes: u64) {
+    let mut metrics = lock();
+    metrics.extract_read_bytes = read_bytes;
+    metrics.extract_total_bytes = total_bytes;
+}
+
+/// Render all metrics in the Prometheus text exposition format
+pub fn render() -> String {
+    render_metrics(&lock(), DOWNLOAD_RETRIES.load(Ordering::Relaxed))
+}
+
+fn render_metrics(metrics: &Metrics, retries: u64) -> String {
+    let mut out = String::new();
+
+    out.push_str(
+        "# HELP snapshot_downloader_download_bytes Bytes of each file downloaded so far\n",
+    );
+    out.push_str("# TYPE snapshot_downloader_download_bytes gauge\n");
+    for (file, download) in &metrics.downloads {
+        let _ = writeln!(
+            out,
+            "snapshot_downloader_download_bytes{{file=\"{}\"}} {}",
+            escape_label(file),
+            download.bytes
+        );
+    }
+
+    out.push_str("# HELP snapshot_downloader_download_rate_bytes_per_second Average download rate of each file\n");
+    out.push_str("# TYPE snapshot_downloader_download_rate_bytes_per_second gauge\n");
+    for (file, download) in &metrics.downloads {
+        let elapsed = download.started.elapsed().as_secs_f64();
+        let rate = if elapsed > 0.0 {
+            download.bytes.saturating_sub(download.start_bytes) as f64 / elapsed
+        } else {
+            0.0
+        };
+        let _ = writeln!(
+            out,
+            "snapshot_downloader_download_rate_bytes_per_second{{file=\"{}\"}} {:.0}",
+            escape_label(file),
+            rate
+        );
+    }
+
+    out.push_str("# HELP snapshot_downloader_download_retries_total Failed download attempts that were retried\n");
+    out.push_str("# TYPE snapshot_downloader_download_retries_total counter\n");
+    let _ = writeln!(out, "snapshot_downloader_download_retries_total {retries}");
+
+    out.push_str("# HELP snapshot_downloader_extract_read_bytes Archive bytes read by the snapshot extraction\n");
+    out.push_str("# TYPE snapshot_downloader_extract_read_bytes gauge\n");
+    let _ = writeln!(
+        out,
+        "snapshot_downloader_extract_read_bytes {}",
+        metrics.extract_read_bytes
+    );
+    out.push_str(
+        "# HELP snapshot_downloader_extract_total_bytes Size of the archive being extracted\n",
+    );
+    out.push_str("# TYPE snapshot_downloader_extract_total_bytes gauge\n");
+    let _ = writeln!(
+        out,
+        "snapshot_downloader_extract_total_bytes {}",
+        metrics.extract_total_bytes
+    );
+
+    out.push_str("# HELP snapshot_downloader_phase Current phase of the run\n");
+    out.push_str("# TYPE snapshot_downloader_phase gauge\n");
+    if let Some(phase) = metrics.phase {
+        let _ = writeln!(out, "snapshot_downloader_phase{{phase=\"{phase}\"}} 1");
+    }
+
+    out
+}
+
+/// Escape a Prometheus label value
+fn escape_label(value: &str) -> String {
+    value
+        .replace('\\', "\\\\")
+        .replace('"', "\\\"")
+        .replace('\n', "\\n")
+}
+
+async fn handle(
+    request: Request<hyper::body::Incoming>,
+) -> Result<Response<Full<Bytes>>, Infallible> {
+    let response = if request.method() == Method::GET && request.uri().path() == "/metrics" {
+        Response::builder()
+            .header("content-type", "text/plain; version=0.0.4")
+            .body(Full::new(Bytes::from(render())))
+    } else {
+        Response::builder()
+            .status(StatusCode::NOT_FOUND)
+            .body(Full::new(Bytes::new()))
+    };
+    Ok(response.unwrap_or_default())
+}
+
+/// Serve `/metrics` on an already bound listener until the task is aborted
+pub async fn serve(listener: TcpListener) -> Result<()> {
+    info!(
+        "Serving metrics on http://{}/metrics",
+        listener
+            .local_addr()
+            .context("Failed to read metrics address")?
+    );
+
+    loop {
+        let (stream, _) = listener
+            .accept()
+            .await
+            .context("Failed to accept metrics connection")?;
+        tokio::spawn(async move {
+            if let Err(e) = http1::Builder::new()
+                .serve_connection(TokioIo::new(stream), service_fn(handle))
+                .await
+            {
+                debug!("Metrics connection error: {}", e);
+            }
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_render_metrics() {
+        let mut downloads = BTreeMap::new();
+        downloads.insert(
+            "snapshot".to_string(),
+            DownloadMetrics {
+                bytes: 2048,
+                started: Instant::now(),
+                start_bytes: 1024,
+            },
+        );
+        let metrics = Metrics {
+            phase: Some("extract_snapshot"),
+            downloads,
+            extract_read_bytes: 25,
+            extract_total_bytes: 100,
+        };
+
+        let body = render_metrics(&metrics, 3);
+        assert!(body.contains("snapshot_downloader_download_bytes{file=\"snapshot\"} 2048"));
+        assert!(
+            body.contains("snapshot_downloader_download_rate_bytes_per_second{file=\"snapshot\"}")
+        );
+        assert!(body.contains("snapshot_downloader_download_retries_total 3"));
+        assert!(body.contains("snapshot_downloader_phase{phase=\"extract_snapshot\"} 1"));
+        assert!(body.contains("snapshot_downloader_extract_read_bytes 25"));
+        assert!(body.contains("snapshot_downloader_extract_total_bytes 100"));
+    }
+
+    #[test]
+    fn test_escape_label() {
+        assert_eq!(escape_label(r#"part "1"\n"#), r#"part \"1\"\\n"#);
+    }
+}
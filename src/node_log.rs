@@ -0,0 +1,127 @@
+use anyhow::{Context, Result};
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+
+/// Append-only log file that rotates to `<file>.1`, `<file>.2`, ... once it exceeds a size limit
+pub struct RotatingLog {
+    path: PathBuf,
+    max_bytes: u64,
+    max_files: usize,
+    file: File,
+    size: u64,
+}
+
+impl RotatingLog {
+    /// Open (or create) the log file, appending to any existing content
+    /// `max_bytes` of 0 disables rotation; `max_files` is how many rotated files to keep
+    pub fn open(path: &Path, max_bytes: u64, max_files: usize) -> Result<Self> {
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent)
+                .with_context(|| format!("Failed to create log directory {}", parent.display()))?;
+        }
+        let file = open_append(path)
+            .with_context(|| format!("Failed to open log file {}", path.display()))?;
+        let size = file.metadata()?.len();
+
+        Ok(Self {
+            path: path.to_path_buf(),
+            max_bytes,
+            max_files,
+            file,
+            size,
+        })
+    }
+
+    /// Append a line, rotating first if it would push the file past `max_bytes`
+    pub fn write_line(&mut self, line: &str) -> io::Result<()> {
+        let len = line.len() as u64 + 1;
+        if self.max_bytes > 0 && self.size > 0 && self.size + len > self.max_bytes {
+            self.rotate()?;
+        }
+        writeln!(self.file, "{line}")?;
+        self.size += len;
+        Ok(())
+    }
+
+    /// Shift `<file>.N` to `<file>.N+1`, dropping the oldest, and start a fresh file
+    fn rotate(&mut self) -> io::Result<()> {
+        if self.max_files == 0 {
+            self.file = File::create(&self.path)?;
+            self.size = 0;
+            return Ok(());
+        }
+
+        let oldest = self.rotated_path(self.max_files);
+        if oldest.exists() {
+            fs::remove_file(&oldest)?;
+        }
+        for index in (1..self.max_files).rev() {
+            let from = self.rotated_path(index);
+            if from.exists() {
+                fs::rename(&from, self.rotated_path(index + 1))?;
+            }
+        }
+        fs::rename(&self.path, self.rotated_path(1))?;
+
+        self.file = open_append(&self.path)?;
+        self.size = 0;
+        Ok(())
+    }
+
+    fn rotated_path(&self, index: usize) -> PathBuf {
+        let mut name = self.path.as_os_str().to_os_string();
+        name.push(format!(".{index}"));
+        PathBuf::from(name)
+    }
+}
+
+fn open_append(path: &Path) -> io::Result<File> {
+    OpenOptions::new().create(true).append(true).open(path)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::tempdir;
+
+    #[test]
+    fn test_lines_land_in_file() -> Result<()> {
+        let temp_dir = tempdir()?;
+        let log_path = temp_dir.path().join("logs/node.log");
+
+        let mut log = RotatingLog::open(&log_path, 0, 3)?;
+        log.write_line("first")?;
+        log.write_line("second")?;
+        drop(log);
+
+        // Reopening appends instead of truncating
+        RotatingLog::open(&log_path, 0, 3)?.write_line("third")?;
+        assert_eq!(fs::read_to_string(&log_path)?, "first\nsecond\nthird\n");
+        Ok(())
+    }
+
+    #[test]
+    fn test_rotation_keeps_max_files() -> Result<()> {
+        let temp_dir = tempdir()?;
+        let log_path = temp_dir.path().join("node.log");
+
+        // Each line is 6 bytes with its newline, so every write after the first rotates
+        let mut log = RotatingLog::open(&log_path, 8, 2)?;
+        for line in ["line1", "line2", "line3", "line4"] {
+            log.write_line(line)?;
+        }
+
+        assert_eq!(fs::read_to_string(&log_path)?, "line4\n");
+        assert_eq!(
+            fs::read_to_string(temp_dir.path().join("node.log.1"))?,
+            "line3\n"
+        );
+        assert_eq!(
+            fs::read_to_string(temp_dir.path().join("node.log.2"))?,
+            "line2\n"
+        );
+        assert!(!temp_dir.path().join("node.log.3").exists());
+        Ok(())
+    }
+}
@@ -0,0 +1,341 @@
+use anyhow::Result;
+use clap::ValueEnum;
+use indicatif::{MultiProgress, ProgressBar, ProgressStyle};
+use serde::Serialize;
+use std::collections::HashMap;
+use std::sync::{Mutex, OnceLock};
+use std::time::{Duration, Instant};
+
+/// Minimum time between two JSON progress events for the same file
+const JSON_PROGRESS_INTERVAL: Duration = Duration::from_millis(500);
+
+/// How often a spinner redraws while no data arrives
+const SPINNER_TICK: Duration = Duration::from_millis(100);
+
+static PROGRESS_MODE: OnceLock<ProgressMode> = OnceLock::new();
+
+/// Aggregate progress of the multipart download in progress, if any
+static ACTIVE_AGGREGATE: Mutex<Option<AggregateState>> = Mutex::new(None);
+
+/// Terminal bars of concurrent downloads, if any are running
+static SHARED_MULTI: Mutex<Option<MultiProgress>> = Mutex::new(None);
+
+/// How progress is reported to the user
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, ValueEnum)]
+pub enum ProgressMode {
+    /// Interactive terminal progress bars
+    #[default]
+    Bar,
+    /// Newline-delimited JSON events on stdout
+    Json,
+}
+
+/// Set the process-wide progress mode (only the first call takes effect)
+pub fn set_mode(mode: ProgressMode) {
+    let _ = PROGRESS_MODE.set(mode);
+}
+
+/// Get the process-wide progress mode
+pub fn mode() -> ProgressMode {
+    PROGRESS_MODE.get().copied().unwrap_or_default()
+}
+
+/// A single machine-readable progress event
+#[derive(Debug, Serialize)]
+struct ProgressEvent<'a> {
+    phase: &'a str,
+    file: &'a str,
+    downloaded: u64,
+    total: u64,
+}
+
+/// A machine-readable event summarizing a finished phase, e.g. the files an extraction wrote
+#[derive(Debug, Serialize)]
+struct SummaryEvent<'a, T> {
+    phase: &'a str,
+    file: &'a str,
+    #[serde(flatten)]
+    summary: &'a T,
+}
+
+/// Print a summary of a finished phase as a JSON event, in JSON mode only
+pub fn emit_summary<T: Serialize>(phase: &str, file: &str, summary: &T) {
+    if mode() != ProgressMode::Json {
+        return;
+    }
+    let event = SummaryEvent {
+        phase,
+        file,
+        summary,
+    };
+    if let Ok(line) = serde_json::to_string(&event) {
+        println!("{line}");
+    }
+}
+
+/// Progress reporter that draws a terminal bar or emits JSON events depending on the mode
+pub enum Progress {
+    Bar(ProgressBar),
+    Json(JsonProgress),
+}
+
+pub struct JsonProgress {
+    phase: String,
+    file: String,
+    total: u64,
+    last_emit: Mutex<Option<Instant>>,
+}
+
+impl JsonProgress {
+    fn emit(&self, position: u64) {
+        let event = ProgressEvent {
+            phase: &self.phase,
+            file: &self.file,
+            downloaded: position,
+            total: self.total,
+        };
+        if let Ok(line) = serde_json::to_string(&event) {
+            println!("{line}");
+        }
+    }
+}
+
+impl Progress {
+    /// Create a progress reporter for the given phase (e.g. "download") and file
+    /// The template is only used for terminal bars
+    pub fn new(phase: &str, file: &str, total: u64, template: &str) -> Result<Self> {
+        match mode() {
+            ProgressMode::Bar => Ok(Self::Bar(attach_to_multi(styled_bar(total, template)?))),
+            ProgressMode::Json => Ok(Self::json(phase, file, total)),
+        }
+    }
+
+    /// Create a progress reporter for a transfer of unknown size
+    /// Terminal output is a spinner rather than a bar; JSON events report a total of 0
+    pub fn new_spinner(phase: &str, file: &str, template: &str) -> Result<Self> {
+        match mode() {
+            ProgressMode::Bar => {
+                let pb = ProgressBar::new_spinner();
+                pb.set_style(ProgressStyle::default_spinner().template(template)?);
+                pb.enable_steady_tick(SPINNER_TICK);
+                Ok(Self::Bar(attach_to_multi(pb)))
+            }
+            ProgressMode::Json => Ok(Self::json(phase, file, 0)),
+        }
+    }
+
+    fn json(phase: &str, file: &str, total: u64) -> Self {
+        Self::Json(JsonProgress {
+            phase: phase.to_string(),
+            file: file.to_string(),
+            total,
+            last_emit: Mutex::new(None),
+        })
+    }
+
+    /// Update the current position, throttling JSON events
+    pub fn set_position(&self, position: u64) {
+        match self {
+            Self::Bar(pb) => pb.set_position(position),
+            Self::Json(json) => {
+                let mut last_emit = json.last_emit.lock().unwrap_or_else(|e| e.into_inner());
+                let due = last_emit.is_none_or(|last| last.elapsed() >= JSON_PROGRESS_INTERVAL);
+                if due {
+                    *last_emit = Some(Instant::now());
+                    json.emit(position);
+                }
+            }
+        }
+    }
+
+    /// Set the text terminal bars draw where their template has `{prefix}`
+    pub fn set_prefix(&self, prefix: impl Into<String>) {
+        if let Self::Bar(pb) = self {
+            pb.set_prefix(prefix.into());
+        }
+    }
+
+    /// Mark progress as complete, always emitting a final JSON event
+    pub fn finish_with_message(self, position: u64, message: impl Into<String>) {
+        match self {
+            Self::Bar(pb) => pb.finish_with_message(message.into()),
+            Self::Json(json) => json.emit(position),
+        }
+    }
+
+    /// Stop reporting without marking progress as complete
+    pub fn abandon(self) {
+        if let Self::Bar(pb) = self {
+            pb.abandon();
+        }
+    }
+}
+
+fn styled_bar(total: u64, template: &str) -> Result<ProgressBar> {
+    let pb = ProgressBar::new(total);
+    let style = ProgressStyle::default_bar()
+        .template(template)?
+        .progress_chars("#>-");
+    pb.set_style(style);
+    Ok(pb)
+}
+
+/// Draw below the aggregate bar or alongside concurrent downloads, if either is active,
+/// instead of fighting them for the terminal
+fn attach_to_multi(pb: ProgressBar) -> ProgressBar {
+    if let Some(AggregateState {
+        multi: Some(multi), ..
+    }) = lock_aggregate().as_ref()
+    {
+        return multi.add(pb);
+    }
+    match lock_shared_multi().as_ref() {
+        Some(multi) => multi.add(pb),
+        None => pb,
+    }
+}
+
+fn lock_shared_multi() -> std::sync::MutexGuard<'static, Option<MultiProgress>> {
+    SHARED_MULTI.lock().unwrap_or_else(|e| e.into_inner())
+}
+
+/// Draws the bars of every download started while this is alive together, so concurrent
+/// downloads each keep their own line
+pub struct SharedProgress {
+    _private: (),
+}
+
+impl SharedProgress {
+    pub fn start() -> Self {
+        *lock_shared_multi() = Some(MultiProgress::new());
+        Self { _private: () }
+    }
+}
+
+impl Drop for SharedProgress {
+    fn drop(&mut self) {
+        lock_shared_multi().take();
+    }
+}
+
+fn lock_aggregate() -> std::sync::MutexGuard<'static, Option<AggregateState>> {
+    ACTIVE_AGGREGATE.lock().unwrap_or_else(|e| e.into_inner())
+}
+
+struct AggregateState {
+    /// Present for terminal bars, so per-part bars can be drawn alongside the aggregate bar
+    multi: Option<MultiProgress>,
+    progress: Progress,
+    /// Latest position of each part, keyed by its file label
+    parts: HashMap<String, u64>,
+}
+
+impl AggregateState {
+    /// Record a part's position, returning the bytes downloaded across all parts
+    fn record(&mut self, part: &str, position: u64) -> u64 {
+        self.parts.insert(part.to_string(), position);
+        self.parts.values().sum()
+    }
+}
+
+/// Overall byte progress across all parts of a multipart download
+/// Download progress reported through `record_part_position` while this is alive counts towards it
+pub struct AggregateProgress {
+    total: u64,
+}
+
+impl AggregateProgress {
+    /// Start tracking `total` bytes across all parts of `file`
+    pub fn start(file: &str, total: u64) -> Result<Self> {
+        let (multi, progress) = match mode() {
+            ProgressMode::Bar => {
+                let multi = lock_shared_multi().clone().unwrap_or_default();
+                let pb = multi.add(styled_bar(
+                    total,
+                    "[{elapsed_precise}] Total [{bar:40.green/blue}] {bytes}/{total_bytes} ({eta})",
+                )?);
+                (Some(multi), Progress::Bar(pb))
+            }
+            ProgressMode::Json => (None, Progress::new("download", file, total, "")?),
+        };
+
+        *lock_aggregate() = Some(AggregateState {
+            multi,
+            progress,
+            parts: HashMap::new(),
+        });
+        Ok(Self { total })
+    }
+
+    /// Mark every part as downloaded
+    pub fn finish(self) {
+        if let Some(state) = lock_aggregate().take() {
+            state
+                .progress
+                .finish_with_message(self.total, "All parts downloaded");
+        }
+    }
+}
+
+impl Drop for AggregateProgress {
+    fn drop(&mut self) {
+        if let Some(state) = lock_aggregate().take() {
+            state.progress.abandon();
+        }
+    }
+}
+
+/// Report how many bytes of a multipart part are on disk, updating the aggregate progress if active
+pub fn record_part_position(part: &str, position: u64) {
+    if let Some(state) = lock_aggregate().as_mut() {
+        let downloaded = state.record(part, position);
+        state.progress.set_position(downloaded);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_progress_event_format() {
+        let event = ProgressEvent {
+            phase: "download",
+            file: "snapshot",
+            downloaded: 123,
+            total: 456,
+        };
+        assert_eq!(
+            serde_json::to_string(&event).unwrap(),
+            r#"{"phase":"download","file":"snapshot","downloaded":123,"total":456}"#
+        );
+    }
+
+    #[test]
+    fn test_summary_event_format() {
+        let event = SummaryEvent {
+            phase: "extract",
+            file: "snapshot.tar.lz4",
+            summary: &serde_json::json!({"files": 3, "dirs": 1}),
+        };
+        assert_eq!(
+            serde_json::to_string(&event).unwrap(),
+            r#"{"phase":"extract","file":"snapshot.tar.lz4","files":3,"dirs":1}"#
+        );
+    }
+
+    #[test]
+    fn test_aggregate_sums_latest_part_positions() {
+        let mut state = AggregateState {
+            multi: None,
+            progress: Progress::Bar(ProgressBar::hidden()),
+            parts: HashMap::new(),
+        };
+
+        assert_eq!(state.record("part 1", 10), 10);
+        assert_eq!(state.record("part 2", 5), 15);
+        // A part's position replaces its previous one, so retries are not counted twice
+        assert_eq!(state.record("part 1", 12), 17);
+        assert_eq!(state.record("part 2", 0), 12);
+    }
+}
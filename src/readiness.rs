@@ -0,0 +1,92 @@
+use anyhow::{Context, Result};
+use serde_json::Value as JsonValue;
+use std::time::Duration;
+use tracing::{debug, info};
+
+/// Poll the node's RPC `/status` endpoint until it reports `catching_up: false`
+/// Connection errors are expected while the node starts up and are retried
+pub async fn wait_for_rpc_ready(client: &reqwest::Client, url: &str, interval: Duration) {
+    loop {
+        match is_catching_up(client, url).await {
+            Ok(false) => {
+                info!("Node reports catching_up: false at {}", url);
+                return;
+            }
+            Ok(true) => debug!("Node is still catching up"),
+            Err(e) => debug!("Node status not available yet: {:#}", e),
+        }
+        tokio::time::sleep(interval).await;
+    }
+}
+
+/// Fetch `/status` and read `sync_info.catching_up`
+async fn is_catching_up(client: &reqwest::Client, url: &str) -> Result<bool> {
+    let body: JsonValue = client
+        .get(url)
+        .send()
+        .await?
+        .error_for_status()?
+        .json()
+        .await
+        .context("Invalid status response")?;
+
+    // CometBFT wraps the status in a JSON-RPC `result`; some proxies return it bare
+    body.get("result")
+        .unwrap_or(&body)
+        .pointer("/sync_info/catching_up")
+        .and_then(JsonValue::as_bool)
+        .context("Status response has no sync_info.catching_up")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::Arc;
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+    use tokio::net::TcpListener;
+
+    /// Mock RPC that reports catching up for the first two requests, then synced
+    async fn spawn_mock_rpc() -> (String, Arc<AtomicUsize>) {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let requests = Arc::new(AtomicUsize::new(0));
+        let counter = requests.clone();
+
+        tokio::spawn(async move {
+            loop {
+                let Ok((mut socket, _)) = listener.accept().await else {
+                    return;
+                };
+                let mut buf = vec![0u8; 4096];
+                let _ = socket.read(&mut buf).await;
+                let catching_up = counter.fetch_add(1, Ordering::SeqCst) < 2;
+                let body = format!(
+                    r#"{{"jsonrpc":"2.0","id":-1,"result":{{"sync_info":{{"latest_block_height":"100","catching_up":{catching_up}}}}}}}"#
+                );
+                let response = format!(
+                    "HTTP/1.1 200 OK\r\ncontent-type: application/json\r\ncontent-length: {}\r\nconnection: close\r\n\r\n{body}",
+                    body.len()
+                );
+                let _ = socket.write_all(response.as_bytes()).await;
+                let _ = socket.shutdown().await;
+            }
+        });
+
+        (format!("http://{addr}/status"), requests)
+    }
+
+    #[tokio::test]
+    async fn test_wait_for_rpc_ready() {
+        let (url, requests) = spawn_mock_rpc().await;
+        let client = reqwest::Client::new();
+
+        tokio::time::timeout(
+            Duration::from_secs(5),
+            wait_for_rpc_ready(&client, &url, Duration::from_millis(10)),
+        )
+        .await
+        .expect("node never reported ready");
+        assert_eq!(requests.load(Ordering::SeqCst), 3);
+    }
+}
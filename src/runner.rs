@@ -1,11 +1,38 @@
 use anyhow::{Context, Result};
-use std::io::{BufRead, BufReader};
+use regex::Regex;
+use std::ffi::OsStr;
+use std::io::{BufRead, BufReader, Write};
+use std::path::{Path, PathBuf};
 use std::process::Command;
-use std::process::Stdio;
+use std::process::{Child, ExitStatus, Stdio};
+use std::sync::{mpsc, Arc, Mutex};
+use std::time::Duration;
 use tokio::sync::oneshot;
 use tracing::{debug, info, warn};
 
-use crate::config::Config;
+use crate::config::{
+    Config, HomeFlagStyle, InitSkipCondition, PostStartTimeoutPolicy, ReadinessMode,
+};
+use crate::node_log::RotatingLog;
+use crate::readiness;
+use crate::systemd;
+
+/// How often to check whether a child process has exited
+const EXIT_POLL_INTERVAL: Duration = Duration::from_millis(100);
+
+/// Which stage of [`terminate_process`] stopped the process
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TerminationStage {
+    /// The process had already exited before termination started
+    AlreadyExited,
+    /// The process exited after SIGTERM within the timeout
+    Sigterm,
+    /// The process ignored SIGTERM and was killed with SIGKILL
+    Sigkill,
+    /// The process was killed right away, on platforms without SIGTERM (`TerminateProcess` on
+    /// Windows)
+    Killed,
+}
 
 pub fn genesis_exists(config: &Config) -> bool {
     let genesis_path = config.home_dir.join("config").join("genesis.json");
@@ -13,9 +40,175 @@ pub fn genesis_exists(config: &Config) -> bool {
     genesis_path.exists()
 }
 
+/// Whether the home holds a parseable `data/priv_validator_state.json`, as snapshots usually do
+fn validator_state_exists(config: &Config) -> bool {
+    let state_path = config
+        .home_dir
+        .join("data")
+        .join("priv_validator_state.json");
+    debug!("Checking for validator state at: {:?}", state_path);
+    std::fs::read(&state_path)
+        .ok()
+        .and_then(|bytes| serde_json::from_slice::<serde_json::Value>(&bytes).ok())
+        .is_some_and(|state| state.get("height").is_some())
+}
+
+/// Why `init` can be skipped, if any of the `init_skip_if` conditions holds
+pub fn init_skip_reason(config: &Config) -> Option<&'static str> {
+    config
+        .init_skip_if
+        .iter()
+        .find_map(|condition| match condition {
+            InitSkipCondition::Genesis => {
+                genesis_exists(config).then_some("genesis.json already exists")
+            }
+            InitSkipCondition::ValidatorState => validator_state_exists(config)
+                .then_some("data/priv_validator_state.json already exists"),
+        })
+}
+
+/// Format the `init` command line for display, without requiring the binary or home to exist yet
+pub fn init_command_line(config: &Config) -> String {
+    let (binary, home) = absolute_paths(config);
+    format_command(&build_init_command(config, &binary, &home))
+}
+
+/// Format the `start` command line for display, without requiring the binary or home to exist yet
+pub fn start_command_line(config: &Config) -> String {
+    let (binary, home) = absolute_paths(config);
+    format_command(&build_start_command(config, &binary, &home))
+}
+
+/// Absolute binary and home paths, falling back to the configured paths
+fn absolute_paths(config: &Config) -> (PathBuf, PathBuf) {
+    let absolute = |path: &Path| std::path::absolute(path).unwrap_or_else(|_| path.to_path_buf());
+    (
+        absolute(&config.workspace_dir.join(&config.binary_relative_path)),
+        absolute(&config.home_dir),
+    )
+}
+
+/// Build `<binary> init <moniker> --chain-id <id> --home <home>` followed by `init_args`
+fn build_init_command(config: &Config, binary: &Path, home: &Path) -> Command {
+    let mut command = Command::new(binary);
+    command
+        .arg("init")
+        .arg(&config.moniker)
+        .arg("--chain-id")
+        .arg(&config.chain_id)
+        .args(home_args(config, home))
+        .args(&config.init_args)
+        .envs(&config.env);
+    command
+}
+
+/// Build `<binary> start --home <home>` followed by `start_args`
+fn build_start_command(config: &Config, binary: &Path, home: &Path) -> Command {
+    let mut command = Command::new(binary);
+    command
+        .arg("start")
+        .args(home_args(config, home))
+        .args(&config.start_args)
+        .envs(&config.env);
+    command
+}
+
+/// Arguments passing the node home, following `home_flag` and `home_flag_style`
+fn home_args<'a>(config: &'a Config, home: &'a Path) -> Vec<&'a OsStr> {
+    match config.home_flag_style {
+        HomeFlagStyle::Flag => vec![OsStr::new(&config.home_flag), home.as_os_str()],
+        HomeFlagStyle::Positional => vec![home.as_os_str()],
+    }
+}
+
+/// Render a command as a space-separated line for logging
+fn format_command(command: &Command) -> String {
+    std::iter::once(command.get_program())
+        .chain(command.get_args())
+        .map(|part| part.to_string_lossy())
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+/// Check the extracted binary is executable and, if `expected_binary_version` is set, reports it
+pub fn verify_binary(config: &Config) -> Result<()> {
+    let binary_path = config.workspace_dir.join(&config.binary_relative_path);
+    check_executable(&binary_path)?;
+
+    let Some(expected) = &config.expected_binary_version else {
+        return Ok(());
+    };
+
+    let output = Command::new(&binary_path)
+        .args(&config.binary_version_args)
+        .envs(&config.env)
+        .output()
+        .with_context(|| {
+            format!(
+                "Failed to run {} (wrong architecture or corrupt binary?)",
+                binary_path.display()
+            )
+        })?;
+    if !output.status.success() {
+        return Err(anyhow::anyhow!(
+            "{} {} failed: {}",
+            binary_path.display(),
+            config.binary_version_args.join(" "),
+            String::from_utf8_lossy(&output.stderr).trim()
+        ));
+    }
+
+    // Some Cosmos SDK versions print the version to stderr
+    let reported = match String::from_utf8_lossy(&output.stdout).trim() {
+        "" => String::from_utf8_lossy(&output.stderr).trim().to_string(),
+        stdout => stdout.to_string(),
+    };
+    if !versions_match(&reported, expected) {
+        return Err(anyhow::anyhow!(
+            "Binary version mismatch: expected {}, {} reported {:?}",
+            expected,
+            binary_path.display(),
+            reported
+        ));
+    }
+
+    info!("Binary version {} matches", reported);
+    Ok(())
+}
+
+fn versions_match(reported: &str, expected: &str) -> bool {
+    reported.trim().trim_start_matches('v') == expected.trim().trim_start_matches('v')
+}
+
+#[cfg(unix)]
+fn check_executable(binary_path: &Path) -> Result<()> {
+    use std::os::unix::fs::PermissionsExt;
+
+    let metadata = std::fs::metadata(binary_path)
+        .with_context(|| format!("Binary not found at {}", binary_path.display()))?;
+    if !metadata.is_file() || metadata.permissions().mode() & 0o111 == 0 {
+        return Err(anyhow::anyhow!(
+            "Binary at {} is not an executable file",
+            binary_path.display()
+        ));
+    }
+    Ok(())
+}
+
+#[cfg(not(unix))]
+fn check_executable(binary_path: &Path) -> Result<()> {
+    if !binary_path.is_file() {
+        return Err(anyhow::anyhow!(
+            "Binary not found at {}",
+            binary_path.display()
+        ));
+    }
+    Ok(())
+}
+
 pub fn run_binary_init(config: &Config) -> Result<()> {
-    if genesis_exists(config) {
-        info!("Genesis file already exists, skipping initialization");
+    if let Some(reason) = init_skip_reason(config) {
+        info!("Skipping initialization, {}", reason);
         return Ok(());
     }
 
@@ -35,14 +228,7 @@ pub fn run_binary_init(config: &Config) -> Result<()> {
         "Running binary init command with chain-id: {} and moniker: {}",
         config.chain_id, config.moniker
     );
-    let output = Command::new(&binary_abs_path)
-        .arg("init")
-        .arg(&config.moniker)
-        .arg("--chain-id")
-        .arg(&config.chain_id)
-        .arg("--home")
-        .arg(&home_abs_path)
-        .output()?;
+    let output = build_init_command(config, &binary_abs_path, &home_abs_path).output()?;
 
     if !output.status.success() {
         let error = String::from_utf8_lossy(&output.stderr);
@@ -57,9 +243,27 @@ pub fn run_binary_init(config: &Config) -> Result<()> {
     Ok(())
 }
 
+/// Spawn `<binary> start` and stream its output; must be called from within a Tokio runtime
 pub fn run_binary_start(
     config: &Config,
-) -> Result<(std::process::Child, Option<oneshot::Receiver<()>>)> {
+) -> Result<(std::process::Child, oneshot::Receiver<PostStartEvent>)> {
+    let node = start_node(config)?;
+    Ok((node.child, node.post_start_rx))
+}
+
+/// A node started by [`start_node`]
+struct StartedNode {
+    child: Child,
+    post_start_rx: oneshot::Receiver<PostStartEvent>,
+    /// Fires once both output streams have ended and every line was written and matched
+    output_done: oneshot::Receiver<()>,
+}
+
+/// How long to wait for the rest of the node's output once it has exited; a process the node
+/// started can hold its stdout open after it
+const OUTPUT_DRAIN_TIMEOUT: Duration = Duration::from_secs(2);
+
+fn start_node(config: &Config) -> Result<StartedNode> {
     info!("Starting binary...");
 
     let binary_path = config.workspace_dir.join(&config.binary_relative_path);
@@ -71,20 +275,27 @@ pub fn run_binary_start(
     debug!("Binary path: {:?}", binary_abs_path);
     debug!("Home path: {:?}", home_abs_path);
 
-    // Print the command for the user to run later
-    let binary_abs_path_str = binary_abs_path.to_string_lossy();
-    let home_abs_path_str = home_abs_path.to_string_lossy();
-    let command_str = format!("{binary_abs_path_str} start --home {home_abs_path_str}");
+    let mut command = build_start_command(config, &binary_abs_path, &home_abs_path);
 
+    // Print the command for the user to run later
     info!("To start the node later, run the following command:");
-    info!("{}", command_str);
+    info!("{}", format_command(&command));
+
+    // Optional log file shared by the stdout and stderr readers, opened (like everything else
+    // that can fail) before the node is spawned so an error cannot leave it running
+    let node_log = match &config.log_file {
+        Some(path) => {
+            info!("Writing node output to {}", path.display());
+            let log = RotatingLog::open(path, config.log_max_bytes, config.log_max_files)?;
+            Some(Arc::new(Mutex::new(log)))
+        }
+        None => None,
+    };
+    let post_start_pattern = PostStartMatcher::from_config(config)?;
 
     // Run the binary start command
     info!("Running binary start command");
-    let mut child = Command::new(&binary_abs_path)
-        .arg("start")
-        .arg("--home")
-        .arg(&home_abs_path)
+    let mut child = command
         .stdout(std::process::Stdio::piped())
         .stderr(std::process::Stdio::piped())
         .spawn()
@@ -92,86 +303,741 @@ pub fn run_binary_start(
 
     info!("Binary process started, streaming logs...");
 
-    // Get the post start command and pattern from config
+    // Get the post start command and readiness trigger from config
     let post_start_command = config.post_start_command.clone();
-    let post_start_pattern = config
-        .post_start_pattern
-        .clone()
-        .unwrap_or_else(|| "committed state".to_string());
+    let post_start_dir = config.command_working_dirs.post_start.clone();
+    let command_shell = config.command_shell.clone();
     let stop_after_post_start = config.stop_after_post_start;
 
-    // Channel to signal when post start pattern is detected and we should stop
-    let (shutdown_tx, shutdown_rx) = if stop_after_post_start {
-        let (tx, rx) = oneshot::channel();
-        (Some(tx), Some(rx))
-    } else {
-        (None, None)
+    // Fired by the log matcher or the RPC poller once the node is ready
+    let (mut ready_tx, ready_rx) = oneshot::channel();
+    // Tells the caller to stop the node, after the post-start command or on a readiness timeout
+    let (mut event_tx, event_rx) = oneshot::channel();
+
+    // In log mode the stdout reader looks for the pattern; in RPC mode a poller fires readiness
+    let (log_ready_tx, log_pattern) = match config.readiness.mode {
+        ReadinessMode::Log => (Some(ready_tx), Some(post_start_pattern)),
+        ReadinessMode::Rpc => {
+            let rpc_url = config.readiness.rpc_url.clone();
+            let interval = Duration::from_secs(config.readiness.interval_secs);
+            info!(
+                "Polling {} every {:?} to detect sync completion",
+                rpc_url, interval
+            );
+
+            tokio::spawn(async move {
+                let client = reqwest::Client::new();
+                tokio::select! {
+                    _ = readiness::wait_for_rpc_ready(&client, &rpc_url, interval) => {
+                        let _ = ready_tx.send(());
+                    }
+                    // Stop polling once nobody is waiting for readiness anymore
+                    _ = ready_tx.closed() => {}
+                }
+            });
+            (None, None)
+        }
     };
 
+    // Wait for readiness, bounded by post_start_timeout_secs, then run the post-start command
+    let post_start_timeout = config.post_start_timeout_secs.map(Duration::from_secs);
+    let on_timeout = config.post_start_on_timeout;
+    tokio::spawn(async move {
+        let ready = async {
+            match post_start_timeout {
+                Some(timeout) => tokio::time::timeout(timeout, ready_rx).await.ok(),
+                None => Some(ready_rx.await),
+            }
+        };
+        let ready = tokio::select! {
+            ready = ready => ready,
+            // The node exited and the caller stopped listening
+            _ = event_tx.closed() => return,
+        };
+
+        match ready {
+            Some(Ok(())) => {
+                systemd::notify_ready();
+                systemd::notify_status("Node is ready");
+                let _ = tokio::task::spawn_blocking(move || {
+                    let post_start = post_start_command.as_deref().map(|command| ShellCommand {
+                        command,
+                        shell: &command_shell,
+                        working_dir: post_start_dir.as_deref(),
+                    });
+                    on_node_ready(post_start.as_ref(), stop_after_post_start, event_tx)
+                })
+                .await;
+            }
+            // Node output ended before it became ready
+            Some(Err(_)) => {}
+            None => {
+                let secs = post_start_timeout.unwrap_or_default().as_secs();
+                match on_timeout {
+                    PostStartTimeoutPolicy::Fail => {
+                        warn!("Node did not become ready within {}s, shutting down", secs);
+                        let _ = event_tx.send(PostStartEvent::TimedOut);
+                    }
+                    PostStartTimeoutPolicy::Continue => {
+                        warn!(
+                            "Node did not become ready within {}s, continuing without the post-start command",
+                            secs
+                        );
+                    }
+                }
+            }
+        }
+    });
+
+    // Both streams go through one channel so a single writer prints whole lines, appends them to
+    // the log file and looks for the readiness pattern
+    let (line_tx, line_rx) = mpsc::channel();
     if let Some(stdout) = child.stdout.take() {
-        let stdout_reader = BufReader::new(stdout);
-        let post_start_cmd = post_start_command.clone();
-        let pattern = post_start_pattern.clone();
-        let mut shutdown_sender = shutdown_tx;
-        let mut pattern_detected = false;
+        let line_tx = line_tx.clone();
+        std::thread::spawn(move || forward_lines(stdout, OutputStream::Stdout, &line_tx));
+    }
+    if let Some(stderr) = child.stderr.take() {
+        std::thread::spawn(move || forward_lines(stderr, OutputStream::Stderr, &line_tx));
+    }
+    let (output_done_tx, output_done) = oneshot::channel();
+    std::thread::spawn(move || {
+        write_node_output(
+            &line_rx,
+            node_log.as_deref(),
+            log_pattern,
+            log_ready_tx,
+            &mut std::io::stdout(),
+            &mut std::io::stderr(),
+        );
+        let _ = output_done_tx.send(());
+    });
 
-        std::thread::spawn(move || {
-            for line in stdout_reader.lines().map_while(Result::ok) {
-                println!("[STDOUT] {line}");
-
-                // Check for post-start pattern detection (only once)
-                if !pattern_detected && line.contains(&pattern) {
-                    pattern_detected = true;
-                    info!("Detected pattern '{}' in stdout output", pattern);
-
-                    // Execute post start command if configured
-                    let command_success = if let Some(ref cmd) = post_start_cmd {
-                        execute_post_start_command(cmd).is_ok()
-                    } else {
-                        info!("No post start command configured, proceeding to shutdown");
-                        true
-                    };
-
-                    // Always shutdown - whether command succeeded or failed
-                    if command_success {
-                        info!("Post-start command succeeded. Shutting down binary process.");
-                    } else {
-                        warn!("Post-start command failed. Shutting down binary process.");
+    Ok(StartedNode {
+        child,
+        post_start_rx: event_rx,
+        output_done,
+    })
+}
+
+/// Start the node and supervise it until it exits cleanly or a shutdown is requested
+/// Failed exits are restarted according to `restart_policy`; a shutdown request or the
+/// post-start trigger terminates the node instead
+/// Returns the exit code to exit with: 0 for a clean exit or shutdown, otherwise the code of the
+/// node's last failed run
+pub async fn supervise_node(
+    config: &Config,
+    mut shutdown_rx: oneshot::Receiver<()>,
+) -> Result<i32> {
+    let shutdown_timeout = Duration::from_secs(config.shutdown_timeout_secs);
+    let policy = &config.restart_policy;
+    let mut restarts = 0;
+
+    loop {
+        // Start the binary and get the process handle
+        let StartedNode {
+            child: mut binary_process,
+            post_start_rx,
+            output_done,
+        } = start_node(config).context("Failed to start binary")?;
+        let process_id = binary_process.id();
+        let _pid_file = match config.pid_file.as_deref() {
+            Some(path) => match PidFile::create(path, process_id) {
+                Ok(pid_file) => Some(pid_file),
+                Err(e) => {
+                    // Nothing would supervise the node, so stop it rather than leave it running
+                    stop_node(&mut binary_process, shutdown_timeout).await;
+                    return Err(e);
+                }
+            },
+            None => None,
+        };
+
+        // Block until we receive a shutdown signal, a post-start event, OR the process exits on its own
+        let end = tokio::select! {
+            _ = &mut shutdown_rx => {
+                info!("Shutdown signal received, terminating process {}", process_id);
+                RunEnd::Shutdown
+            }
+            event = async {
+                match post_start_rx.await {
+                    Ok(event) => event,
+                    // No event will be sent for this run, wait for the other branches
+                    Err(_) => std::future::pending().await,
+                }
+            } => RunEnd::PostStart(event),
+            exit_status = wait_for_exit(&mut binary_process) => RunEnd::Exited(exit_status),
+        };
+
+        let status = match end {
+            RunEnd::Exited(Ok(status)) => {
+                // The exit can be seen before the writer has caught up with the last lines, so
+                // let it finish first: those lines are printed and logged, and a readiness
+                // pattern in them is matched, before the exit is handled
+                let _ = tokio::time::timeout(OUTPUT_DRAIN_TIMEOUT, output_done).await;
+                status
+            }
+            RunEnd::Exited(Err(e)) => {
+                warn!("Error waiting for binary process: {}", e);
+                return Ok(0);
+            }
+            RunEnd::Shutdown => {
+                if let Some(command) = &config.pre_shutdown_command {
+                    let working_dir = config.command_working_dirs.pre_shutdown.as_deref();
+                    let cmd = ShellCommand::new(config, command, working_dir);
+                    if let Err(e) = execute_pre_shutdown_command(&cmd, shutdown_timeout).await {
+                        warn!("{:#}, terminating the node anyway", e);
+                    }
+                }
+                stop_node(&mut binary_process, shutdown_timeout).await;
+                return Ok(0);
+            }
+            RunEnd::PostStart(PostStartEvent::Completed) => {
+                info!(
+                    "Post start command completed, terminating process {} and exiting program",
+                    process_id
+                );
+                stop_node(&mut binary_process, shutdown_timeout).await;
+                return Ok(0);
+            }
+            RunEnd::PostStart(PostStartEvent::TimedOut) => {
+                warn!(
+                    "Post-start readiness timed out, terminating process {}",
+                    process_id
+                );
+                stop_node(&mut binary_process, shutdown_timeout).await;
+                return Err(anyhow::anyhow!(
+                    "Node did not become ready within {}s",
+                    config.post_start_timeout_secs.unwrap_or_default()
+                ));
+            }
+        };
+
+        info!("Binary process exited with status: {:?}", status);
+        if status.success() {
+            return Ok(0);
+        }
+        if restarts >= policy.max_restarts {
+            if policy.max_restarts > 0 {
+                warn!("Giving up after {} restarts", restarts);
+            }
+            return Ok(exit_code(&status));
+        }
+
+        let delay = policy.calculate_delay(restarts);
+        restarts += 1;
+        warn!(
+            "Binary process exited unexpectedly, restarting in {:?} (restart {}/{})",
+            delay, restarts, policy.max_restarts
+        );
+        tokio::select! {
+            _ = &mut shutdown_rx => {
+                info!("Shutdown signal received, not restarting");
+                return Ok(0);
+            }
+            _ = tokio::time::sleep(delay) => {}
+        }
+    }
+}
+
+/// Process exit code for a node exit status, using the shell's 128 + signal for a killed node
+pub fn exit_code(status: &ExitStatus) -> i32 {
+    if let Some(code) = status.code() {
+        return code;
+    }
+    #[cfg(unix)]
+    {
+        use std::os::unix::process::ExitStatusExt;
+        if let Some(signal) = status.signal() {
+            return 128 + signal;
+        }
+    }
+    1
+}
+
+/// How one run of the supervised node ended
+enum RunEnd {
+    Shutdown,
+    PostStart(PostStartEvent),
+    Exited(std::io::Result<ExitStatus>),
+}
+
+/// Terminate the node, logging rather than failing if it cannot be stopped cleanly
+async fn stop_node(child: &mut Child, timeout: Duration) {
+    let process_id = child.id();
+    match terminate_process(child, timeout).await {
+        Ok((stage, status)) => info!(
+            "Process {} terminated ({:?}) with status: {}",
+            process_id, stage, status
+        ),
+        Err(e) => warn!("Failed to terminate process {}: {:#}", process_id, e),
+    }
+}
+
+/// Wait for a child process to exit without blocking the async runtime
+pub async fn wait_for_exit(child: &mut Child) -> std::io::Result<ExitStatus> {
+    loop {
+        if let Some(status) = child.try_wait()? {
+            return Ok(status);
+        }
+        tokio::time::sleep(EXIT_POLL_INTERVAL).await;
+    }
+}
+
+/// Stop a child process: send SIGTERM, wait up to `timeout`, then escalate to SIGKILL
+///
+/// A node killed mid-commit can corrupt its database, so it gets the whole timeout to shut down
+/// cleanly. Without SIGTERM it is killed right away. Returns how the process was stopped and
+/// its final exit status
+pub async fn terminate_process(
+    child: &mut Child,
+    timeout: Duration,
+) -> Result<(TerminationStage, ExitStatus)> {
+    let pid = child.id();
+    if let Some(status) = child.try_wait()? {
+        info!("Process {} already exited with status: {}", pid, status);
+        return Ok((TerminationStage::AlreadyExited, status));
+    }
+    stop_running_process(child, timeout).await
+}
+
+#[cfg(unix)]
+async fn stop_running_process(
+    child: &mut Child,
+    timeout: Duration,
+) -> Result<(TerminationStage, ExitStatus)> {
+    let pid = child.id();
+    info!(
+        "Sending SIGTERM to process {}, waiting up to {}s",
+        pid,
+        timeout.as_secs()
+    );
+    send_sigterm(child)?;
+
+    match tokio::time::timeout(timeout, wait_for_exit(child)).await {
+        Ok(status) => {
+            let status = status.context("Failed to wait for process after SIGTERM")?;
+            info!(
+                "Process {} exited after SIGTERM with status: {}",
+                pid, status
+            );
+            Ok((TerminationStage::Sigterm, status))
+        }
+        Err(_) => {
+            warn!(
+                "Process {} did not exit within {}s, sending SIGKILL",
+                pid,
+                timeout.as_secs()
+            );
+            child.kill().context("Failed to send SIGKILL")?;
+            let status = child
+                .wait()
+                .context("Failed to wait for process after SIGKILL")?;
+            info!(
+                "Process {} exited after SIGKILL with status: {}",
+                pid, status
+            );
+            Ok((TerminationStage::Sigkill, status))
+        }
+    }
+}
+
+#[cfg(unix)]
+fn send_sigterm(child: &Child) -> Result<()> {
+    let pid = libc::pid_t::try_from(child.id()).context("Process id out of range")?;
+    // SAFETY: kill only sends a signal to the given pid and has no memory safety requirements
+    if unsafe { libc::kill(pid, libc::SIGTERM) } != 0 {
+        return Err(std::io::Error::last_os_error()).context("Failed to send SIGTERM");
+    }
+    Ok(())
+}
+
+/// Without SIGTERM there is no way to ask for a clean shutdown, so the process is killed right
+/// away (`TerminateProcess` on Windows)
+#[cfg(not(unix))]
+async fn stop_running_process(
+    child: &mut Child,
+    _timeout: Duration,
+) -> Result<(TerminationStage, ExitStatus)> {
+    let pid = child.id();
+    info!("Killing process {}", pid);
+    child.kill().context("Failed to kill process")?;
+    let status = child
+        .wait()
+        .context("Failed to wait for process after killing it")?;
+    info!("Process {} was killed with status: {}", pid, status);
+    Ok((TerminationStage::Killed, status))
+}
+
+/// Why the node should be stopped once it has started
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PostStartEvent {
+    /// The node became ready and the post-start command ran (with `stop_after_post_start`)
+    Completed,
+    /// The node did not become ready within `post_start_timeout_secs` (with `on_timeout: fail`)
+    TimedOut,
+}
+
+/// Matches node stdout lines against `post_start_pattern`
+pub enum PostStartMatcher {
+    Substring(String),
+    Regex(Regex),
+}
+
+impl PostStartMatcher {
+    /// Build the matcher from config, defaulting to the "committed state" substring
+    pub fn from_config(config: &Config) -> Result<Self> {
+        let pattern = config
+            .post_start_pattern
+            .clone()
+            .unwrap_or_else(|| "committed state".to_string());
+        if config.post_start_pattern_is_regex {
+            let regex = Regex::new(&pattern)
+                .with_context(|| format!("Invalid post_start_pattern regex '{pattern}'"))?;
+            Ok(Self::Regex(regex))
+        } else {
+            Ok(Self::Substring(pattern))
+        }
+    }
+
+    pub fn is_match(&self, line: &str) -> bool {
+        match self {
+            Self::Substring(pattern) => line.contains(pattern.as_str()),
+            Self::Regex(regex) => regex.is_match(line),
+        }
+    }
+}
+
+impl std::fmt::Display for PostStartMatcher {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Substring(pattern) => write!(f, "{pattern}"),
+            Self::Regex(regex) => write!(f, "{}", regex.as_str()),
+        }
+    }
+}
+
+/// Run the post-start command once the node is ready, then ask the caller to stop the node if configured
+fn on_node_ready(
+    post_start_cmd: Option<&ShellCommand>,
+    stop_after_post_start: bool,
+    event_tx: oneshot::Sender<PostStartEvent>,
+) {
+    // Execute post start command if configured
+    let command_success = if let Some(cmd) = post_start_cmd {
+        execute_post_start_command(cmd).is_ok()
+    } else {
+        info!("No post start command configured, proceeding to shutdown");
+        true
+    };
+
+    if !stop_after_post_start {
+        return;
+    }
+
+    // Always shutdown - whether command succeeded or failed
+    if command_success {
+        info!("Post-start command succeeded. Shutting down binary process.");
+    } else {
+        warn!("Post-start command failed. Shutting down binary process.");
+    }
+    let _ = event_tx.send(PostStartEvent::Completed);
+}
+
+/// The node's PID written to `pid_file`, removed again when dropped
+struct PidFile {
+    path: PathBuf,
+}
+
+impl PidFile {
+    /// Write `pid` to `path`, replacing a file left behind by an earlier run
+    fn create(path: &Path, pid: u32) -> Result<Self> {
+        if let Ok(stale) = std::fs::read_to_string(path) {
+            warn!(
+                "Replacing stale PID file {} (pid {})",
+                path.display(),
+                stale.trim()
+            );
+        }
+        if let Some(parent) = path.parent().filter(|p| !p.as_os_str().is_empty()) {
+            std::fs::create_dir_all(parent)
+                .with_context(|| format!("Failed to create {}", parent.display()))?;
+        }
+        std::fs::write(path, format!("{pid}\n"))
+            .with_context(|| format!("Failed to write PID file {}", path.display()))?;
+        debug!("Wrote PID {} to {}", pid, path.display());
+        Ok(Self {
+            path: path.to_path_buf(),
+        })
+    }
+}
+
+impl Drop for PidFile {
+    fn drop(&mut self) {
+        match std::fs::remove_file(&self.path) {
+            Err(e) if e.kind() != std::io::ErrorKind::NotFound => {
+                warn!("Failed to remove PID file {}: {}", self.path.display(), e);
+            }
+            _ => {}
+        }
+    }
+}
+
+/// Which of the node's output streams a line came from
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum OutputStream {
+    Stdout,
+    Stderr,
+}
+
+/// Send each line read from `reader` to the output writer until the stream ends
+/// Lines are split on raw newlines, so invalid UTF-8 is replaced rather than ending the stream
+fn forward_lines(
+    reader: impl std::io::Read,
+    stream: OutputStream,
+    tx: &mpsc::Sender<(OutputStream, String)>,
+) {
+    let mut reader = BufReader::new(reader);
+    let mut buf = Vec::new();
+    loop {
+        buf.clear();
+        match reader.read_until(b'\n', &mut buf) {
+            Ok(0) => break,
+            Ok(_) => {
+                if buf.ends_with(b"\n") {
+                    buf.pop();
+                    if buf.ends_with(b"\r") {
+                        buf.pop();
                     }
+                }
+                let line = String::from_utf8_lossy(&buf).into_owned();
+                if tx.send((stream, line)).is_err() {
+                    break;
+                }
+            }
+            Err(e) if e.kind() == std::io::ErrorKind::Interrupted => {}
+            Err(e) => {
+                warn!("Failed to read node {:?}: {}", stream, e);
+                break;
+            }
+        }
+    }
+}
+
+/// Print node output lines as they arrive, one whole line per write, until both streams end
+/// Stdout lines are checked for `pattern`, firing `ready_tx` on the first match
+fn write_node_output(
+    rx: &mpsc::Receiver<(OutputStream, String)>,
+    node_log: Option<&Mutex<RotatingLog>>,
+    mut pattern: Option<PostStartMatcher>,
+    mut ready_tx: Option<oneshot::Sender<()>>,
+    stdout: &mut impl Write,
+    stderr: &mut impl Write,
+) {
+    for (stream, line) in rx {
+        // Output that cannot be printed is still logged and matched
+        let _ = match stream {
+            OutputStream::Stdout => {
+                writeln!(stdout, "[STDOUT] {line}").and_then(|_| stdout.flush())
+            }
+            OutputStream::Stderr => {
+                writeln!(stderr, "[STDERR] {line}").and_then(|_| stderr.flush())
+            }
+        };
+        write_node_log(node_log, &line);
+
+        // Check for post-start pattern detection (only once)
+        if stream == OutputStream::Stdout && pattern.as_ref().is_some_and(|p| p.is_match(&line)) {
+            if let Some(pattern) = pattern.take() {
+                info!("Detected pattern '{}' in stdout output", pattern);
+            }
+            if let Some(tx) = ready_tx.take() {
+                let _ = tx.send(());
+            }
+        }
+    }
+}
+
+/// Append a line of node output to the log file, if one is configured
+fn write_node_log(node_log: Option<&Mutex<RotatingLog>>, line: &str) {
+    if let Some(node_log) = node_log {
+        let mut node_log = node_log.lock().unwrap_or_else(|e| e.into_inner());
+        if let Err(e) = node_log.write_line(line) {
+            warn!("Failed to write node log: {}", e);
+        }
+    }
+}
+
+/// Default `command_shell`
+pub const DEFAULT_COMMAND_SHELL: &str = "sh";
+
+/// `command_shell` that runs commands without a shell, split into arguments by `split_command`
+pub const NO_SHELL: &str = "no-shell";
+
+/// A configured command and how to run it
+#[derive(Debug, Clone, Copy)]
+pub struct ShellCommand<'a> {
+    pub command: &'a str,
+    /// The configured `command_shell`
+    pub shell: &'a str,
+    /// Directory to run the command in, the current one if `None`
+    pub working_dir: Option<&'a Path>,
+}
+
+impl<'a> ShellCommand<'a> {
+    /// `command` run with the configured shell in `working_dir`
+    pub fn new(config: &'a Config, command: &'a str, working_dir: Option<&'a Path>) -> Self {
+        Self {
+            command,
+            shell: &config.command_shell,
+            working_dir,
+        }
+    }
+}
 
-                    if let Some(tx) = shutdown_sender.take() {
-                        let _ = tx.send(());
+/// Start a configured command with its output piped
+pub fn spawn_command(cmd: &ShellCommand) -> Result<Child> {
+    let mut child = build_shell_command(cmd.command, cmd.shell, cmd.working_dir)?;
+    child
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .with_context(|| match cmd.working_dir {
+            Some(dir) => format!("Failed to start '{}' in {}", cmd.command, dir.display()),
+            None => format!("Failed to start '{}'", cmd.command),
+        })
+}
+
+/// The command that runs `command` as `<shell> -c <command>`, where `shell` may carry its own
+/// arguments (e.g. `bash -eo pipefail`), or split into arguments for `no-shell`
+fn build_shell_command(command: &str, shell: &str, working_dir: Option<&Path>) -> Result<Command> {
+    let mut child = if shell == NO_SHELL {
+        let args = split_command(command)?;
+        let mut child = Command::new(&args[0]);
+        child.args(&args[1..]);
+        child
+    } else {
+        let mut words = shell.split_whitespace();
+        let program = words.next().context("command_shell is empty")?;
+        let mut child = Command::new(program);
+        child.args(words).arg("-c").arg(command);
+        child
+    };
+    if let Some(dir) = working_dir {
+        child.current_dir(dir);
+    }
+    Ok(child)
+}
+
+/// Split a command into arguments at whitespace, keeping quoted text and backslash-escaped
+/// characters together like a POSIX shell, but without expanding variables, globs or redirections
+pub fn split_command(command: &str) -> Result<Vec<String>> {
+    let unterminated = || anyhow::anyhow!("Unterminated quote or escape in command: {}", command);
+    let mut args = Vec::new();
+    // None between arguments, so `''` still makes an empty argument
+    let mut current: Option<String> = None;
+    let mut chars = command.chars();
+    while let Some(c) = chars.next() {
+        match c {
+            c if c.is_whitespace() => args.extend(current.take()),
+            '\'' => {
+                let arg = current.get_or_insert_with(String::new);
+                loop {
+                    match chars.next().ok_or_else(unterminated)? {
+                        '\'' => break,
+                        c => arg.push(c),
+                    }
+                }
+            }
+            '"' => {
+                let arg = current.get_or_insert_with(String::new);
+                loop {
+                    match chars.next().ok_or_else(unterminated)? {
+                        '"' => break,
+                        '\\' => match chars.next().ok_or_else(unterminated)? {
+                            c @ ('"' | '\\' | '$' | '`') => arg.push(c),
+                            c => {
+                                arg.push('\\');
+                                arg.push(c);
+                            }
+                        },
+                        c => arg.push(c),
                     }
                 }
             }
+            '\\' => {
+                let c = chars.next().ok_or_else(unterminated)?;
+                current.get_or_insert_with(String::new).push(c);
+            }
+            c => current.get_or_insert_with(String::new).push(c),
+        }
+    }
+    args.extend(current);
+    if args.is_empty() {
+        return Err(anyhow::anyhow!("Command is empty"));
+    }
+    Ok(args)
+}
+
+/// Execute the post binary extract command
+pub fn execute_post_binary_extract_command(cmd: &ShellCommand) -> Result<()> {
+    info!("Executing post-binary-extract command: {}", cmd.command);
+
+    let mut child = spawn_command(cmd).context("Failed to execute post-binary-extract command")?;
+
+    let mut handles = Vec::new();
+
+    // Stream stdout in real-time
+    if let Some(stdout) = child.stdout.take() {
+        let stdout_reader = BufReader::new(stdout);
+        let handle = std::thread::spawn(move || {
+            for line in stdout_reader.lines().map_while(Result::ok) {
+                info!("[Post-binary-extract stdout] {}", line);
+            }
         });
+        handles.push(handle);
     }
 
-    // Stream stderr (no pattern detection, just logging)
+    // Stream stderr in real-time
     if let Some(stderr) = child.stderr.take() {
         let stderr_reader = BufReader::new(stderr);
-
-        std::thread::spawn(move || {
+        let handle = std::thread::spawn(move || {
             for line in stderr_reader.lines().map_while(Result::ok) {
-                eprintln!("[STDERR] {line}");
+                warn!("[Post-binary-extract stderr] {}", line);
             }
         });
+        handles.push(handle);
     }
 
-    // Return the child process handle and optional shutdown receiver
-    Ok((child, shutdown_rx))
+    let status = child
+        .wait()
+        .context("Failed to wait for post-binary-extract command")?;
+
+    for handle in handles {
+        let _ = handle.join();
+    }
+
+    if status.success() {
+        info!("Post-binary-extract command executed successfully");
+        Ok(())
+    } else {
+        let exit_code = status.code().unwrap_or(-1);
+        warn!(
+            "Post-binary-extract command failed with exit code: {}",
+            exit_code
+        );
+        Err(anyhow::anyhow!(
+            "Post-binary-extract command failed with exit code: {}",
+            exit_code
+        ))
+    }
 }
 
 /// Execute the post snapshot download command
-pub fn execute_post_snapshot_download_command(command: &str) -> Result<()> {
-    info!("Executing post-snapshot-download command: {}", command);
+pub fn execute_post_snapshot_download_command(cmd: &ShellCommand) -> Result<()> {
+    info!("Executing post-snapshot-download command: {}", cmd.command);
 
-    let mut child = Command::new("sh")
-        .args(["-c", command])
-        .stdout(Stdio::piped())
-        .stderr(Stdio::piped())
-        .spawn()
-        .context("Failed to execute post-snapshot-download command")?;
+    let mut child =
+        spawn_command(cmd).context("Failed to execute post-snapshot-download command")?;
 
     let mut handles = Vec::new();
 
@@ -222,15 +1088,10 @@ pub fn execute_post_snapshot_download_command(command: &str) -> Result<()> {
 }
 
 /// Execute the pre start command
-pub fn execute_pre_start_command(command: &str) -> Result<()> {
-    info!("Executing pre-start command: {}", command);
+pub fn execute_pre_start_command(cmd: &ShellCommand) -> Result<()> {
+    info!("Executing pre-start command: {}", cmd.command);
 
-    let mut child = Command::new("sh")
-        .args(["-c", command])
-        .stdout(Stdio::piped())
-        .stderr(Stdio::piped())
-        .spawn()
-        .context("Failed to execute pre-start command")?;
+    let mut child = spawn_command(cmd).context("Failed to execute pre-start command")?;
 
     let mut handles = Vec::new();
 
@@ -277,16 +1138,73 @@ pub fn execute_pre_start_command(command: &str) -> Result<()> {
     }
 }
 
+/// Execute the pre-shutdown command, killing it if it has not finished within `timeout`
+pub async fn execute_pre_shutdown_command(cmd: &ShellCommand<'_>, timeout: Duration) -> Result<()> {
+    info!("Executing pre-shutdown command: {}", cmd.command);
+    let deadline = tokio::time::Instant::now() + timeout;
+
+    let mut child = spawn_command(cmd).context("Failed to execute pre-shutdown command")?;
+
+    // Closed once both output threads have ended
+    let (output_tx, mut output_done) = tokio::sync::mpsc::channel::<()>(1);
+
+    // Stream stdout in real-time
+    if let Some(stdout) = child.stdout.take() {
+        let stdout_reader = BufReader::new(stdout);
+        let output_tx = output_tx.clone();
+        std::thread::spawn(move || {
+            for line in stdout_reader.lines().map_while(Result::ok) {
+                info!("[Pre-shutdown stdout] {}", line);
+            }
+            drop(output_tx);
+        });
+    }
+
+    // Stream stderr in real-time
+    if let Some(stderr) = child.stderr.take() {
+        let stderr_reader = BufReader::new(stderr);
+        std::thread::spawn(move || {
+            for line in stderr_reader.lines().map_while(Result::ok) {
+                warn!("[Pre-shutdown stderr] {}", line);
+            }
+            drop(output_tx);
+        });
+    }
+
+    let status = match tokio::time::timeout_at(deadline, wait_for_exit(&mut child)).await {
+        Ok(status) => status.context("Failed to wait for pre-shutdown command")?,
+        Err(_) => {
+            // Its output threads end once anything it started closes the pipes
+            let _ = child.kill();
+            let _ = child.wait();
+            return Err(anyhow::anyhow!(
+                "Pre-shutdown command did not finish within {}s and was killed",
+                timeout.as_secs()
+            ));
+        }
+    };
+
+    // A process the command left running in the background can hold its output open, so the
+    // rest of the output is only waited for until the deadline
+    let _ = tokio::time::timeout_at(deadline, output_done.recv()).await;
+
+    if status.success() {
+        info!("Pre-shutdown command executed successfully");
+        Ok(())
+    } else {
+        let exit_code = status.code().unwrap_or(-1);
+        Err(anyhow::anyhow!(
+            "Pre-shutdown command failed with exit code: {}",
+            exit_code
+        ))
+    }
+}
+
 /// Execute the post start command
-pub fn execute_post_start_command(command: &str) -> Result<()> {
-    info!("Executing post-start command: {}", command);
+pub fn execute_post_start_command(cmd: &ShellCommand) -> Result<()> {
+    info!("Executing post-start command: {}", cmd.command);
 
-    let mut child = Command::new("sh")
-        .args(["-c", command])
-        .stdout(Stdio::piped())
-        .stderr(Stdio::piped())
-        .spawn()
-        .context("Failed to execute post-start command")?;
+    let mut child = spawn_command(cmd).context("Failed to execute post-start command")?;
 
     let mut handles = Vec::new();
 
@@ -332,3 +1250,649 @@ pub fn execute_post_start_command(command: &str) -> Result<()> {
         ))
     }
 }
+
+#[cfg(all(test, unix))]
+mod tests {
+    use super::*;
+    use std::fs;
+    use std::os::unix::fs::PermissionsExt;
+    use tempfile::tempdir;
+
+    /// Load a minimal config rooted at `dir`, with extra YAML appended
+    fn test_config(dir: &Path, extra: &str) -> Result<Config> {
+        let config_path = dir.join("config.yaml");
+        fs::write(
+            &config_path,
+            format!(
+                r#"
+snapshot_url: "https://example.com/snapshot.tar.gz"
+binary_url: "https://example.com/gaiad.tar.gz"
+binary_relative_path: "bin/gaiad"
+chain_id: "cosmoshub-4"
+moniker: "test-node"
+base_dir: "{}"
+{extra}"#,
+                dir.display()
+            ),
+        )?;
+        Config::from_file(&config_path)
+    }
+
+    #[test]
+    fn test_extra_args_are_appended() -> Result<()> {
+        let temp_dir = tempdir()?;
+        let config = test_config(
+            temp_dir.path(),
+            "init_args: [\"--overwrite\"]\nstart_args: [\"--pruning\", \"custom\"]\n",
+        )?;
+        let (binary, home) = absolute_paths(&config);
+
+        let init = build_init_command(&config, &binary, &home);
+        let init_args: Vec<_> = init.get_args().collect();
+        assert_eq!(init_args.last().unwrap(), &"--overwrite");
+
+        let start = build_start_command(&config, &binary, &home);
+        let start_args: Vec<_> = start.get_args().collect();
+        assert_eq!(start_args[0], "start");
+        assert_eq!(&start_args[3..], ["--pruning", "custom"]);
+        assert!(start_command_line(&config).ends_with(" --pruning custom"));
+        Ok(())
+    }
+
+    #[test]
+    fn test_init_skip_if_genesis_present() -> Result<()> {
+        let temp_dir = tempdir()?;
+        let config = test_config(temp_dir.path(), "")?;
+        assert_eq!(init_skip_reason(&config), None);
+
+        fs::create_dir_all(config.home_dir.join("config"))?;
+        fs::write(config.home_dir.join("config/genesis.json"), "{}")?;
+        assert_eq!(
+            init_skip_reason(&config),
+            Some("genesis.json already exists")
+        );
+        // Nothing is run, so the missing binary does not matter
+        run_binary_init(&config)?;
+
+        let config = test_config(temp_dir.path(), "init_skip_if: []\n")?;
+        assert_eq!(init_skip_reason(&config), None);
+        Ok(())
+    }
+
+    #[test]
+    fn test_init_skip_if_data_present() -> Result<()> {
+        let temp_dir = tempdir()?;
+        let config = test_config(temp_dir.path(), "")?;
+        let state_path = config.home_dir.join("data/priv_validator_state.json");
+        fs::create_dir_all(state_path.parent().unwrap())?;
+        fs::write(&state_path, r#"{"height":"42","round":0,"step":0}"#)?;
+
+        // Only checked when configured
+        assert_eq!(init_skip_reason(&config), None);
+
+        let config = test_config(
+            temp_dir.path(),
+            "init_skip_if: [genesis, validator_state]\n",
+        )?;
+        assert_eq!(
+            init_skip_reason(&config),
+            Some("data/priv_validator_state.json already exists")
+        );
+
+        // A truncated state file is not taken as a populated home
+        fs::write(&state_path, r#"{"height":"4"#)?;
+        assert_eq!(init_skip_reason(&config), None);
+        Ok(())
+    }
+
+    #[test]
+    fn test_home_flag_styles() -> Result<()> {
+        let temp_dir = tempdir()?;
+        let args = |config: &Config| {
+            let (binary, home) = absolute_paths(config);
+            let init = build_init_command(config, &binary, &home);
+            let start = build_start_command(config, &binary, &home);
+            let collect = |command: &Command| {
+                command
+                    .get_args()
+                    .map(|arg| arg.to_string_lossy().into_owned())
+                    .collect::<Vec<_>>()
+            };
+            (home.display().to_string(), collect(&init), collect(&start))
+        };
+
+        let config = test_config(temp_dir.path(), "")?;
+        let (home, init, start) = args(&config);
+        assert_eq!(
+            init,
+            [
+                "init",
+                "test-node",
+                "--chain-id",
+                "cosmoshub-4",
+                "--home",
+                &home
+            ]
+        );
+        assert_eq!(start, ["start", "--home", &home]);
+
+        let config = test_config(
+            temp_dir.path(),
+            "home_flag: \"--home-dir\"\nstart_args: [\"--pruning\", \"nothing\"]\n",
+        )?;
+        let (home, init, start) = args(&config);
+        assert_eq!(&init[4..], ["--home-dir", &home]);
+        assert_eq!(
+            start,
+            ["start", "--home-dir", &home, "--pruning", "nothing"]
+        );
+
+        let config = test_config(temp_dir.path(), "home_flag_style: positional\n")?;
+        let (home, init, start) = args(&config);
+        assert_eq!(
+            init,
+            ["init", "test-node", "--chain-id", "cosmoshub-4", &home]
+        );
+        assert_eq!(start, ["start", &home]);
+
+        let err = test_config(temp_dir.path(), "home_flag: \"\"\n").unwrap_err();
+        assert!(err.to_string().contains("home_flag"), "{err}");
+        Ok(())
+    }
+
+    #[test]
+    fn test_post_start_regex_pattern() -> Result<()> {
+        let temp_dir = tempdir()?;
+        let config = test_config(
+            temp_dir.path(),
+            "post_start_pattern: 'committed state.*height=(\\d{7,})'\npost_start_pattern_is_regex: true\n",
+        )?;
+        let matcher = PostStartMatcher::from_config(&config)?;
+
+        assert!(matcher.is_match("INF committed state app_hash=ABCD height=1000000 module=state"));
+        assert!(!matcher.is_match("INF committed state app_hash=ABCD height=999 module=state"));
+        Ok(())
+    }
+
+    #[test]
+    fn test_post_start_pattern_defaults_to_substring() -> Result<()> {
+        let temp_dir = tempdir()?;
+        let config = test_config(temp_dir.path(), "post_start_pattern: 'height=(1'\n")?;
+        let matcher = PostStartMatcher::from_config(&config)?;
+        assert!(matcher.is_match("committed height=(1"));
+        Ok(())
+    }
+
+    #[test]
+    fn test_verify_binary_version() -> Result<()> {
+        let temp_dir = tempdir()?;
+        let config = test_config(temp_dir.path(), "expected_binary_version: \"25.2.0\"\n")?;
+
+        // Fake binary that prints its version only for `version`
+        let binary_path = config.workspace_dir.join(&config.binary_relative_path);
+        fs::create_dir_all(binary_path.parent().unwrap())?;
+        fs::write(
+            &binary_path,
+            "#!/bin/sh
+[ \"$1\" = version ] || exit 1
+echo v25.2.0
+",
+        )?;
+
+        // Not executable yet
+        fs::set_permissions(&binary_path, fs::Permissions::from_mode(0o644))?;
+        let err = verify_binary(&config).unwrap_err();
+        assert!(err.to_string().contains("not an executable"), "{err}");
+
+        fs::set_permissions(&binary_path, fs::Permissions::from_mode(0o755))?;
+        verify_binary(&config)?;
+
+        let mismatched = test_config(temp_dir.path(), "expected_binary_version: \"v25.1.0\"\n")?;
+        let err = verify_binary(&mismatched).unwrap_err();
+        assert!(err.to_string().contains("version mismatch"), "{err}");
+
+        // A custom version command is used instead of `version`
+        let custom = test_config(
+            temp_dir.path(),
+            "expected_binary_version: \"25.2.0\"\nbinary_version_args: [\"--version\"]\n",
+        )?;
+        assert!(verify_binary(&custom).is_err());
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_supervise_node_restarts_failed_exits() -> Result<()> {
+        let temp_dir = tempdir()?;
+        let config = test_config(
+            temp_dir.path(),
+            "restart_policy:\n  max_restarts: 3\n  backoff_secs: 0\n",
+        )?;
+        crate::utils::create_directories(&config)?;
+
+        // Fake binary that fails twice, then exits cleanly
+        let binary_path = config.workspace_dir.join(&config.binary_relative_path);
+        fs::create_dir_all(binary_path.parent().unwrap())?;
+        fs::write(
+            &binary_path,
+            "#!/bin/sh\nn=$(cat \"$3/runs\" 2>/dev/null || echo 0)\nn=$((n + 1))\necho $n > \"$3/runs\"\n[ $n -ge 3 ]\n",
+        )?;
+        fs::set_permissions(&binary_path, fs::Permissions::from_mode(0o755))?;
+
+        let (_shutdown_tx, shutdown_rx) = oneshot::channel();
+        assert_eq!(supervise_node(&config, shutdown_rx).await?, 0);
+        assert_eq!(fs::read_to_string(config.home_dir.join("runs"))?, "3\n");
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_unwritable_pid_file_stops_the_node() -> Result<()> {
+        let temp_dir = tempdir()?;
+        // A regular file stands where the PID file's directory should be
+        let not_a_dir = temp_dir.path().join("not-a-dir");
+        fs::write(&not_a_dir, "")?;
+        let config = test_config(
+            temp_dir.path(),
+            &format!("pid_file: \"{}\"\n", not_a_dir.join("node.pid").display()),
+        )?;
+        crate::utils::create_directories(&config)?;
+
+        // Fake binary that records its PID and keeps running
+        let binary_path = config.workspace_dir.join(&config.binary_relative_path);
+        fs::create_dir_all(binary_path.parent().unwrap())?;
+        fs::write(
+            &binary_path,
+            "#!/bin/sh\necho $$ > \"$3/pid\"\nexec sleep 30\n",
+        )?;
+        fs::set_permissions(&binary_path, fs::Permissions::from_mode(0o755))?;
+
+        let (_shutdown_tx, shutdown_rx) = oneshot::channel();
+        let err = tokio::time::timeout(
+            Duration::from_secs(10),
+            supervise_node(&config, shutdown_rx),
+        )
+        .await
+        .expect("supervisor did not return")
+        .unwrap_err();
+        assert!(format!("{err:#}").contains("not-a-dir"), "{err:#}");
+
+        // The node was stopped and reaped (possibly before it wrote its PID), so its PID no
+        // longer exists
+        if let Ok(pid) = fs::read_to_string(config.home_dir.join("pid")) {
+            let pid: libc::pid_t = pid.trim().parse()?;
+            // SAFETY: signal 0 only checks whether the process exists
+            assert_ne!(unsafe { libc::kill(pid, 0) }, 0);
+        }
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_unopenable_log_file_does_not_start_the_node() -> Result<()> {
+        let temp_dir = tempdir()?;
+        // A regular file stands where the log's directory should be
+        let not_a_dir = temp_dir.path().join("not-a-dir");
+        fs::write(&not_a_dir, "")?;
+        let config = test_config(
+            temp_dir.path(),
+            &format!("log_file: \"{}\"\n", not_a_dir.join("node.log").display()),
+        )?;
+        crate::utils::create_directories(&config)?;
+
+        // Fake binary that would leave a marker if it were started
+        let binary_path = config.workspace_dir.join(&config.binary_relative_path);
+        fs::create_dir_all(binary_path.parent().unwrap())?;
+        fs::write(
+            &binary_path,
+            "#!/bin/sh\ntouch \"$3/started\"\nexec sleep 30\n",
+        )?;
+        fs::set_permissions(&binary_path, fs::Permissions::from_mode(0o755))?;
+
+        let (_shutdown_tx, shutdown_rx) = oneshot::channel();
+        assert!(supervise_node(&config, shutdown_rx).await.is_err());
+        tokio::time::sleep(Duration::from_millis(200)).await;
+        assert!(!config.home_dir.join("started").exists());
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_supervise_node_writes_all_output_before_exit() -> Result<()> {
+        let temp_dir = tempdir()?;
+        let log_path = temp_dir.path().join("node.log");
+        let config = test_config(
+            temp_dir.path(),
+            &format!("log_file: \"{}\"\n", log_path.display()),
+        )?;
+        crate::utils::create_directories(&config)?;
+
+        // Fake binary whose last line is written after it has exited, by a process it started
+        let binary_path = config.workspace_dir.join(&config.binary_relative_path);
+        fs::create_dir_all(binary_path.parent().unwrap())?;
+        fs::write(
+            &binary_path,
+            "#!/bin/sh\necho first\n(sleep 0.5; echo last) &\n",
+        )?;
+        fs::set_permissions(&binary_path, fs::Permissions::from_mode(0o755))?;
+
+        let (_shutdown_tx, shutdown_rx) = oneshot::channel();
+        assert_eq!(supervise_node(&config, shutdown_rx).await?, 0);
+        assert_eq!(fs::read_to_string(&log_path)?, "first\nlast\n");
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_pid_file_holds_node_pid() -> Result<()> {
+        let temp_dir = tempdir()?;
+        let pid_file = temp_dir.path().join("run/node.pid");
+        let config = test_config(
+            temp_dir.path(),
+            &format!("pid_file: \"{}\"\n", pid_file.display()),
+        )?;
+        crate::utils::create_directories(&config)?;
+
+        // A stale PID file from an earlier run is replaced
+        fs::create_dir_all(pid_file.parent().unwrap())?;
+        fs::write(&pid_file, "99999\n")?;
+
+        // Fake node that waits until the PID file names it, then exits cleanly
+        let binary_path = config.workspace_dir.join(&config.binary_relative_path);
+        fs::create_dir_all(binary_path.parent().unwrap())?;
+        fs::write(
+            &binary_path,
+            format!(
+                "#!/bin/sh\nuntil [ \"$(cat {})\" = \"$$\" ]; do sleep 0.05; done\necho $$ > \"$3/pid\"\n",
+                pid_file.display()
+            ),
+        )?;
+        fs::set_permissions(&binary_path, fs::Permissions::from_mode(0o755))?;
+
+        let (_shutdown_tx, shutdown_rx) = oneshot::channel();
+        let code = tokio::time::timeout(
+            Duration::from_secs(10),
+            supervise_node(&config, shutdown_rx),
+        )
+        .await??;
+        assert_eq!(code, 0);
+        assert!(config.home_dir.join("pid").exists());
+        assert!(!pid_file.exists());
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_pre_shutdown_command_runs_before_termination() -> Result<()> {
+        let temp_dir = tempdir()?;
+        let events = test_config(temp_dir.path(), "")?.home_dir.join("events");
+        let config = test_config(
+            temp_dir.path(),
+            &format!(
+                "pre_shutdown_command: \"echo pre-shutdown >> {}\"\nshutdown_timeout_secs: 5\n",
+                events.display()
+            ),
+        )?;
+        crate::utils::create_directories(&config)?;
+
+        // Fake node that records when it starts and when it is asked to stop
+        let binary_path = config.workspace_dir.join(&config.binary_relative_path);
+        fs::create_dir_all(binary_path.parent().unwrap())?;
+        fs::write(
+            &binary_path,
+            "#!/bin/sh
+trap 'echo terminated >> \"$3/events\"; exit 0' TERM
+echo started >> \"$3/events\"
+while :; do sleep 0.1; done
+",
+        )?;
+        fs::set_permissions(&binary_path, fs::Permissions::from_mode(0o755))?;
+
+        let (shutdown_tx, shutdown_rx) = oneshot::channel();
+        let supervisor = supervise_node(&config, shutdown_rx);
+        let trigger = async {
+            while !fs::read_to_string(&events).is_ok_and(|events| events.contains("started")) {
+                tokio::time::sleep(Duration::from_millis(50)).await;
+            }
+            let _ = shutdown_tx.send(());
+        };
+        let (exit_code, ()) = tokio::join!(supervisor, trigger);
+        assert_eq!(exit_code?, 0);
+        assert_eq!(
+            fs::read_to_string(&events)?,
+            "started\npre-shutdown\nterminated\n"
+        );
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_pre_shutdown_command_is_bounded_by_timeout() -> Result<()> {
+        let started = std::time::Instant::now();
+        let cmd = ShellCommand {
+            command: "exec sleep 30",
+            shell: DEFAULT_COMMAND_SHELL,
+            working_dir: None,
+        };
+        let err = execute_pre_shutdown_command(&cmd, Duration::from_millis(300))
+            .await
+            .unwrap_err();
+        assert!(err.to_string().contains("did not finish"), "{err}");
+        assert!(started.elapsed() < Duration::from_secs(5));
+
+        // A background process holding the output open does not hold up the shutdown either
+        let started = std::time::Instant::now();
+        let cmd = ShellCommand {
+            command: "sleep 3 &",
+            ..cmd
+        };
+        execute_pre_shutdown_command(&cmd, Duration::from_millis(300)).await?;
+        assert!(started.elapsed() < Duration::from_secs(5));
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_post_start_timeout_fails_when_pattern_never_appears() -> Result<()> {
+        let temp_dir = tempdir()?;
+        let config = test_config(
+            temp_dir.path(),
+            "post_start_timeout_secs: 1\npost_start_on_timeout: fail\n",
+        )?;
+        crate::utils::create_directories(&config)?;
+
+        // Fake binary that keeps running without ever printing the pattern
+        let binary_path = config.workspace_dir.join(&config.binary_relative_path);
+        fs::create_dir_all(binary_path.parent().unwrap())?;
+        fs::write(
+            &binary_path,
+            "#!/bin/sh\necho replaying blocks\nexec sleep 30\n",
+        )?;
+        fs::set_permissions(&binary_path, fs::Permissions::from_mode(0o755))?;
+
+        let (_shutdown_tx, shutdown_rx) = oneshot::channel();
+        let err = tokio::time::timeout(
+            Duration::from_secs(10),
+            supervise_node(&config, shutdown_rx),
+        )
+        .await
+        .expect("supervisor did not stop after the timeout")
+        .unwrap_err();
+        assert!(err.to_string().contains("did not become ready within 1s"));
+        Ok(())
+    }
+
+    #[test]
+    fn test_run_binary_init_passes_env() -> Result<()> {
+        let temp_dir = tempdir()?;
+        let config = test_config(temp_dir.path(), "env:\n  FOO: \"bar\"\n")?;
+        crate::utils::create_directories(&config)?;
+
+        // Fake binary that records $FOO in the home directory passed via --home
+        let binary_path = config.workspace_dir.join(&config.binary_relative_path);
+        fs::create_dir_all(binary_path.parent().unwrap())?;
+        fs::write(&binary_path, "#!/bin/sh\necho \"$FOO\" > \"$6/foo\"\n")?;
+        fs::set_permissions(&binary_path, fs::Permissions::from_mode(0o755))?;
+
+        run_binary_init(&config)?;
+        assert_eq!(fs::read_to_string(config.home_dir.join("foo"))?, "bar\n");
+        Ok(())
+    }
+
+    #[test]
+    fn test_commands_run_in_working_dir() -> Result<()> {
+        let temp_dir = tempdir()?;
+        let output = build_shell_command("pwd", "sh", Some(temp_dir.path()))?.output()?;
+        assert_eq!(
+            Path::new(String::from_utf8(output.stdout)?.trim()).canonicalize()?,
+            temp_dir.path().canonicalize()?
+        );
+
+        execute_post_start_command(&ShellCommand {
+            command: "touch ready",
+            shell: DEFAULT_COMMAND_SHELL,
+            working_dir: Some(temp_dir.path()),
+        })?;
+        assert!(temp_dir.path().join("ready").exists());
+
+        let missing = temp_dir.path().join("missing");
+        let err = execute_pre_start_command(&ShellCommand {
+            command: "true",
+            shell: DEFAULT_COMMAND_SHELL,
+            working_dir: Some(&missing),
+        })
+        .unwrap_err();
+        assert!(format!("{err:#}").contains("missing"), "{err:#}");
+        Ok(())
+    }
+
+    #[test]
+    fn test_commands_run_with_their_shell() -> Result<()> {
+        let temp_dir = tempdir()?;
+        let config = test_config(temp_dir.path(), "command_shell: \"no-shell\"\n")?;
+        // Without a shell the `;` is part of the file name
+        execute_post_start_command(&ShellCommand::new(
+            &config,
+            "touch a;b",
+            Some(temp_dir.path()),
+        ))?;
+        assert!(temp_dir.path().join("a;b").exists());
+        assert!(!temp_dir.path().join("a").exists());
+        Ok(())
+    }
+
+    #[test]
+    fn test_no_shell_splits_command() -> Result<()> {
+        let temp_dir = tempdir()?;
+        let command = r#"printf '%s|' 'a b' "c\"d $HOME" e\ f > out.txt"#;
+        let output = build_shell_command(command, NO_SHELL, Some(temp_dir.path()))?.output()?;
+        assert!(output.status.success());
+        // Nothing is expanded or redirected
+        assert_eq!(
+            String::from_utf8(output.stdout)?,
+            "a b|c\"d $HOME|e f|>|out.txt|"
+        );
+        assert!(!temp_dir.path().join("out.txt").exists());
+
+        // A shell with its own arguments
+        let output = build_shell_command("echo $0", "sh -e", None)?.output()?;
+        assert_eq!(String::from_utf8(output.stdout)?, "sh\n");
+
+        assert_eq!(split_command("  a  '' b")?, ["a", "", "b"]);
+        assert!(split_command("echo 'unterminated").is_err());
+        assert!(split_command("echo \\").is_err());
+        assert!(split_command("   ").is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_node_output_keeps_lines_whole() -> Result<()> {
+        // The readiness line arrives in two writes, then both streams write long lines at once
+        let line = "x".repeat(4000);
+        let mut child = Command::new("sh")
+            .args([
+                "-c",
+                "printf 'committed '; sleep 0.1; printf 'state\\n'; \
+                 for i in $(seq 50); do echo \"out $i $0\"; done & \
+                 for i in $(seq 50); do echo \"err $i $0\" >&2; done & \
+                 wait; printf 'no newline'",
+            ])
+            .arg(&line)
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped())
+            .spawn()?;
+
+        let (line_tx, line_rx) = mpsc::channel();
+        let stdout = child.stdout.take().unwrap();
+        let stderr = child.stderr.take().unwrap();
+        let stdout_tx = line_tx.clone();
+        std::thread::spawn(move || forward_lines(stdout, OutputStream::Stdout, &stdout_tx));
+        std::thread::spawn(move || forward_lines(stderr, OutputStream::Stderr, &line_tx));
+
+        let (ready_tx, mut ready_rx) = oneshot::channel();
+        let pattern = PostStartMatcher::Substring("committed state".to_string());
+        let (mut out, mut err) = (Vec::new(), Vec::new());
+        write_node_output(
+            &line_rx,
+            None,
+            Some(pattern),
+            Some(ready_tx),
+            &mut out,
+            &mut err,
+        );
+        child.wait()?;
+        assert_eq!(ready_rx.try_recv(), Ok(()));
+
+        let out = String::from_utf8(out)?;
+        let err = String::from_utf8(err)?;
+        let mut out_lines: Vec<&str> = out.lines().collect();
+        assert!(out_lines.contains(&"[STDOUT] committed state"));
+        assert!(out_lines.contains(&"[STDOUT] no newline"));
+        out_lines.retain(|l| !l.contains("committed state") && !l.contains("no newline"));
+        assert_eq!(out_lines.len(), 50);
+        for (i, l) in out_lines.iter().enumerate() {
+            assert_eq!(*l, format!("[STDOUT] out {} {line}", i + 1));
+        }
+        let err_lines: Vec<&str> = err.lines().collect();
+        assert_eq!(err_lines.len(), 50);
+        for (i, l) in err_lines.iter().enumerate() {
+            assert_eq!(*l, format!("[STDERR] err {} {line}", i + 1));
+        }
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_terminate_process_with_sigterm() -> Result<()> {
+        let mut child = Command::new("sleep").arg("30").spawn()?;
+        let (stage, status) = terminate_process(&mut child, Duration::from_secs(5)).await?;
+        assert_eq!(stage, TerminationStage::Sigterm);
+        assert_eq!(exit_code(&status), 128 + libc::SIGTERM);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_terminate_process_lets_node_shut_down_cleanly() -> Result<()> {
+        let temp_dir = tempdir()?;
+        let flushed = temp_dir.path().join("flushed");
+        // Fake node that needs a moment to flush its state when asked to stop
+        let mut child = Command::new("sh")
+            .args([
+                "-c",
+                "trap 'sleep 0.5; touch \"$0\"; exit 3' TERM; while :; do sleep 0.1; done",
+            ])
+            .arg(&flushed)
+            .spawn()?;
+        tokio::time::sleep(Duration::from_millis(200)).await;
+
+        let (stage, status) = terminate_process(&mut child, Duration::from_secs(5)).await?;
+        assert_eq!(stage, TerminationStage::Sigterm);
+        assert_eq!(status.code(), Some(3));
+        assert!(flushed.exists());
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_terminate_process_escalates_to_sigkill() -> Result<()> {
+        let mut child = Command::new("sh")
+            .args(["-c", "trap '' TERM; exec sleep 30"])
+            .spawn()?;
+        // Give the shell time to install the trap before signalling it
+        tokio::time::sleep(Duration::from_millis(200)).await;
+        let (stage, status) = terminate_process(&mut child, Duration::from_millis(300)).await?;
+        assert_eq!(stage, TerminationStage::Sigkill);
+        assert_eq!(exit_code(&status), 128 + libc::SIGKILL);
+        Ok(())
+    }
+}
@@ -0,0 +1,222 @@
+use anyhow::{Context, Result};
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::process::{Command, Output};
+use tracing::{debug, info};
+
+/// Throwaway GnuPG home holding only the pinned key, removed when dropped
+struct TempGpgHome {
+    path: PathBuf,
+}
+
+impl TempGpgHome {
+    fn create() -> Result<Self> {
+        let nanos = std::time::SystemTime::now()
+            .duration_since(std::time::UNIX_EPOCH)
+            .map(|d| d.subsec_nanos())
+            .unwrap_or_default();
+        let path = std::env::temp_dir().join(format!(
+            "snapshot-downloader-gpg-{}-{}",
+            std::process::id(),
+            nanos
+        ));
+        fs::create_dir_all(&path)
+            .with_context(|| format!("Failed to create GnuPG home {}", path.display()))?;
+
+        // gpg refuses to use a home directory others can read
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+            fs::set_permissions(&path, fs::Permissions::from_mode(0o700))?;
+        }
+
+        Ok(Self { path })
+    }
+
+    fn gpg(&self) -> Command {
+        let mut cmd = Command::new("gpg");
+        cmd.arg("--homedir").arg(&self.path).arg("--batch");
+        cmd
+    }
+}
+
+impl Drop for TempGpgHome {
+    fn drop(&mut self) {
+        // Stop any agent started for this home before removing it
+        let _ = Command::new("gpgconf")
+            .arg("--homedir")
+            .arg(&self.path)
+            .args(["--kill", "all"])
+            .output();
+        let _ = fs::remove_dir_all(&self.path);
+    }
+}
+
+fn run_gpg(mut cmd: Command, action: &str) -> Result<Output> {
+    let output = cmd
+        .output()
+        .with_context(|| format!("Failed to run gpg to {action} (is GnuPG installed?)"))?;
+    debug!(
+        "gpg {} output: {}",
+        action,
+        String::from_utf8_lossy(&output.stderr).trim()
+    );
+    Ok(output)
+}
+
+/// Verify a detached OpenPGP signature of `file` against a single pinned public key
+/// Only the given key is trusted: verification runs in a temporary GnuPG home, so keys in the
+/// user's keyring are never consulted
+pub fn verify_detached_signature(file: &Path, signature: &Path, public_key: &Path) -> Result<()> {
+    info!(
+        "Verifying signature of {} with key {}",
+        file.display(),
+        public_key.display()
+    );
+
+    let home = TempGpgHome::create()?;
+
+    let mut import = home.gpg();
+    import.arg("--quiet").arg("--import").arg(public_key);
+    let output = run_gpg(import, "import the public key")?;
+    if !output.status.success() {
+        return Err(anyhow::anyhow!(
+            "Failed to import public key {}: {}",
+            public_key.display(),
+            String::from_utf8_lossy(&output.stderr).trim()
+        ));
+    }
+
+    let mut verify = home.gpg();
+    verify
+        .args(["--status-fd", "1", "--verify"])
+        .arg(signature)
+        .arg(file);
+    let output = run_gpg(verify, "verify the signature")?;
+
+    // A zero exit status alone would also accept an expired or revoked key's signature
+    let status = String::from_utf8_lossy(&output.stdout);
+    let valid = status
+        .lines()
+        .any(|line| line.starts_with("[GNUPG:] VALIDSIG "));
+    if !output.status.success() || !valid {
+        return Err(anyhow::anyhow!(
+            "Signature verification failed for {}: {}",
+            file.display(),
+            String::from_utf8_lossy(&output.stderr).trim()
+        ));
+    }
+
+    info!("Signature of {} is valid", file.display());
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::tempdir;
+
+    /// Public key of a throwaway ed25519 signing key (Release Signer <release@example.com>)
+    const RELEASE_KEY: &str = "\
+-----BEGIN PGP PUBLIC KEY BLOCK-----
+
+mDMEatI8sRYJKwYBBAHaRw8BAQdAbFTSDpRAY9EWjBPpr8LSS4gNBfmpqJz2Pg/J
+GMp11uC0JFJlbGVhc2UgU2lnbmVyIDxyZWxlYXNlQGV4YW1wbGUuY29tPoiQBBMW
+CAA4FiEEuoDVBshrUN27TZEVU58nbt7/KRMFAmrSPLECGwMFCwkIBwIGFQoJCAsC
+BBYCAwECHgECF4AACgkQU58nbt7/KRNZnQD/SXp6V85+ZelcM2fVqY9W4J/fOlWz
+DcPLTtDupkqZnMMA/1SP0GOgAlJdHvhskuEFqd+qfKWJnwdAi5ZnvV0jdBUK
+=yyIf
+-----END PGP PUBLIC KEY BLOCK-----
+";
+    /// That key's detached signature of `SIGNED_ARCHIVE`
+    const ARCHIVE_SIGNATURE: &str = "\
+-----BEGIN PGP SIGNATURE-----
+
+iHUEABYIAB0WIQS6gNUGyGtQ3btNkRVTnydu3v8pEwUCatI8sQAKCRBTnydu3v8p
+E8TdAQCkN63w3dDVBWujGIla8gQvVGKeONs4AG58y5u/UMRqMgEAtoK7BiG9xUSf
+vPc97stqPjnWjSREkDMxF5u8SmI2QgA=
+=orT4
+-----END PGP SIGNATURE-----
+";
+    const SIGNED_ARCHIVE: &[u8] = b"known-good release archive";
+
+    /// Generate a signing key, returning its home and the exported public key
+    fn generate_key(dir: &Path) -> Result<(TempGpgHome, PathBuf)> {
+        let home = TempGpgHome::create()?;
+        let mut gen = home.gpg();
+        gen.args([
+            "--passphrase",
+            "",
+            "--pinentry-mode",
+            "loopback",
+            "--quick-gen-key",
+            "Release Signer <release@example.com>",
+            "ed25519",
+            "sign",
+            "never",
+        ]);
+        assert!(run_gpg(gen, "generate a key")?.status.success());
+
+        let public_key = dir.join("release.asc");
+        let mut export = home.gpg();
+        export
+            .args(["--armor", "--output"])
+            .arg(&public_key)
+            .args(["--export", "release@example.com"]);
+        assert!(run_gpg(export, "export the key")?.status.success());
+        Ok((home, public_key))
+    }
+
+    fn sign(home: &TempGpgHome, file: &Path) -> Result<PathBuf> {
+        let signature = file.with_extension("sig");
+        let mut sign = home.gpg();
+        sign.args(["--pinentry-mode", "loopback", "--passphrase", ""])
+            .arg("--output")
+            .arg(&signature)
+            .arg("--detach-sign")
+            .arg(file);
+        assert!(run_gpg(sign, "sign")?.status.success());
+        Ok(signature)
+    }
+
+    #[test]
+    fn test_verify_checked_in_signature() -> Result<()> {
+        let temp_dir = tempdir()?;
+        let public_key = temp_dir.path().join("release.asc");
+        fs::write(&public_key, RELEASE_KEY)?;
+        let signature = temp_dir.path().join("gaiad.tar.gz.asc");
+        fs::write(&signature, ARCHIVE_SIGNATURE)?;
+        let binary = temp_dir.path().join("gaiad.tar.gz");
+        fs::write(&binary, SIGNED_ARCHIVE)?;
+
+        verify_detached_signature(&binary, &signature, &public_key)?;
+
+        fs::write(&binary, b"tampered release archive")?;
+        let err = verify_detached_signature(&binary, &signature, &public_key).unwrap_err();
+        assert!(err.to_string().contains("verification failed"), "{err}");
+
+        // A signature is not a key to verify with
+        fs::write(&binary, SIGNED_ARCHIVE)?;
+        assert!(verify_detached_signature(&binary, &signature, &signature).is_err());
+        Ok(())
+    }
+
+    #[test]
+    #[ignore = "requires gpg"]
+    fn test_verify_detached_signature() -> Result<()> {
+        let temp_dir = tempdir()?;
+        let (signer, public_key) = generate_key(temp_dir.path())?;
+
+        let binary = temp_dir.path().join("gaiad.tar.gz");
+        fs::write(&binary, b"known-good release archive")?;
+        let signature = sign(&signer, &binary)?;
+
+        verify_detached_signature(&binary, &signature, &public_key)?;
+
+        // The same signature must not verify a tampered archive
+        fs::write(&binary, b"tampered release archive")?;
+        let err = verify_detached_signature(&binary, &signature, &public_key).unwrap_err();
+        assert!(err.to_string().contains("verification failed"), "{err}");
+        Ok(())
+    }
+}
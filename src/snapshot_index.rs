@@ -0,0 +1,451 @@
+use anyhow::{Context, Result};
+use regex::Regex;
+use serde_json::Value as JsonValue;
+use std::cmp::Ordering;
+use std::fs;
+use std::io;
+use std::path::Path;
+use tracing::{debug, info};
+
+use crate::config::IndexSelector;
+use crate::download::{self, DownloadOptions};
+
+/// Names of snapshot archives in an index, used unless `snapshot_index_pattern` is set
+pub const DEFAULT_INDEX_PATTERN: &str = r"\.(tar\.(gz|lz4|zst)|tgz|tar|lz4|zst|gz)$";
+
+/// A file listed by a snapshot index
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct IndexEntry {
+    /// Absolute URL of the file
+    pub url: String,
+    /// File name, the last segment of the URL
+    pub name: String,
+    /// Last modification in Unix seconds, if the index lists it
+    pub modified: Option<i64>,
+}
+
+/// Parse a snapshot index fetched from `index_url`, resolving relative links against it
+///
+/// JSON indexes are an array, or an object with a `files` or `snapshots` array, of file names or
+/// URLs, or of objects with the file under `name`, `url`, `key`, `path` or `file` and optionally
+/// its modification time under `modified`, `last_modified`, `lastModified`, `mtime` or
+/// `timestamp` (Unix seconds, RFC 3339 or an HTTP date). Nginx's JSON autoindex is one such
+/// index. Anything else is read as an HTML page and its links are listed, without times.
+pub fn parse_index(content: &str, index_url: &str) -> Result<Vec<IndexEntry>> {
+    let base = reqwest::Url::parse(index_url).ok();
+    let resolve = |link: &str| -> Option<String> {
+        match (reqwest::Url::parse(link), &base) {
+            (Ok(url), _) => Some(url.to_string()),
+            (Err(_), Some(base)) => base.join(link).ok().map(|url| url.to_string()),
+            (Err(_), None) => None,
+        }
+    };
+
+    let trimmed = content.trim_start();
+    let links: Vec<(String, Option<i64>)> = if trimmed.starts_with('[') || trimmed.starts_with('{')
+    {
+        let json: JsonValue =
+            serde_json::from_str(content).context("Invalid JSON snapshot index")?;
+        json_entries(&json)?
+    } else {
+        html_links(content)
+    };
+
+    let mut entries = Vec::new();
+    for (link, modified) in links {
+        let Some(url) = resolve(&link) else {
+            debug!("Skipping index entry with an unresolvable URL: {}", link);
+            continue;
+        };
+        let name = url
+            .trim_end_matches('/')
+            .rsplit('/')
+            .next()
+            .unwrap_or_default();
+        let name = percent_encoding::percent_decode_str(name)
+            .decode_utf8_lossy()
+            .into_owned();
+        entries.push(IndexEntry {
+            url,
+            name,
+            modified,
+        });
+    }
+    Ok(entries)
+}
+
+/// Files and times listed by a JSON index, skipping directories
+fn json_entries(json: &JsonValue) -> Result<Vec<(String, Option<i64>)>> {
+    let items = match json {
+        JsonValue::Array(items) => items,
+        JsonValue::Object(object) => ["files", "snapshots"]
+            .iter()
+            .find_map(|key| object.get(*key).and_then(JsonValue::as_array))
+            .context("JSON snapshot index has no 'files' or 'snapshots' array")?,
+        _ => unreachable!("only arrays and objects are parsed as JSON"),
+    };
+
+    let mut entries = Vec::new();
+    for item in items {
+        match item {
+            JsonValue::String(link) => entries.push((link.clone(), None)),
+            JsonValue::Object(object) => {
+                if object.get("type").and_then(JsonValue::as_str) == Some("directory") {
+                    continue;
+                }
+                let link = ["name", "url", "key", "path", "file"]
+                    .iter()
+                    .find_map(|key| object.get(*key).and_then(JsonValue::as_str))
+                    .with_context(|| format!("Snapshot index entry without a file name: {item}"))?;
+                let modified = [
+                    "modified",
+                    "last_modified",
+                    "lastModified",
+                    "mtime",
+                    "timestamp",
+                ]
+                .iter()
+                .find_map(|key| object.get(*key))
+                .and_then(parse_time);
+                entries.push((link.to_string(), modified));
+            }
+            _ => return Err(anyhow::anyhow!("Invalid snapshot index entry: {item}")),
+        }
+    }
+    Ok(entries)
+}
+
+/// Targets of the links in an HTML page, other than parent directories, subdirectories and
+/// in-page or query links
+fn html_links(html: &str) -> Vec<(String, Option<i64>)> {
+    let href = Regex::new(r#"(?i)href\s*=\s*["']([^"']+)["']"#).expect("valid regex");
+    href.captures_iter(html)
+        .map(|captures| captures[1].to_string())
+        .filter(|link| {
+            !link.starts_with(['?', '#'])
+                && !link.ends_with('/')
+                && !link.starts_with("mailto:")
+                && !link.starts_with("javascript:")
+        })
+        .map(|link| (link, None))
+        .collect()
+}
+
+/// A modification time as Unix seconds: a number, digits, an RFC 3339 timestamp or an HTTP date
+fn parse_time(value: &JsonValue) -> Option<i64> {
+    match value {
+        JsonValue::Number(number) => number
+            .as_i64()
+            .or_else(|| number.as_f64().map(|secs| secs as i64)),
+        JsonValue::String(text) => {
+            let text = text.trim();
+            if let Ok(secs) = text.parse::<i64>() {
+                return Some(secs);
+            }
+            parse_rfc3339(text).or_else(|| {
+                httpdate::parse_http_date(text)
+                    .ok()?
+                    .duration_since(std::time::UNIX_EPOCH)
+                    .ok()
+                    .and_then(|duration| i64::try_from(duration.as_secs()).ok())
+            })
+        }
+        _ => None,
+    }
+}
+
+/// Parse `YYYY-MM-DDTHH:MM:SS[.fraction](Z|±HH:MM)` into Unix seconds
+fn parse_rfc3339(text: &str) -> Option<i64> {
+    let re = Regex::new(
+        r"^(\d{4})-(\d{2})-(\d{2})[Tt ](\d{2}):(\d{2}):(\d{2})(?:\.\d+)?(?:([Zz])|([+-])(\d{2}):?(\d{2}))$",
+    )
+    .expect("valid regex");
+    let captures = re.captures(text)?;
+    let field = |i: usize| captures.get(i).and_then(|m| m.as_str().parse::<i64>().ok());
+    let (year, month, day) = (field(1)?, field(2)?, field(3)?);
+    let (hour, minute, second) = (field(4)?, field(5)?, field(6)?);
+    if !(1..=12).contains(&month) || !(1..=31).contains(&day) || hour > 23 || minute > 59 {
+        return None;
+    }
+    let offset = match captures.get(8).map(|m| m.as_str()) {
+        Some(sign) => {
+            let offset = field(9)? * 3600 + field(10)? * 60;
+            if sign == "-" {
+                -offset
+            } else {
+                offset
+            }
+        }
+        None => 0,
+    };
+    Some(days_from_civil(year, month, day) * 86400 + hour * 3600 + minute * 60 + second - offset)
+}
+
+/// Days since 1970-01-01 of a proleptic Gregorian date
+fn days_from_civil(year: i64, month: i64, day: i64) -> i64 {
+    let year = if month <= 2 { year - 1 } else { year };
+    let era = year.div_euclid(400);
+    let year_of_era = year - era * 400;
+    let day_of_year = (153 * ((month + 9) % 12) + 2) / 5 + day - 1;
+    let day_of_era = year_of_era * 365 + year_of_era / 4 - year_of_era / 100 + day_of_year;
+    era * 146097 + day_of_era - 719468
+}
+
+/// Compare names with runs of digits compared by value, so `height-9999` sorts before
+/// `height-10000`
+fn natural_cmp(a: &str, b: &str) -> Ordering {
+    let chunks = |s: &str| -> Vec<(bool, String)> {
+        let mut chunks: Vec<(bool, String)> = Vec::new();
+        for c in s.chars() {
+            let digit = c.is_ascii_digit();
+            match chunks.last_mut() {
+                Some((is_digit, chunk)) if *is_digit == digit => chunk.push(c),
+                _ => chunks.push((digit, c.to_string())),
+            }
+        }
+        chunks
+    };
+    let (a_chunks, b_chunks) = (chunks(a), chunks(b));
+    for ((a_digit, a_chunk), (b_digit, b_chunk)) in a_chunks.iter().zip(&b_chunks) {
+        let ordering = if *a_digit && *b_digit {
+            let (a_num, b_num) = (
+                a_chunk.trim_start_matches('0'),
+                b_chunk.trim_start_matches('0'),
+            );
+            a_num.len().cmp(&b_num.len()).then_with(|| a_num.cmp(b_num))
+        } else {
+            a_chunk.cmp(b_chunk)
+        };
+        if ordering != Ordering::Equal {
+            return ordering;
+        }
+    }
+    a_chunks.len().cmp(&b_chunks.len()).then_with(|| a.cmp(b))
+}
+
+/// The latest of the entries whose name matches `pattern`
+pub fn select_latest<'a>(
+    entries: &'a [IndexEntry],
+    selector: IndexSelector,
+    pattern: &Regex,
+) -> Result<&'a IndexEntry> {
+    let candidates = entries.iter().filter(|entry| pattern.is_match(&entry.name));
+    let latest = match selector {
+        IndexSelector::LatestByName => candidates.max_by(|a, b| natural_cmp(&a.name, &b.name)),
+        IndexSelector::LatestByModified => {
+            let candidates: Vec<&IndexEntry> = candidates.collect();
+            if !candidates.is_empty() && candidates.iter().all(|entry| entry.modified.is_none()) {
+                return Err(anyhow::anyhow!(
+                    "The snapshot index lists no modification times; use latest-by-name"
+                ));
+            }
+            candidates
+                .into_iter()
+                .filter(|entry| entry.modified.is_some())
+                .max_by(|a, b| {
+                    a.modified
+                        .cmp(&b.modified)
+                        .then_with(|| natural_cmp(&a.name, &b.name))
+                })
+        }
+    };
+    latest.with_context(|| {
+        format!(
+            "No snapshot in the index matches '{}' ({} entries listed)",
+            pattern.as_str(),
+            entries.len()
+        )
+    })
+}
+
+/// List the snapshots at `index_url`: the objects under an s3:// prefix, or the files listed by
+/// the JSON or HTML index at any other URL
+/// A copy of the index left by an earlier run is replaced, since it changes as snapshots are
+/// published
+pub async fn fetch_index(
+    index_url: &str,
+    download_dir: &Path,
+    options: &DownloadOptions,
+) -> Result<Vec<IndexEntry>> {
+    if download::is_s3_url(index_url) {
+        let objects = download::list_s3_objects(index_url, options).await?;
+        return Ok(objects
+            .into_iter()
+            .map(|(url, modified)| IndexEntry {
+                name: url.rsplit('/').next().unwrap_or_default().to_string(),
+                url,
+                modified,
+            })
+            .collect());
+    }
+
+    let stale_path = download_dir.join(crate::utils::download_filename(index_url));
+    match fs::remove_file(&stale_path) {
+        Err(e) if e.kind() != io::ErrorKind::NotFound => {
+            return Err(e).with_context(|| format!("Failed to remove {}", stale_path.display()))
+        }
+        _ => {}
+    }
+
+    let path = download::download_with_mirrors(
+        &[index_url.to_string()],
+        download_dir,
+        "snapshot index",
+        options,
+    )
+    .await
+    .context("Failed to download snapshot index")?;
+    let content = fs::read_to_string(&path)
+        .with_context(|| format!("Failed to read snapshot index {}", path.display()))?;
+    parse_index(&content, index_url)
+}
+
+/// Fetch the index at `index_url` and pick the snapshot to download
+pub async fn resolve_latest(
+    index_url: &str,
+    selector: IndexSelector,
+    pattern: &Regex,
+    download_dir: &Path,
+    options: &DownloadOptions,
+) -> Result<String> {
+    let entries = fetch_index(index_url, download_dir, options).await?;
+    let latest = select_latest(&entries, selector, pattern)
+        .with_context(|| format!("Failed to pick a snapshot from {index_url}"))?;
+    info!("Latest snapshot listed at {}: {}", index_url, latest.url);
+    Ok(latest.url.clone())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn default_pattern() -> Regex {
+        Regex::new(DEFAULT_INDEX_PATTERN).unwrap()
+    }
+
+    #[test]
+    fn test_select_latest_by_name_and_modified() -> Result<()> {
+        let listing = r#"[
+            {"name": "cosmoshub-4_9999.tar.lz4", "type": "file", "mtime": "Thu, 02 May 2024 10:00:00 GMT"},
+            {"name": "cosmoshub-4_10000.tar.lz4", "type": "file", "mtime": "Wed, 01 May 2024 10:00:00 GMT"},
+            {"name": "cosmoshub-4_10000.tar.lz4.md5", "type": "file", "mtime": "Fri, 03 May 2024 10:00:00 GMT"},
+            {"name": "archive", "type": "directory", "mtime": "Sat, 04 May 2024 10:00:00 GMT"}
+        ]"#;
+        let entries = parse_index(listing, "https://snapshots.example.com/cosmoshub/")?;
+        assert_eq!(entries.len(), 3);
+
+        let by_name = select_latest(&entries, IndexSelector::LatestByName, &default_pattern())?;
+        assert_eq!(
+            by_name.url,
+            "https://snapshots.example.com/cosmoshub/cosmoshub-4_10000.tar.lz4"
+        );
+        let by_modified = select_latest(
+            &entries,
+            IndexSelector::LatestByModified,
+            &default_pattern(),
+        )?;
+        assert_eq!(by_modified.name, "cosmoshub-4_9999.tar.lz4");
+
+        let pattern = Regex::new(r"_9+\.tar\.lz4$")?;
+        let only_nines = select_latest(&entries, IndexSelector::LatestByName, &pattern)?;
+        assert_eq!(only_nines.name, "cosmoshub-4_9999.tar.lz4");
+
+        assert!(
+            select_latest(&entries, IndexSelector::LatestByName, &Regex::new("zip$")?).is_err()
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_parse_index_formats() -> Result<()> {
+        let html = r#"<html><body><pre>
+            <a href="../">../</a>
+            <a href="?C=M;O=A">Last modified</a>
+            <a href="old/">old/</a>
+            <a href="snapshot-100.tar.gz">snapshot-100.tar.gz</a>
+            <A HREF='https://mirror.example.com/snapshot-200.tar.gz'>mirror</A>
+        </pre></body></html>"#;
+        let entries = parse_index(html, "https://example.com/snapshots/index.html")?;
+        let urls: Vec<&str> = entries.iter().map(|entry| entry.url.as_str()).collect();
+        assert_eq!(
+            urls,
+            vec![
+                "https://example.com/snapshots/snapshot-100.tar.gz",
+                "https://mirror.example.com/snapshot-200.tar.gz",
+            ]
+        );
+        assert!(select_latest(
+            &entries,
+            IndexSelector::LatestByModified,
+            &default_pattern()
+        )
+        .is_err());
+
+        let json = r#"{"snapshots": [
+            "snapshot-1.tar.zst",
+            {"url": "snapshot-2.tar.zst", "last_modified": "2024-05-01T12:00:00+02:00"},
+            {"key": "snapshot-3.tar.zst", "timestamp": 1714557600}
+        ]}"#;
+        let entries = parse_index(json, "https://example.com/snapshots/list.json")?;
+        assert_eq!(entries[0].modified, None);
+        assert_eq!(entries[1].modified, Some(1714557600));
+        assert_eq!(entries[2].modified, Some(1714557600));
+        // Equal times fall back to the name
+        let latest = select_latest(
+            &entries,
+            IndexSelector::LatestByModified,
+            &default_pattern(),
+        )?;
+        assert_eq!(latest.name, "snapshot-3.tar.zst");
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_resolve_latest_from_local_index() -> Result<()> {
+        let temp_dir = tempfile::tempdir()?;
+        let published = temp_dir.path().join("published");
+        let downloads = temp_dir.path().join("downloads");
+        fs::create_dir_all(&published)?;
+        fs::create_dir_all(&downloads)?;
+        let index_url = format!("file://{}/index.json", published.display());
+
+        fs::write(
+            published.join("index.json"),
+            r#"["snap-1.tar.gz", "snap-2.tar.gz"]"#,
+        )?;
+        let options = DownloadOptions::default();
+        let pattern = default_pattern();
+        let resolve = || {
+            resolve_latest(
+                &index_url,
+                IndexSelector::LatestByName,
+                &pattern,
+                &downloads,
+                &options,
+            )
+        };
+        assert_eq!(
+            resolve().await?,
+            format!("file://{}/snap-2.tar.gz", published.display())
+        );
+
+        // A newer listing replaces the copy fetched by the earlier run
+        fs::write(
+            published.join("index.json"),
+            r#"["snap-2.tar.gz", "snap-3.tar.gz"]"#,
+        )?;
+        assert_eq!(
+            resolve().await?,
+            format!("file://{}/snap-3.tar.gz", published.display())
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_natural_cmp() {
+        assert_eq!(natural_cmp("height-9999", "height-10000"), Ordering::Less);
+        assert_eq!(natural_cmp("height-010", "height-9"), Ordering::Greater);
+        assert_eq!(natural_cmp("a.tar.gz", "b.tar.gz"), Ordering::Less);
+        assert_eq!(natural_cmp("snap-1", "snap-1.tar"), Ordering::Less);
+    }
+}
@@ -0,0 +1,79 @@
+use std::sync::atomic::{AtomicBool, Ordering};
+use tracing::debug;
+
+static ENABLED: AtomicBool = AtomicBool::new(false);
+
+/// Enable or disable notifications for the whole process (see `systemd_notify`)
+pub fn set_enabled(enabled: bool) {
+    ENABLED.store(enabled, Ordering::Relaxed);
+}
+
+/// Tell systemd the node is ready (`READY=1`), for units with `Type=notify`
+pub fn notify_ready() {
+    notify(&[sd::NotifyState::Ready]);
+}
+
+/// Report what the tool is doing (`STATUS=...`), shown by `systemctl status`
+pub fn notify_status(status: &str) {
+    notify(&[sd::NotifyState::Status(status)]);
+}
+
+/// Tell systemd the node is shutting down (`STOPPING=1`)
+pub fn notify_stopping() {
+    notify(&[sd::NotifyState::Stopping]);
+}
+
+/// Send notifications if enabled; without `NOTIFY_SOCKET` (not run by systemd) this does nothing
+fn notify(states: &[sd::NotifyState]) {
+    if !ENABLED.load(Ordering::Relaxed) {
+        return;
+    }
+    // A failed notification must never take the node down
+    if let Err(e) = sd::notify(false, states) {
+        debug!("Failed to notify systemd: {}", e);
+    }
+}
+
+#[cfg(target_os = "linux")]
+use sd_notify as sd;
+
+/// Stand-in for platforms without systemd, where notifications are dropped
+#[cfg(not(target_os = "linux"))]
+mod sd {
+    pub enum NotifyState<'a> {
+        Ready,
+        Stopping,
+        Status(&'a str),
+    }
+
+    /// Drop the notifications, keeping the status in the debug log
+    pub fn notify(_unset_env: bool, states: &[NotifyState]) -> std::io::Result<()> {
+        for state in states {
+            match state {
+                NotifyState::Ready | NotifyState::Stopping => {}
+                NotifyState::Status(status) => tracing::debug!("Status: {}", status),
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(all(test, target_os = "linux"))]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_notify_without_socket_is_noop() {
+        // Only meaningful when not started by systemd, so there is no socket to write to
+        if std::env::var_os("NOTIFY_SOCKET").is_some() {
+            return;
+        }
+        assert!(sd::notify(false, &[sd::NotifyState::Ready]).is_ok());
+
+        set_enabled(true);
+        notify_status("Downloading snapshot");
+        notify_ready();
+        notify_stopping();
+        set_enabled(false);
+    }
+}
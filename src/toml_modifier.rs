@@ -1,52 +1,118 @@
 use anyhow::{Context, Result};
+use serde::{Deserialize, Serialize};
 use serde_yaml::Value as YamlValue;
+use std::collections::{BTreeMap, BTreeSet, HashMap};
 use std::fs;
 use std::path::{Path, PathBuf};
 use toml::value::Table;
 use toml::Value as TomlValue;
+use toml_edit::{DocumentMut, Item};
 use tracing::info;
 
+/// YAML override value that removes the matching key (or whole table) from the TOML file
+/// e.g. `mempool: { wal_dir: "__delete__" }` drops `wal_dir` from the `[mempool]` table
+pub const DELETE_SENTINEL: &str = "__delete__";
+
+/// Suffix appended to a TOML file name for its pre-modification backup
+pub const BACKUP_SUFFIX: &str = ".bak";
+
+/// A single leaf value changed by applying overrides, as reported by [`TomlModifier::diff_file`]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TomlChange {
+    /// Dotted path of the changed key
+    pub key: String,
+    /// Previous value, or `None` if the key was added
+    pub old: Option<String>,
+    /// New value, or `None` if the key was removed
+    pub new: Option<String>,
+}
+
+impl std::fmt::Display for TomlChange {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let old = self.old.as_deref().unwrap_or("(unset)");
+        let new = self.new.as_deref().unwrap_or("(unset)");
+        write!(f, "{}: {} -> {}", self.key, old, new)
+    }
+}
+
+/// How an array in the YAML overrides is combined with an existing TOML array
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum ArrayMergeStrategy {
+    /// Replace the existing array with the override
+    #[default]
+    Replace,
+    /// Add the override items after the existing items
+    Append,
+    /// Add the override items before the existing items
+    Prepend,
+    /// Append only override items that are not already present
+    UniqueAppend,
+}
+
 pub struct TomlModifier {
     home_dir: PathBuf,
+    backup: bool,
+    array_merge: ArrayMergeStrategy,
+    array_merge_overrides: HashMap<String, ArrayMergeStrategy>,
 }
 
 impl TomlModifier {
     /// Create a new TomlModifier with the given workspace directory
+    /// Files are backed up before they are first modified
     pub fn new<P: AsRef<Path>>(home_dir: P) -> Self {
         Self {
             home_dir: home_dir.as_ref().to_path_buf(),
+            backup: true,
+            array_merge: ArrayMergeStrategy::default(),
+            array_merge_overrides: HashMap::new(),
         }
     }
 
+    /// Set the default array merge strategy and per-key overrides (keyed by dotted path, e.g. "p2p.seeds")
+    pub fn with_array_merge(
+        mut self,
+        strategy: ArrayMergeStrategy,
+        overrides: HashMap<String, ArrayMergeStrategy>,
+    ) -> Self {
+        self.array_merge = strategy;
+        self.array_merge_overrides = overrides;
+        self
+    }
+
+    /// Enable or disable writing `<file>.bak` before modifying a TOML file
+    pub fn with_backup(mut self, backup: bool) -> Self {
+        self.backup = backup;
+        self
+    }
+
     /// Apply configuration changes to app.toml and config.toml based on YAML configuration
     pub fn apply_config_changes(
         &self,
         app_yaml: Option<&YamlValue>,
         config_yaml: Option<&YamlValue>,
     ) -> Result<()> {
-        if let Some(app_config) = app_yaml {
-            self.modify_app_toml(app_config)
-                .context("Failed to modify app.toml")?;
-        }
+        let overrides: Vec<(&str, &YamlValue)> =
+            [("app.toml", app_yaml), ("config.toml", config_yaml)]
+                .into_iter()
+                .filter_map(|(file_name, yaml)| yaml.map(|yaml| (file_name, yaml)))
+                .collect();
+        self.apply_overrides(&overrides)
+    }
 
-        if let Some(config_toml) = config_yaml {
-            self.modify_config_toml(config_toml)
-                .context("Failed to modify config.toml")?;
+    /// Apply YAML overrides to TOML files, given as (path relative to `home_dir/config`, overrides)
+    pub fn apply_overrides(&self, overrides: &[(&str, &YamlValue)]) -> Result<()> {
+        for (file_name, yaml) in overrides {
+            self.modify_file(file_name, yaml)
+                .with_context(|| format!("Failed to modify {file_name}"))?;
         }
-
         Ok(())
     }
 
-    /// Modify app.toml with the provided YAML configuration
-    fn modify_app_toml(&self, app_yaml: &YamlValue) -> Result<()> {
-        let app_toml_path = self.home_dir.join("config/app.toml");
-        self.modify_toml(app_toml_path, app_yaml, "app.toml")
-    }
-
-    /// Modify config.toml with the provided YAML configuration
-    fn modify_config_toml(&self, config_yaml: &YamlValue) -> Result<()> {
-        let config_toml_path = self.home_dir.join("config/config.toml");
-        self.modify_toml(config_toml_path, config_yaml, "config.toml")
+    /// Modify a TOML file relative to `home_dir/config` with the provided YAML configuration
+    pub fn modify_file(&self, file_name: &str, yaml: &YamlValue) -> Result<()> {
+        let toml_path = crate::utils::config_file_path(&self.home_dir, file_name)?;
+        self.modify_toml(toml_path, yaml, file_name)
     }
 
     /// Generic method to modify a TOML file with the provided YAML configuration
@@ -65,18 +131,13 @@ impl TomlModifier {
             toml_path.display()
         ))?;
 
-        // Parse existing TOML
-        let mut toml_value: TomlValue = toml::from_str(&toml_content)
-            .context(format!("Failed to parse {file_name} content"))?;
+        if self.backup {
+            Self::backup_toml(&toml_path, &toml_content, file_name)?;
+        }
 
-        // Convert YAML to TOML-compatible structure and merge
-        let yaml_as_toml = Self::yaml_to_toml(yaml_config)?;
-        Self::merge_toml_values(&mut toml_value, &yaml_as_toml);
+        let modified_toml = self.apply_to_content(&toml_content, yaml_config, file_name)?;
 
         // Write back to file
-        let modified_toml = toml::to_string_pretty(&toml_value)
-            .context(format!("Failed to serialize modified {file_name}"))?;
-
         fs::write(&toml_path, modified_toml).context(format!(
             "Failed to write modified {} to {}",
             file_name,
@@ -87,6 +148,84 @@ impl TomlModifier {
         Ok(())
     }
 
+    /// Compute the changes overrides would make to a TOML file relative to `home_dir/config`, without writing
+    pub fn diff_file(&self, file_name: &str, yaml: &YamlValue) -> Result<Vec<TomlChange>> {
+        let toml_path = crate::utils::config_file_path(&self.home_dir, file_name)?;
+        let original = fs::read_to_string(&toml_path).context(format!(
+            "Failed to read {} at {}",
+            file_name,
+            toml_path.display()
+        ))?;
+        let modified = self.apply_to_content(&original, yaml, file_name)?;
+        Self::diff_content(&original, &modified)
+    }
+
+    /// Merge overrides into TOML content, returning the modified content
+    fn apply_to_content(
+        &self,
+        toml_content: &str,
+        yaml_config: &YamlValue,
+        file_name: &str,
+    ) -> Result<String> {
+        // Parse existing TOML, keeping comments and key order intact
+        let mut document: DocumentMut = toml_content
+            .parse()
+            .context(format!("Failed to parse {file_name} content"))?;
+
+        // Convert YAML to TOML-compatible structure and merge
+        let yaml_as_toml = Self::yaml_to_toml(yaml_config)?;
+        self.merge_toml_values(document.as_item_mut(), &yaml_as_toml);
+
+        Ok(document.to_string())
+    }
+
+    /// List the leaf values that differ between two TOML documents
+    fn diff_content(original: &str, modified: &str) -> Result<Vec<TomlChange>> {
+        let mut old_values = BTreeMap::new();
+        let mut new_values = BTreeMap::new();
+        flatten_toml(&toml::from_str(original)?, "", &mut old_values);
+        flatten_toml(&toml::from_str(modified)?, "", &mut new_values);
+
+        let keys: BTreeSet<&String> = old_values.keys().chain(new_values.keys()).collect();
+        Ok(keys
+            .into_iter()
+            .filter_map(|key| {
+                let old = old_values.get(key);
+                let new = new_values.get(key);
+                (old != new).then(|| TomlChange {
+                    key: key.clone(),
+                    old: old.cloned(),
+                    new: new.cloned(),
+                })
+            })
+            .collect())
+    }
+
+    /// Save the original file content next to it, unless a backup already exists
+    /// An existing backup is kept so it always holds the content from before the first modification
+    fn backup_toml(toml_path: &Path, original_content: &str, file_name: &str) -> Result<()> {
+        let mut backup_name = toml_path.as_os_str().to_os_string();
+        backup_name.push(BACKUP_SUFFIX);
+        let backup_path = PathBuf::from(backup_name);
+
+        if backup_path.exists() {
+            info!(
+                "Backup of {} already exists at {}, not overwriting",
+                file_name,
+                backup_path.display()
+            );
+            return Ok(());
+        }
+
+        fs::write(&backup_path, original_content).context(format!(
+            "Failed to write backup of {} to {}",
+            file_name,
+            backup_path.display()
+        ))?;
+        info!("Backed up {} to {}", file_name, backup_path.display());
+        Ok(())
+    }
+
     /// Convert YAML value to TOML value
     fn yaml_to_toml(yaml_value: &YamlValue) -> Result<TomlValue> {
         match yaml_value {
@@ -113,7 +252,10 @@ impl TomlModifier {
                 let mut toml_table = Table::new();
                 for (key, value) in map {
                     if let YamlValue::String(key_str) = key {
-                        toml_table.insert(key_str.to_string(), Self::yaml_to_toml(value)?);
+                        // Dotted keys like "p2p.seeds" are shorthand for nested tables
+                        let path: Vec<&str> = key_str.split('.').collect();
+                        Self::insert_path(&mut toml_table, &path, Self::yaml_to_toml(value)?)
+                            .with_context(|| format!("Invalid override key '{key_str}'"))?;
                     } else {
                         anyhow::bail!("YAML mapping key must be a string");
                     }
@@ -127,28 +269,201 @@ impl TomlModifier {
         }
     }
 
-    /// Recursively merge TOML values, preserving existing structure
-    fn merge_toml_values(target: &mut TomlValue, source: &TomlValue) {
-        match (target, source) {
-            (TomlValue::Table(target_table), TomlValue::Table(source_table)) => {
+    /// Insert a value at a dotted key path, creating intermediate tables and merging with
+    /// tables already produced by other keys (so `p2p.seeds` and `p2p: {...}` can coexist)
+    fn insert_path(table: &mut Table, path: &[&str], value: TomlValue) -> Result<()> {
+        let (first, rest) = path.split_first().context("Empty key")?;
+        if first.is_empty() {
+            anyhow::bail!("Empty key segment");
+        }
+
+        if rest.is_empty() {
+            match (table.get_mut(*first), value) {
+                (Some(TomlValue::Table(existing)), TomlValue::Table(source)) => {
+                    for (key, value) in source {
+                        Self::insert_path(existing, &[key.as_str()], value)?;
+                    }
+                }
+                (_, value) => {
+                    table.insert(first.to_string(), value);
+                }
+            }
+            return Ok(());
+        }
+
+        let child = table
+            .entry(first.to_string())
+            .or_insert_with(|| TomlValue::Table(Table::new()));
+        match child {
+            TomlValue::Table(child_table) => Self::insert_path(child_table, rest, value),
+            _ => anyhow::bail!("'{}' is already set to a non-table value", first),
+        }
+    }
+
+    /// Recursively merge TOML values into an editable document item, preserving existing structure
+    /// Only the touched values change; comments, formatting and key order are kept
+    fn merge_toml_values(&self, target: &mut Item, source: &TomlValue) {
+        self.merge_at_path(target, source, &mut Vec::new());
+    }
+
+    fn merge_at_path(&self, target: &mut Item, source: &TomlValue, path: &mut Vec<String>) {
+        // Arrays are combined according to the configured strategy
+        let strategy = self.array_strategy(path);
+        if let (Some(target_array), TomlValue::Array(source_array)) =
+            (target.as_array_mut(), source)
+        {
+            if strategy != ArrayMergeStrategy::Replace {
+                Self::merge_arrays(target_array, source_array, strategy);
+                return;
+            }
+        }
+
+        match (target.as_table_like_mut(), source) {
+            (Some(target_table), TomlValue::Table(source_table)) => {
                 for (key, source_value) in source_table {
+                    if Self::is_delete_sentinel(source_value) {
+                        // Remove the key or table if present
+                        target_table.remove(key);
+                        continue;
+                    }
+
                     match target_table.get_mut(key.as_str()) {
                         Some(target_value) => {
                             // Recursively merge if both are tables
-                            Self::merge_toml_values(target_value, source_value);
+                            path.push(key.clone());
+                            self.merge_at_path(target_value, source_value, path);
+                            path.pop();
                         }
                         None => {
                             // Insert new key-value pair
-                            target_table.insert(key.to_string(), source_value.clone());
+                            target_table.insert(key, Self::toml_to_item(source_value));
                         }
                     }
                 }
             }
-            (target, source) => {
-                // For non-table values, replace the target with the source
-                *target = source.clone();
+            (_, source) => {
+                // For non-table values, replace the target with the source,
+                // keeping any surrounding whitespace and trailing comment
+                let mut replacement = Self::toml_to_item(source);
+                if let (Item::Value(old), Item::Value(new)) = (&*target, &mut replacement) {
+                    *new.decor_mut() = old.decor().clone();
+                }
+                *target = replacement;
+            }
+        }
+    }
+
+    /// Array merge strategy for the key at the given path
+    fn array_strategy(&self, path: &[String]) -> ArrayMergeStrategy {
+        self.array_merge_overrides
+            .get(&path.join("."))
+            .copied()
+            .unwrap_or(self.array_merge)
+    }
+
+    /// Combine the override items into an existing array according to the strategy
+    fn merge_arrays(
+        target: &mut toml_edit::Array,
+        source: &[TomlValue],
+        strategy: ArrayMergeStrategy,
+    ) {
+        match strategy {
+            ArrayMergeStrategy::Replace => {
+                *target = source.iter().map(Self::toml_to_edit_value).collect();
+            }
+            ArrayMergeStrategy::Append => {
+                for item in source {
+                    target.push(Self::toml_to_edit_value(item));
+                }
+            }
+            ArrayMergeStrategy::Prepend => {
+                for (i, item) in source.iter().enumerate() {
+                    target.insert(i, Self::toml_to_edit_value(item));
+                }
+            }
+            ArrayMergeStrategy::UniqueAppend => {
+                for item in source {
+                    let value = Self::toml_to_edit_value(item);
+                    let key = Self::value_key(&value);
+                    if !target
+                        .iter()
+                        .any(|existing| Self::value_key(existing) == key)
+                    {
+                        target.push(value);
+                    }
+                }
+            }
+        }
+    }
+
+    /// Formatting-independent representation of a value, used to detect duplicates
+    fn value_key(value: &toml_edit::Value) -> String {
+        let mut value = value.clone();
+        value.decor_mut().clear();
+        value.to_string()
+    }
+
+    /// Whether a source value asks for the target key to be deleted
+    fn is_delete_sentinel(value: &TomlValue) -> bool {
+        value.as_str() == Some(DELETE_SENTINEL)
+    }
+
+    /// Convert a TOML value into a document item, using standard tables for nested tables
+    /// Delete sentinels have nothing to remove in newly created tables and are skipped
+    fn toml_to_item(value: &TomlValue) -> Item {
+        match value {
+            TomlValue::Table(table) => {
+                let mut edit_table = toml_edit::Table::new();
+                for (key, value) in table {
+                    if !Self::is_delete_sentinel(value) {
+                        edit_table.insert(key, Self::toml_to_item(value));
+                    }
+                }
+                Item::Table(edit_table)
+            }
+            other => Item::Value(Self::toml_to_edit_value(other)),
+        }
+    }
+
+    /// Convert a TOML value into an inline document value
+    fn toml_to_edit_value(value: &TomlValue) -> toml_edit::Value {
+        match value {
+            TomlValue::String(s) => s.as_str().into(),
+            TomlValue::Integer(i) => (*i).into(),
+            TomlValue::Float(f) => (*f).into(),
+            TomlValue::Boolean(b) => (*b).into(),
+            TomlValue::Datetime(dt) => (*dt).into(),
+            TomlValue::Array(array) => array
+                .iter()
+                .map(Self::toml_to_edit_value)
+                .collect::<toml_edit::Array>()
+                .into(),
+            TomlValue::Table(table) => table
+                .iter()
+                .filter(|(_, value)| !Self::is_delete_sentinel(value))
+                .map(|(key, value)| (key.clone(), Self::toml_to_edit_value(value)))
+                .collect::<toml_edit::InlineTable>()
+                .into(),
+        }
+    }
+}
+
+/// Collect every non-table value under its dotted path
+fn flatten_toml(value: &TomlValue, path: &str, out: &mut BTreeMap<String, String>) {
+    match value {
+        TomlValue::Table(table) => {
+            for (key, value) in table {
+                let key_path = if path.is_empty() {
+                    key.clone()
+                } else {
+                    format!("{path}.{key}")
+                };
+                flatten_toml(value, &key_path, out);
             }
         }
+        other => {
+            out.insert(path.to_string(), other.to_string());
+        }
     }
 }
 
@@ -161,22 +476,14 @@ mod tests {
 
     #[test]
     fn test_merge_toml_values() {
-        let mut target = TomlValue::Table({
-            let mut t = Table::new();
-            t.insert(
-                "existing".to_string(),
-                TomlValue::String("value".to_string()),
-            );
-            t.insert(
-                "section".to_string(),
-                TomlValue::Table({
-                    let mut st = Table::new();
-                    st.insert("key1".to_string(), TomlValue::Integer(1));
-                    st
-                }),
-            );
-            t
-        });
+        let mut target: DocumentMut = r#"
+existing = "value"
+
+[section]
+key1 = 1
+"#
+        .parse()
+        .unwrap();
 
         let source = TomlValue::Table({
             let mut t = Table::new();
@@ -193,28 +500,323 @@ mod tests {
         });
 
         let modifier = TomlModifier::new("/tmp");
-        modifier.merge_toml_values(&mut target, source);
+        modifier.merge_toml_values(target.as_item_mut(), &source);
 
-        if let TomlValue::Table(table) = target {
-            assert_eq!(table.get("existing").unwrap().as_str().unwrap(), "value");
-            assert_eq!(table.get("new").unwrap().as_str().unwrap(), "value");
+        assert_eq!(target["existing"].as_str().unwrap(), "value");
+        assert_eq!(target["new"].as_str().unwrap(), "value");
+        assert_eq!(target["section"]["key1"].as_integer().unwrap(), 1);
+        assert_eq!(target["section"]["key2"].as_integer().unwrap(), 2);
+    }
 
-            if let TomlValue::Table(section) = table.get("section").unwrap() {
-                assert_eq!(section.get("key1").unwrap().as_integer().unwrap(), 1);
-                assert_eq!(section.get("key2").unwrap().as_integer().unwrap(), 2);
-            } else {
-                panic!("Expected section to be a table");
-            }
-        } else {
-            panic!("Expected target to be a table");
-        }
+    fn merge_yaml(target: &mut DocumentMut, yaml: &str) {
+        merge_yaml_with(&TomlModifier::new("/tmp"), target, yaml);
+    }
+
+    fn merge_yaml_with(modifier: &TomlModifier, target: &mut DocumentMut, yaml: &str) {
+        let source = TomlModifier::yaml_to_toml(&serde_yaml::from_str(yaml).unwrap()).unwrap();
+        modifier.merge_toml_values(target.as_item_mut(), &source);
+    }
+
+    fn merged_array(strategy: ArrayMergeStrategy) -> Vec<String> {
+        let mut target: DocumentMut = "[p2p]\npeers = [\"a\", \"b\"]\n".parse().unwrap();
+        let modifier = TomlModifier::new("/tmp").with_array_merge(strategy, HashMap::new());
+        merge_yaml_with(&modifier, &mut target, "p2p:\n  peers: [\"b\", \"c\"]\n");
+
+        target["p2p"]["peers"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|v| v.as_str().unwrap().to_string())
+            .collect()
+    }
+
+    #[test]
+    fn test_array_merge_replace() {
+        assert_eq!(merged_array(ArrayMergeStrategy::Replace), ["b", "c"]);
+    }
+
+    #[test]
+    fn test_array_merge_append() {
+        assert_eq!(
+            merged_array(ArrayMergeStrategy::Append),
+            ["a", "b", "b", "c"]
+        );
+    }
+
+    #[test]
+    fn test_array_merge_prepend() {
+        assert_eq!(
+            merged_array(ArrayMergeStrategy::Prepend),
+            ["b", "c", "a", "b"]
+        );
+    }
+
+    #[test]
+    fn test_array_merge_unique_append() {
+        assert_eq!(
+            merged_array(ArrayMergeStrategy::UniqueAppend),
+            ["a", "b", "c"]
+        );
+    }
+
+    #[test]
+    fn test_array_merge_per_key_override() {
+        let mut target: DocumentMut = "[p2p]\npeers = [\"a\", \"b\"]\nseeds = [\"x\", \"y\"]\n"
+            .parse()
+            .unwrap();
+        let overrides = HashMap::from([("p2p.peers".to_string(), ArrayMergeStrategy::Append)]);
+        let modifier =
+            TomlModifier::new("/tmp").with_array_merge(ArrayMergeStrategy::Replace, overrides);
+
+        merge_yaml_with(
+            &modifier,
+            &mut target,
+            "p2p:\n  peers: [\"c\"]\n  seeds: [\"z\"]\n",
+        );
+
+        assert_eq!(target["p2p"]["peers"].as_array().unwrap().len(), 3);
+        assert_eq!(target["p2p"]["seeds"].as_array().unwrap().len(), 1);
+    }
+
+    #[test]
+    fn test_merge_deletes_scalar_key() {
+        let mut target: DocumentMut = r#"
+[mempool]
+size = 5000
+wal_dir = ""
+"#
+        .parse()
+        .unwrap();
+
+        merge_yaml(&mut target, "mempool:\n  wal_dir: __delete__\n");
+
+        assert!(target["mempool"].get("wal_dir").is_none());
+        assert_eq!(target["mempool"]["size"].as_integer().unwrap(), 5000);
+    }
+
+    #[test]
+    fn test_merge_deletes_sub_table() {
+        let mut target: DocumentMut = r#"
+[statesync]
+enable = false
+
+[fastsync]
+version = "v0"
+
+[fastsync.extra]
+key = 1
+"#
+        .parse()
+        .unwrap();
+
+        merge_yaml(
+            &mut target,
+            "fastsync: __delete__\nstatesync:\n  enable: true\n",
+        );
+
+        assert!(target.get("fastsync").is_none());
+        assert!(!target.to_string().contains("fastsync"));
+        assert!(target["statesync"]["enable"].as_bool().unwrap());
+    }
+
+    #[test]
+    fn test_merge_delete_missing_key_is_noop() {
+        let mut target: DocumentMut = "[p2p]\nseeds = \"\"\n".parse().unwrap();
+
+        merge_yaml(
+            &mut target,
+            "p2p:\n  removed: __delete__\nnew_section:\n  gone: __delete__\n  kept: 1\n",
+        );
+
+        assert!(target["p2p"].get("removed").is_none());
+        assert!(target["new_section"].get("gone").is_none());
+        assert_eq!(target["new_section"]["kept"].as_integer().unwrap(), 1);
+    }
+
+    #[test]
+    fn test_yaml_to_toml_dotted_keys() {
+        let yaml: YamlValue = serde_yaml::from_str(
+            r#"
+p2p:
+  seeds: "seed1:26656"
+p2p.max_num_inbound_peers: 80
+statesync.rpc_servers: "rpc1:26657,rpc2:26657"
+"#,
+        )
+        .unwrap();
+
+        let toml = TomlModifier::yaml_to_toml(&yaml).unwrap();
+        assert_eq!(toml["p2p"]["seeds"].as_str().unwrap(), "seed1:26656");
+        assert_eq!(
+            toml["p2p"]["max_num_inbound_peers"].as_integer().unwrap(),
+            80
+        );
+        assert_eq!(
+            toml["statesync"]["rpc_servers"].as_str().unwrap(),
+            "rpc1:26657,rpc2:26657"
+        );
+    }
+
+    #[test]
+    fn test_yaml_to_toml_dotted_key_conflicts_with_scalar() {
+        let yaml: YamlValue = serde_yaml::from_str("pruning: custom\npruning.keep: 100\n").unwrap();
+        assert!(TomlModifier::yaml_to_toml(&yaml).is_err());
+    }
+
+    #[test]
+    fn test_merge_dotted_and_nested_keys_same_section() {
+        let mut target: DocumentMut = r#"
+[p2p]
+seeds = ""
+max_num_inbound_peers = 40
+max_num_outbound_peers = 10
+"#
+        .parse()
+        .unwrap();
+
+        merge_yaml(
+            &mut target,
+            "p2p.max_num_inbound_peers: 80\np2p:\n  seeds: \"seed1:26656\"\n",
+        );
+
+        assert_eq!(target["p2p"]["seeds"].as_str().unwrap(), "seed1:26656");
+        assert_eq!(
+            target["p2p"]["max_num_inbound_peers"].as_integer().unwrap(),
+            80
+        );
+        assert_eq!(
+            target["p2p"]["max_num_outbound_peers"]
+                .as_integer()
+                .unwrap(),
+            10
+        );
+    }
+
+    #[test]
+    fn test_merge_preserves_comments_and_order() {
+        let mut target: DocumentMut = r#"# Top-level comment
+[p2p]
+# Comma separated list of seed nodes
+seeds = "" # inline note
+# Maximum number of inbound peers
+max_num_inbound_peers = 40
+
+[rpc]
+laddr = "tcp://127.0.0.1:26657"
+"#
+        .parse()
+        .unwrap();
+
+        merge_yaml(&mut target, "p2p:\n  seeds: \"seed1:26656\"\n");
+
+        let output = target.to_string();
+        assert!(output.contains("# Top-level comment"));
+        assert!(output.contains("# Comma separated list of seed nodes"));
+        assert!(output.contains("seeds = \"seed1:26656\" # inline note"));
+        assert!(output.contains("# Maximum number of inbound peers"));
+        assert!(output.find("[p2p]").unwrap() < output.find("[rpc]").unwrap());
+    }
+
+    #[test]
+    fn test_modify_toml_writes_backup() -> Result<()> {
+        let temp_dir = tempdir()?;
+        let config_dir = temp_dir.path().join("config");
+        fs::create_dir_all(&config_dir)?;
+
+        let original = "# generated by init\n[rpc]\nladdr = \"tcp://127.0.0.1:26657\"\n";
+        let config_toml_path = config_dir.join("config.toml");
+        fs::write(&config_toml_path, original)?;
+
+        let config_yaml: YamlValue = serde_yaml::from_str("rpc:\n  laddr: tcp://0.0.0.0:26657\n")?;
+        let modifier = TomlModifier::new(temp_dir.path());
+        modifier.apply_config_changes(None, Some(&config_yaml))?;
+
+        let backup_path = config_dir.join("config.toml.bak");
+        assert_eq!(fs::read_to_string(&backup_path)?, original);
+        assert_ne!(fs::read_to_string(&config_toml_path)?, original);
+
+        // A second modification keeps the original backup
+        let config_yaml: YamlValue = serde_yaml::from_str("rpc:\n  laddr: tcp://0.0.0.0:36657\n")?;
+        modifier.apply_config_changes(None, Some(&config_yaml))?;
+        assert_eq!(fs::read_to_string(&backup_path)?, original);
+        Ok(())
+    }
+
+    #[test]
+    fn test_diff_file_does_not_write() -> Result<()> {
+        let temp_dir = tempdir()?;
+        let config_dir = temp_dir.path().join("config");
+        fs::create_dir_all(&config_dir)?;
+        let original = "[rpc]\nladdr = \"tcp://127.0.0.1:26657\"\n\n[mempool]\nwal_dir = \"\"\n";
+        fs::write(config_dir.join("config.toml"), original)?;
+
+        let yaml: YamlValue = serde_yaml::from_str(
+            "rpc:\n  laddr: tcp://0.0.0.0:26657\nmempool:\n  wal_dir: __delete__\np2p:\n  seeds: a@b:26656\n",
+        )?;
+        let changes = TomlModifier::new(temp_dir.path()).diff_file("config.toml", &yaml)?;
+
+        let rendered: Vec<String> = changes.iter().map(ToString::to_string).collect();
+        assert_eq!(
+            rendered,
+            [
+                r#"mempool.wal_dir: "" -> (unset)"#,
+                r#"p2p.seeds: (unset) -> "a@b:26656""#,
+                r#"rpc.laddr: "tcp://127.0.0.1:26657" -> "tcp://0.0.0.0:26657""#,
+            ]
+        );
+        assert_eq!(
+            fs::read_to_string(config_dir.join("config.toml"))?,
+            original
+        );
+        assert!(!config_dir.join("config.toml.bak").exists());
+        Ok(())
+    }
+
+    #[test]
+    fn test_modify_toml_without_backup() -> Result<()> {
+        let temp_dir = tempdir()?;
+        let config_dir = temp_dir.path().join("config");
+        fs::create_dir_all(&config_dir)?;
+        fs::write(config_dir.join("app.toml"), "[api]\nenable = false\n")?;
+
+        let app_yaml: YamlValue = serde_yaml::from_str("api:\n  enable: true\n")?;
+        TomlModifier::new(temp_dir.path())
+            .with_backup(false)
+            .apply_config_changes(Some(&app_yaml), None)?;
+
+        assert!(!config_dir.join("app.toml.bak").exists());
+        Ok(())
+    }
+
+    #[test]
+    fn test_modify_client_toml() -> Result<()> {
+        let temp_dir = tempdir()?;
+        let config_dir = temp_dir.path().join("config");
+        fs::create_dir_all(&config_dir)?;
+        let client_toml_path = config_dir.join("client.toml");
+        fs::write(
+            &client_toml_path,
+            "chain-id = \"\"\nkeyring-backend = \"os\"\noutput = \"text\"\n",
+        )?;
+
+        let client_yaml: YamlValue =
+            serde_yaml::from_str("chain-id: cosmoshub-4\nkeyring-backend: test\n")?;
+        TomlModifier::new(temp_dir.path())
+            .with_backup(false)
+            .apply_overrides(&[("client.toml", &client_yaml)])?;
+
+        assert_eq!(
+            fs::read_to_string(&client_toml_path)?,
+            "chain-id = \"cosmoshub-4\"\nkeyring-backend = \"test\"\noutput = \"text\"\n"
+        );
+        Ok(())
     }
 
     #[test]
     fn test_modify_toml_files() -> Result<()> {
         // Create a temporary directory to simulate workspace
         let temp_dir = tempdir()?;
-        let config_dir = temp_dir.path().join("home/config");
+        let config_dir = temp_dir.path().join("config");
         fs::create_dir_all(&config_dir)?;
 
         // Create sample app.toml
@@ -267,7 +869,7 @@ p2p:
 
         // Apply modifications
         let modifier = TomlModifier::new(temp_dir.path());
-        modifier.apply_config_changes(Some(app_yaml), Some(config_yaml))?;
+        modifier.apply_config_changes(Some(&app_yaml), Some(&config_yaml))?;
 
         // Verify app.toml changes
         let modified_app_toml = fs::read_to_string(&app_toml_path)?;
@@ -275,11 +877,11 @@ p2p:
 
         if let TomlValue::Table(table) = app_value {
             if let TomlValue::Table(api) = table.get("api").unwrap() {
-                assert_eq!(api.get("enable").unwrap().as_bool().unwrap(), true);
-                assert_eq!(api.get("swagger").unwrap().as_bool().unwrap(), true);
+                assert!(api.get("enable").unwrap().as_bool().unwrap());
+                assert!(api.get("swagger").unwrap().as_bool().unwrap());
             }
             if let TomlValue::Table(grpc) = table.get("grpc").unwrap() {
-                assert_eq!(grpc.get("enable").unwrap().as_bool().unwrap(), true);
+                assert!(grpc.get("enable").unwrap().as_bool().unwrap());
             }
             if let TomlValue::Table(state_sync) = table.get("state-sync").unwrap() {
                 assert_eq!(
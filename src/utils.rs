@@ -1,7 +1,12 @@
-use anyhow::Result;
+use anyhow::{Context, Result};
+use md5::{Digest, Md5};
+use percent_encoding::percent_decode_str;
 use std::fs;
+use std::path::{Component, Path, PathBuf};
+use tracing::{info, warn};
 
 use crate::config::Config;
+use crate::download_record::{self, DownloadKind};
 
 pub fn create_directories(config: &Config) -> Result<()> {
     // Create base directory
@@ -18,3 +23,584 @@ pub fn create_directories(config: &Config) -> Result<()> {
 
     Ok(())
 }
+
+/// Remove downloaded snapshot files (the snapshot, its part files and resume state) from
+/// `downloads_dir`, and the binary archive too if `include_binary`, returning the bytes reclaimed
+pub fn prune_downloads(config: &Config, include_binary: bool) -> Result<u64> {
+    let reclaimed = remove_downloads(config, true, include_binary, false)?;
+    info!(
+        "Reclaimed {} bytes from {}",
+        reclaimed,
+        config.downloads_dir.display()
+    );
+    Ok(reclaimed)
+}
+
+/// Remove the selected downloads from `downloads_dir`: the snapshot (with its part files and
+/// resume state), the binary archive (with its signature) and the address book
+/// Returns the bytes reclaimed. Files are found by the paths recorded when they were downloaded
+/// and by the names their URLs map to; parts listed from an S3 prefix are not
+pub fn remove_downloads(
+    config: &Config,
+    snapshot: bool,
+    binary: bool,
+    addrbook: bool,
+) -> Result<u64> {
+    let mut kinds = Vec::new();
+    if snapshot {
+        kinds.push(DownloadKind::Snapshot);
+    }
+    if binary {
+        kinds.extend([DownloadKind::Binary, DownloadKind::BinarySignature]);
+    }
+    if addrbook {
+        kinds.push(DownloadKind::Addrbook);
+    }
+    let mut paths = download_record::take_downloads(config, &kinds)?;
+
+    let mut names = Vec::new();
+    if snapshot {
+        let snapshot_filename = config.get_snapshot_filename()?;
+        names.push(format!("{snapshot_filename}.progress"));
+        names.push(snapshot_filename);
+        let urls = config.get_snapshot_urls();
+        if urls.len() > 1 {
+            names.extend(urls.iter().map(|url| download_filename(url)));
+        }
+    }
+    if binary {
+        names.extend(
+            config
+                .get_binary_sources()
+                .iter()
+                .map(|url| download_filename(url)),
+        );
+        if let Some(ref url) = config.binary_signature_url {
+            names.push(download_filename(url));
+        }
+    }
+    if addrbook {
+        names.extend(
+            config
+                .get_addrbook_sources()
+                .iter()
+                .map(|url| download_filename(url)),
+        );
+    }
+    paths.extend(names.iter().map(|name| config.downloads_dir.join(name)));
+    paths.sort();
+    paths.dedup();
+
+    let mut reclaimed = 0;
+    for path in paths {
+        let Ok(metadata) = fs::metadata(&path) else {
+            continue;
+        };
+        match fs::remove_file(&path) {
+            Ok(()) => {
+                info!("Removed {} ({} bytes)", path.display(), metadata.len());
+                reclaimed += metadata.len();
+            }
+            Err(e) => warn!("Failed to remove {}: {}", path.display(), e),
+        }
+    }
+    Ok(reclaimed)
+}
+
+/// Files kept when clearing the snapshot directories, so a validator never forgets the last
+/// height it signed if the snapshot does not bring its own
+const PRESERVED_FILES: &[&str] = &["priv_validator_state.json"];
+
+/// Empty the `snapshot_dirs` of `home_dir` before extracting a snapshot from scratch (`--force`
+/// or `clean_target_before_extract`)
+/// Everything else in the home directory, such as the config and keys, is left alone
+pub fn clear_snapshot_dirs(config: &Config) -> Result<()> {
+    for dir in &config.snapshot_dirs {
+        let path = config.home_dir.join(dir);
+        let entries = match fs::read_dir(&path) {
+            Ok(entries) => entries,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => continue,
+            Err(e) => return Err(e).with_context(|| format!("Failed to read {}", path.display())),
+        };
+
+        info!("Clearing {}", path.display());
+        for entry in entries {
+            let entry = entry.with_context(|| format!("Failed to read {}", path.display()))?;
+            if PRESERVED_FILES
+                .iter()
+                .any(|name| entry.file_name() == *name)
+            {
+                continue;
+            }
+            let entry_path = entry.path();
+            let result = match entry.file_type() {
+                Ok(file_type) if file_type.is_dir() => fs::remove_dir_all(&entry_path),
+                _ => fs::remove_file(&entry_path),
+            };
+            result.with_context(|| format!("Failed to remove {}", entry_path.display()))?;
+        }
+    }
+    Ok(())
+}
+
+/// Apply `chown_user`/`chown_group` and `dir_mode`/`file_mode` to `home_dir` and everything
+/// below it, so a node running as another user can use what was extracted as root
+/// Symbolic links are re-owned but not followed; does nothing when none of the options is set
+#[cfg(unix)]
+pub fn fix_home_permissions(config: &Config) -> Result<()> {
+    let fixup = PermissionFixup {
+        uid: config.chown_user.as_deref().map(lookup_uid).transpose()?,
+        gid: config.chown_group.as_deref().map(lookup_gid).transpose()?,
+        dir_mode: config.get_dir_mode()?,
+        file_mode: config.get_file_mode()?,
+    };
+    if fixup == PermissionFixup::default() {
+        return Ok(());
+    }
+
+    let entries = fixup.apply(&config.home_dir)?;
+    info!(
+        "Set ownership and permissions of {} entries under {}",
+        entries,
+        config.home_dir.display()
+    );
+    Ok(())
+}
+
+/// Ownership and permissions are Unix concepts, so there is nothing to do elsewhere
+#[cfg(not(unix))]
+pub fn fix_home_permissions(_config: &Config) -> Result<()> {
+    Ok(())
+}
+
+#[cfg(unix)]
+#[derive(Debug, Default, PartialEq)]
+struct PermissionFixup {
+    uid: Option<u32>,
+    gid: Option<u32>,
+    dir_mode: Option<u32>,
+    file_mode: Option<u32>,
+}
+
+#[cfg(unix)]
+impl PermissionFixup {
+    /// Fix `path` and, for a directory, everything below it, returning the number of entries
+    fn apply(&self, path: &Path) -> Result<u64> {
+        use std::os::unix::fs::{lchown, PermissionsExt};
+
+        let metadata = fs::symlink_metadata(path)
+            .with_context(|| format!("Failed to read metadata of {}", path.display()))?;
+        if self.uid.is_some() || self.gid.is_some() {
+            lchown(path, self.uid, self.gid)
+                .with_context(|| format!("Failed to change owner of {}", path.display()))?;
+        }
+
+        let mut entries = 1;
+        let mode = if metadata.is_dir() {
+            for entry in fs::read_dir(path)
+                .with_context(|| format!("Failed to read directory {}", path.display()))?
+            {
+                let entry = entry
+                    .with_context(|| format!("Failed to read directory {}", path.display()))?;
+                entries += self.apply(&entry.path())?;
+            }
+            // Only after the children, so a mode without search permission cannot stop the walk
+            self.dir_mode
+        } else if metadata.is_file() {
+            self.file_mode
+        } else {
+            None
+        };
+        if let Some(mode) = mode {
+            fs::set_permissions(path, fs::Permissions::from_mode(mode))
+                .with_context(|| format!("Failed to set permissions of {}", path.display()))?;
+        }
+        Ok(entries)
+    }
+}
+
+/// Resolve a user name or numeric uid
+#[cfg(unix)]
+fn lookup_uid(user: &str) -> Result<u32> {
+    if let Ok(uid) = user.parse() {
+        return Ok(uid);
+    }
+    lookup_entry(user, libc::getpwnam_r, |entry| entry.pw_uid)
+        .with_context(|| format!("Failed to look up chown_user '{user}'"))?
+        .with_context(|| format!("Unknown chown_user '{user}'"))
+}
+
+/// Resolve a group name or numeric gid
+#[cfg(unix)]
+fn lookup_gid(group: &str) -> Result<u32> {
+    if let Ok(gid) = group.parse() {
+        return Ok(gid);
+    }
+    lookup_entry(group, libc::getgrnam_r, |entry| entry.gr_gid)
+        .with_context(|| format!("Failed to look up chown_group '{group}'"))?
+        .with_context(|| format!("Unknown chown_group '{group}'"))
+}
+
+/// Look up a user's home directory, `None` if there is no such user
+#[cfg(unix)]
+pub fn lookup_user_home(user: &str) -> Result<Option<PathBuf>> {
+    use std::os::unix::ffi::OsStrExt;
+
+    lookup_entry(user, libc::getpwnam_r, |entry| {
+        // SAFETY: `pw_dir` is a NUL-terminated string in the lookup's buffer
+        let dir = unsafe { std::ffi::CStr::from_ptr(entry.pw_dir) };
+        PathBuf::from(std::ffi::OsStr::from_bytes(dir.to_bytes()))
+    })
+    .with_context(|| format!("Failed to look up user '{user}'"))
+}
+
+/// There is no user database to look other users up in elsewhere
+#[cfg(not(unix))]
+pub fn lookup_user_home(_user: &str) -> Result<Option<PathBuf>> {
+    Ok(None)
+}
+
+/// Signature shared by `getpwnam_r` and `getgrnam_r`
+#[cfg(unix)]
+type LookupFn<T> = unsafe extern "C" fn(
+    *const libc::c_char,
+    *mut T,
+    *mut libc::c_char,
+    libc::size_t,
+    *mut *mut T,
+) -> libc::c_int;
+
+/// Look `name` up with `getpwnam_r` or `getgrnam_r` and read what is needed from the entry
+/// Going through libc follows NSS, so users from LDAP, systemd and the like are found too.
+/// Returns `None` if there is no such entry.
+#[cfg(unix)]
+fn lookup_entry<T, R>(
+    name: &str,
+    lookup: LookupFn<T>,
+    read: impl FnOnce(&T) -> R,
+) -> std::io::Result<Option<R>> {
+    let Ok(name) = std::ffi::CString::new(name) else {
+        return Ok(None);
+    };
+    let mut buf: Vec<libc::c_char> = vec![0; 1024];
+    loop {
+        let mut entry = std::mem::MaybeUninit::<T>::uninit();
+        let mut result = std::ptr::null_mut();
+        // SAFETY: every pointer is valid for the call and `buf.len()` is the buffer's length
+        let err = unsafe {
+            lookup(
+                name.as_ptr(),
+                entry.as_mut_ptr(),
+                buf.as_mut_ptr(),
+                buf.len(),
+                &mut result,
+            )
+        };
+        match err {
+            // SAFETY: a non-null result points at the filled in `entry`, whose strings live in
+            // `buf`
+            0 if !result.is_null() => return Ok(Some(read(unsafe { &*result }))),
+            // Some systems report a missing entry as one of these errors
+            0 | libc::ENOENT | libc::ESRCH | libc::EBADF | libc::EPERM => return Ok(None),
+            libc::ERANGE if buf.len() < 1 << 20 => buf.resize(buf.len() * 2, 0),
+            err => return Err(std::io::Error::from_raw_os_error(err)),
+        }
+    }
+}
+
+/// Derive a local filename from the last path segment of a URL
+/// The query string and fragment are stripped and the result is percent-decoded and sanitized
+pub fn filename_from_url(url: &str) -> Option<String> {
+    let path = url.split(['?', '#']).next().unwrap_or(url);
+    let segment = path.rsplit('/').next()?;
+    sanitize_filename(&percent_decode_str(segment).decode_utf8_lossy())
+}
+
+/// Derive a local filename from a URL, falling back to `download-<hash of the URL>` when the
+/// URL has no usable name so the same URL always maps to the same file
+pub fn download_filename(url: &str) -> String {
+    filename_from_url(url).unwrap_or_else(|| {
+        let hash = format!("{:x}", Md5::digest(url.as_bytes()));
+        format!("download-{}", &hash[..16])
+    })
+}
+
+/// Turn a server- or URL-provided name into a single safe path component
+/// Path separators (e.g. from a decoded `%2F`) and control characters are replaced with `_`;
+/// empty names and `.`/`..` are rejected
+pub fn sanitize_filename(name: &str) -> Option<String> {
+    let file_name: String = name
+        .trim()
+        .chars()
+        .map(|c| {
+            if c == '/' || c == '\\' || c.is_control() {
+                '_'
+            } else {
+                c
+            }
+        })
+        .collect();
+
+    match file_name.as_str() {
+        "" | "." | ".." => None,
+        _ => Some(file_name),
+    }
+}
+
+/// Resolve a file name relative to `home_dir/config`, rejecting paths that escape the config directory
+pub fn config_file_path(home_dir: &Path, file_name: &str) -> Result<PathBuf> {
+    let relative = Path::new(file_name);
+    let is_plain_relative = relative
+        .components()
+        .all(|component| matches!(component, Component::Normal(_) | Component::CurDir));
+    if file_name.is_empty() || !is_plain_relative {
+        anyhow::bail!("'{file_name}' must be a relative path inside the config directory");
+    }
+    Ok(home_dir.join("config").join(relative))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_filename_from_url_plain() {
+        assert_eq!(
+            filename_from_url("https://example.com/snapshots/snapshot.tar.gz").as_deref(),
+            Some("snapshot.tar.gz")
+        );
+    }
+
+    #[test]
+    fn test_filename_from_url_strips_query() {
+        assert_eq!(
+            filename_from_url("https://example.com/snapshot.tar.gz?token=abc&x=1").as_deref(),
+            Some("snapshot.tar.gz")
+        );
+    }
+
+    #[test]
+    fn test_filename_from_url_strips_fragment() {
+        assert_eq!(
+            filename_from_url("https://example.com/snapshot.tar.lz4#part").as_deref(),
+            Some("snapshot.tar.lz4")
+        );
+        assert_eq!(
+            filename_from_url("https://example.com/snapshot.tar.zst?a=b#c").as_deref(),
+            Some("snapshot.tar.zst")
+        );
+    }
+
+    #[test]
+    fn test_filename_from_url_percent_decodes() {
+        assert_eq!(
+            filename_from_url("https://example.com/my%20snapshot.tar.gz").as_deref(),
+            Some("my snapshot.tar.gz")
+        );
+    }
+
+    #[test]
+    fn test_filename_from_url_sanitizes_separators() {
+        assert_eq!(
+            filename_from_url("https://example.com/a%2Fb%5Csnap.tar.gz").as_deref(),
+            Some("a_b_snap.tar.gz")
+        );
+        assert_eq!(filename_from_url("https://example.com/%2E%2E"), None);
+        assert_eq!(filename_from_url("https://example.com/%20"), None);
+    }
+
+    #[test]
+    fn test_download_filename_falls_back_to_hash() {
+        assert_eq!(
+            download_filename("https://example.com/my%20snapshot.tar.gz"),
+            "my snapshot.tar.gz"
+        );
+
+        let fallback = download_filename("https://example.com/snapshots/");
+        assert!(fallback.starts_with("download-"), "{fallback}");
+        assert_eq!(fallback.len(), "download-".len() + 16);
+        // Stable for the same URL so an interrupted download can resume
+        assert_eq!(
+            fallback,
+            download_filename("https://example.com/snapshots/")
+        );
+        assert_ne!(fallback, download_filename("s3://bucket/snapshots/"));
+    }
+
+    #[test]
+    fn test_filename_from_url_empty_segment() {
+        assert_eq!(filename_from_url("https://example.com/snapshots/"), None);
+        assert_eq!(
+            filename_from_url("https://example.com/?file=a.tar.gz"),
+            None
+        );
+    }
+
+    #[test]
+    fn test_config_file_path_rejects_escaping_paths() {
+        let home = Path::new("/home/node/.gaia");
+        assert_eq!(
+            config_file_path(home, "client.toml").unwrap(),
+            home.join("config/client.toml")
+        );
+        assert!(config_file_path(home, "../data/priv_validator_state.json").is_err());
+        assert!(config_file_path(home, "/etc/passwd").is_err());
+        assert!(config_file_path(home, "").is_err());
+    }
+
+    #[test]
+    fn test_prune_downloads_removes_snapshot_parts() -> Result<()> {
+        let temp_dir = tempfile::tempdir()?;
+        let config_path = temp_dir.path().join("config.yaml");
+        fs::write(
+            &config_path,
+            format!(
+                r#"
+snapshot_urls:
+  - "https://example.com/snapshot.tar.lz4.part1"
+  - "https://example.com/snapshot.tar.lz4.part2"
+snapshot_filename: "snapshot.tar.lz4"
+binary_url: "https://example.com/gaiad.tar.gz"
+binary_relative_path: "bin/gaiad"
+chain_id: "cosmoshub-4"
+moniker: "test-node"
+base_dir: "{}"
+"#,
+                temp_dir.path().display()
+            ),
+        )?;
+        let config = Config::from_file(&config_path)?;
+        create_directories(&config)?;
+
+        for (name, size) in [
+            ("snapshot.tar.lz4", 100),
+            ("snapshot.tar.lz4.part1", 60),
+            ("snapshot.tar.lz4.part2", 40),
+            ("gaiad.tar.gz", 10),
+        ] {
+            fs::write(config.downloads_dir.join(name), vec![0u8; size])?;
+        }
+
+        assert_eq!(prune_downloads(&config, false)?, 200);
+        let remaining: Vec<_> = fs::read_dir(&config.downloads_dir)?
+            .map(|entry| entry.map(|e| e.file_name()))
+            .collect::<std::io::Result<_>>()?;
+        assert_eq!(remaining, vec![std::ffi::OsString::from("gaiad.tar.gz")]);
+
+        assert_eq!(prune_downloads(&config, true)?, 10);
+        Ok(())
+    }
+
+    #[test]
+    fn test_clear_snapshot_dirs_keeps_other_files() -> Result<()> {
+        let temp_dir = tempfile::tempdir()?;
+        let config_path = temp_dir.path().join("config.yaml");
+        fs::write(
+            &config_path,
+            format!(
+                r#"
+snapshot_url: "https://example.com/snapshot.tar.lz4"
+binary_url: "https://example.com/gaiad.tar.gz"
+binary_relative_path: "bin/gaiad"
+chain_id: "cosmoshub-4"
+moniker: "test-node"
+base_dir: "{}"
+"#,
+                temp_dir.path().display()
+            ),
+        )?;
+        let config = Config::from_file(&config_path)?;
+        let home = &config.home_dir;
+        fs::create_dir_all(home.join("data/application.db"))?;
+        fs::create_dir_all(home.join("config"))?;
+        fs::write(home.join("data/application.db/000001.ldb"), b"stale")?;
+        fs::write(home.join("data/priv_validator_state.json"), b"{}")?;
+        fs::write(home.join("config/node_key.json"), b"{}")?;
+
+        clear_snapshot_dirs(&config)?;
+
+        assert!(!home.join("data/application.db").exists());
+        assert!(home.join("data/priv_validator_state.json").exists());
+        assert!(home.join("config/node_key.json").exists());
+        // A missing directory (here wasm) is not an error
+        assert!(!home.join("wasm").exists());
+        Ok(())
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_fix_home_permissions() -> Result<()> {
+        use std::os::unix::fs::{symlink, MetadataExt, PermissionsExt};
+
+        let temp_dir = tempfile::tempdir()?;
+        // Owning the files by the current user and group works without root
+        let metadata = fs::metadata(temp_dir.path())?;
+        let config_path = temp_dir.path().join("config.yaml");
+        fs::write(
+            &config_path,
+            format!(
+                r#"
+snapshot_url: "https://example.com/snapshot.tar.lz4"
+binary_url: "https://example.com/gaiad.tar.gz"
+binary_relative_path: "bin/gaiad"
+chain_id: "cosmoshub-4"
+moniker: "test-node"
+base_dir: "{}"
+chown_user: "{}"
+chown_group: "{}"
+dir_mode: "0750"
+file_mode: "0640"
+"#,
+                temp_dir.path().display(),
+                metadata.uid(),
+                metadata.gid()
+            ),
+        )?;
+        let config = Config::from_file(&config_path)?;
+        create_directories(&config)?;
+
+        // A sample extracted tree
+        let home = &config.home_dir;
+        fs::create_dir_all(home.join("data/application.db"))?;
+        fs::create_dir_all(home.join("config"))?;
+        fs::write(home.join("data/application.db/000001.ldb"), b"data")?;
+        fs::write(home.join("config/config.toml"), b"")?;
+        fs::set_permissions(
+            home.join("config/config.toml"),
+            fs::Permissions::from_mode(0o666),
+        )?;
+        symlink("config/config.toml", home.join("config.toml"))?;
+
+        fix_home_permissions(&config)?;
+
+        let mode = |path: &str| -> Result<u32> {
+            Ok(fs::symlink_metadata(home.join(path))?.permissions().mode() & 0o7777)
+        };
+        assert_eq!(mode("")?, 0o750);
+        assert_eq!(mode("data")?, 0o750);
+        assert_eq!(mode("data/application.db")?, 0o750);
+        assert_eq!(mode("config")?, 0o750);
+        assert_eq!(mode("data/application.db/000001.ldb")?, 0o640);
+        assert_eq!(mode("config/config.toml")?, 0o640);
+        assert!(fs::symlink_metadata(home.join("config.toml"))?
+            .file_type()
+            .is_symlink());
+        assert_eq!(
+            fs::metadata(home.join("config/config.toml"))?.uid(),
+            metadata.uid()
+        );
+        Ok(())
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_lookup_ids() -> Result<()> {
+        assert_eq!(lookup_uid("1234")?, 1234);
+        assert_eq!(lookup_uid("root")?, 0);
+        assert_eq!(lookup_gid("1234")?, 1234);
+        let err = lookup_gid("no-such-group-here").unwrap_err();
+        assert!(err.to_string().contains("Unknown chown_group"), "{err:#}");
+        assert!(lookup_uid("no-such-user-here").is_err());
+        Ok(())
+    }
+}